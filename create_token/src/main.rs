@@ -1,59 +1,241 @@
-use clap::Parser;
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
 use ides::{
-    auth::{Role, Token},
+    auth::{rotate_token, Role, Token},
+    comment::CommentStatus,
     db,
+    export::{load_comments, load_reactions, ExportFilters, ExportFormat},
+    invitation::{create_invitation, invite_link, public_url},
     prelude::*,
 };
+use sqlx::PgPool;
+use std::path::PathBuf;
 
 #[derive(Parser)]
-#[command(name = "create-admin")]
+#[command(name = "create-token")]
 #[command(
-    about = "Add an admin user. Connects to a database based on $DATABASE_URL"
+    about = "Manage readers and comments. Connects to a database based on $DATABASE_URL"
+)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    arg_required_else_help = true
 )]
 struct Args {
+    /// Without a subcommand, `--name` and `--role` add a user, as they
+    /// always have.
+    #[command(flatten)]
+    create: Option<CreateArgs>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Args)]
+struct CreateArgs {
     #[arg(long)]
     name: String,
     #[arg(long)]
     role: String,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Add a user, and print their token.
+    Create(CreateArgs),
+    /// Give an existing user a new token, and print an invitation link for
+    /// them to sign back in with. Their old token stops working; everything
+    /// they've done is kept. Links are built on $PUBLIC_URL.
+    Rotate {
+        #[arg(long)]
+        id: i32,
+    },
+    /// Export comments and their replies, as Markdown or CSV.
+    ExportComments {
+        /// `md` or `csv`.
+        #[arg(long, default_value = "md")]
+        format: String,
+        /// Only comments written against this book revision.
+        #[arg(long)]
+        revision: Option<i32>,
+        /// Only comments made on or after this date (YYYY-MM-DD).
+        #[arg(long)]
+        since: Option<NaiveDate>,
+        /// Only comments made on or before this date (YYYY-MM-DD).
+        #[arg(long)]
+        until: Option<NaiveDate>,
+        /// `open`, `resolved`, or `wont_fix`.
+        #[arg(long)]
+        status: Option<String>,
+        /// Write to this file instead of stdout.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Export counts of reactions on each block, as Markdown or CSV.
+    ExportReactions {
+        /// `md` or `csv`.
+        #[arg(long, default_value = "md")]
+        format: String,
+        /// Only reactions on this book revision.
+        #[arg(long)]
+        revision: Option<i32>,
+        /// Only reactions made on or after this date (YYYY-MM-DD).
+        #[arg(long)]
+        since: Option<NaiveDate>,
+        /// Only reactions made on or before this date (YYYY-MM-DD).
+        #[arg(long)]
+        until: Option<NaiveDate>,
+        /// Write to this file instead of stdout.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+async fn create(db: &PgPool, name: String, role: String) -> Result<()> {
+    let token = Token::create()?;
+    let digest = token.sha256_hex();
+
+    let role_parsed: Role =
+        role.clone().try_into().map_err(|e: ErrStack| {
+            e.wrap(ErrT::ValidationError)
+                .ctx(format!("{role} is not a valid role"))
+        })?;
+    let role_id: i32 = role_parsed.into();
+    query!(
+        "insert into token
+    (
+        token_digest,
+        name,
+        role_id
+    ) values ($1, $2, $3)",
+        digest,
+        name,
+        role_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::new(ErrT::SqlxError).ctx(e.to_string()))?;
+
+    println!(
+        "user {} created. Token is '{}'",
+        name,
+        token.display_secret_value()
+    );
+
+    Ok(())
+}
+
+async fn rotate(db: &PgPool, id: i32) -> Result<()> {
+    let public_url = public_url()?;
+    // Both or neither, like the admin page; the new secret is never shown,
+    // and the reader gets another one when they accept the invitation.
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|e| ErrStack::sqlx(&e, "rotate: begin"))?;
+    rotate_token(&mut *tx, id).await?.ok_or_else(|| {
+        ErrStack::new(ErrT::ValidationError)
+            .ctx(format!("there is no unrevoked user with id {id}"))
+    })?;
+    let code = create_invitation(&mut *tx, id).await?;
+    tx.commit()
+        .await
+        .map_err(|e| ErrStack::sqlx(&e, "rotate: commit"))?;
+
+    println!(
+        "token for user {} rotated. Send them this invitation link: {}",
+        id,
+        invite_link(&public_url, &code)
+    );
+
+    Ok(())
+}
+
+async fn export_comments(
+    db: &PgPool,
+    format: String,
+    filters: ExportFilters,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let format = ExportFormat::try_from(format.as_str())?;
+    let comments = load_comments(db, &filters).await?;
+    write_output(format.render(&comments), output)
+}
+
+async fn export_reactions(
+    db: &PgPool,
+    format: String,
+    filters: ExportFilters,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let format = ExportFormat::try_from(format.as_str())?;
+    let reactions = load_reactions(db, &filters).await?;
+    write_output(format.render_reactions(&reactions), output)
+}
+
+fn write_output(rendered: String, output: Option<PathBuf>) -> Result<()> {
+    match output {
+        Some(path) => std::fs::write(&path, rendered).map_err(|e| {
+            ErrStack::new(ErrT::Invariant)
+                .ctx(format!("could not write {}: {e}", path.display()))
+        })?,
+        None => print!("{rendered}"),
+    };
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> std::result::Result<(), ()> {
     let result: Result<()> = async {
         let args = Args::parse();
+        let command = match (args.command, args.create) {
+            (Some(command), _) => command,
+            (None, Some(create)) => Command::Create(create),
+            (None, None) => {
+                return Err(ErrStack::new(ErrT::ValidationError)
+                    .ctx("no command given".into()))
+            }
+        };
         let db = db::create_pg_pool().await?;
 
-        let token = Token::create()?;
-        let digest = token.sha256_hex();
-
-        let role: Role =
-            args.role.clone().try_into().map_err(|e: ErrStack| {
-                e.wrap(ErrT::ValidationError)
-                    .ctx(format!("{} is not a valid role", args.role))
-            })?;
-        let role_id: i32 = role.into();
-        query!(
-            "insert into token
-        (
-            token_digest,
-            name,
-            role_id
-        ) values ($1, $2, $3)",
-            digest,
-            args.name,
-            role_id
-        )
-        .execute(&db)
-        .await
-        .map_err(|e| ErrStack::new(ErrT::SqlxError).ctx(e.to_string()))?;
-
-        println!(
-            "user {} created. Token is '{}'",
-            args.name,
-            token.display_secret_value()
-        );
-
-        Ok(())
+        match command {
+            Command::Create(CreateArgs { name, role }) => {
+                create(&db, name, role).await
+            }
+            Command::Rotate { id } => rotate(&db, id).await,
+            Command::ExportComments {
+                format,
+                revision,
+                since,
+                until,
+                status,
+                output,
+            } => {
+                let filters = ExportFilters {
+                    revision,
+                    since,
+                    until,
+                    status: status
+                        .as_deref()
+                        .map(CommentStatus::try_from)
+                        .transpose()?,
+                };
+                export_comments(&db, format, filters, output).await
+            }
+            Command::ExportReactions {
+                format,
+                revision,
+                since,
+                until,
+                output,
+            } => {
+                let filters = ExportFilters {
+                    revision,
+                    since,
+                    until,
+                    status: None,
+                };
+                export_reactions(&db, format, filters, output).await
+            }
+        }
     }
     .await;
     if let Err(ref e) = result {
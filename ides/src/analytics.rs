@@ -0,0 +1,148 @@
+//! Reading analytics, derived from the access log; each page render is one
+//! visit. Grouping visits into sessions, and how far into the book readers
+//! get.
+
+use crate::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+
+/// What the reader did to arrive at the page which was logged.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AccessEventType {
+    PageView,
+    Next,
+    Previous,
+    Jump,
+    Comment,
+    Scroll,
+}
+
+impl From<AccessEventType> for i32 {
+    fn from(val: AccessEventType) -> Self {
+        match val {
+            AccessEventType::PageView => 1,
+            AccessEventType::Next => 2,
+            AccessEventType::Previous => 3,
+            AccessEventType::Jump => 4,
+            AccessEventType::Comment => 5,
+            AccessEventType::Scroll => 6,
+        }
+    }
+}
+
+impl TryInto<AccessEventType> for i32 {
+    type Error = ErrStack;
+    fn try_into(self) -> Result<AccessEventType> {
+        match self {
+            1 => Ok(AccessEventType::PageView),
+            2 => Ok(AccessEventType::Next),
+            3 => Ok(AccessEventType::Previous),
+            4 => Ok(AccessEventType::Jump),
+            5 => Ok(AccessEventType::Comment),
+            6 => Ok(AccessEventType::Scroll),
+            _ => Err(ErrStack::new(ErrT::Invariant)
+                .ctx(format!("{self} is not a valid i32 for AccessEventType"))),
+        }
+    }
+}
+
+impl AccessEventType {
+    /// The events where the reader was shown a page; everything but the
+    /// position pings which the scrolling reader sends as it goes.
+    pub const PAGE_VIEWS: [AccessEventType; 5] = [
+        AccessEventType::PageView,
+        AccessEventType::Next,
+        AccessEventType::Previous,
+        AccessEventType::Jump,
+        AccessEventType::Comment,
+    ];
+}
+
+/// Visits further apart than this belong to separate reading sessions.
+pub const SESSION_GAP: Duration = Duration::minutes(30);
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Session {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub pages: usize,
+}
+
+impl Session {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+/// Group `visits`, which must be in ascending order, into sessions; a new
+/// session begins whenever more than `gap` passes between two visits.
+pub fn sessions(visits: &[DateTime<Utc>], gap: Duration) -> Vec<Session> {
+    let mut result: Vec<Session> = Vec::new();
+    for visit in visits {
+        match result.last_mut() {
+            Some(session) if *visit - session.end <= gap => {
+                session.end = *visit;
+                session.pages += 1;
+            }
+            _ => result.push(Session {
+                start: *visit,
+                end: *visit,
+                pages: 1,
+            }),
+        }
+    }
+    result
+}
+
+/// For each chapter, given the sequence where it starts, the number of
+/// readers whose furthest sequence reached it.
+pub fn funnel(chapter_starts: &[i32], furthest: &[i32]) -> Vec<usize> {
+    chapter_starts
+        .iter()
+        .map(|start| furthest.iter().filter(|f| *f >= start).count())
+        .collect()
+}
+
+/// Time on a page longer than this means the reader walked away; we don't
+/// count it as reading.
+pub const IDLE_GAP: Duration = Duration::minutes(10);
+
+/// If the next event lands at most this many blocks further along, we take it
+/// that the reader moved on to the following page, and that everything in
+/// between was on the page they were reading.
+pub const MAX_PAGE_BLOCKS: i32 = 20;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_sessions_split_on_gap() {
+        let visits = [at(9, 0), at(9, 10), at(9, 40), at(11, 0), at(11, 5)];
+        assert_eq!(
+            sessions(&visits, SESSION_GAP),
+            vec![
+                Session {
+                    start: at(9, 0),
+                    end: at(9, 40),
+                    pages: 3
+                },
+                Session {
+                    start: at(11, 0),
+                    end: at(11, 5),
+                    pages: 2
+                },
+            ]
+        );
+        assert!(sessions(&[], SESSION_GAP).is_empty());
+    }
+
+    #[test]
+    fn test_funnel() {
+        assert_eq!(funnel(&[0, 10, 20], &[5, 10, 25, 0]), vec![4, 2, 1]);
+    }
+}
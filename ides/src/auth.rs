@@ -1,8 +1,15 @@
-use crate::{bytes::Bytes, prelude::*};
+use crate::{
+    bytes::Bytes,
+    prelude::*,
+    session::{
+        SessionCookie, SessionKey, ROTATION_GRACE, SESSION_IDLE_LIFETIME,
+    },
+};
 use aws_lc_rs::{
     digest::{Context, SHA256},
     rand::fill,
 };
+use chrono::{DateTime, Utc};
 
 pub struct Auth {
     #[allow(unused)]
@@ -10,37 +17,142 @@ pub struct Auth {
     #[allow(unused)]
     pub role: Role,
     pub token_id: i32,
+    /// The session this request was made with; `None` if the browser sent
+    /// its token instead.
+    pub session_id: Option<i32>,
 }
 
 pub enum AuthResult {
     Authenticated(Auth),
-    NotAuthenticated,
+    NotAuthenticated(Rejection),
     Err(ErrStack),
 }
 
+/// Why a token was turned away.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rejection {
+    /// No token has this secret.
+    Unknown,
+    /// The admin revoked this token.
+    Revoked,
+    /// The token was given an expiry, which has passed.
+    Expired,
+    /// The session was revoked, or went unused for too long.
+    SignedOut,
+}
+
+/// A token, and the session it was found through, if any.
+struct Qres {
+    name: String,
+    role: String,
+    token_id: i32,
+    revoked_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    session_id: Option<i32>,
+    session_revoked_at: Option<DateTime<Utc>>,
+    last_seen_at: Option<DateTime<Utc>>,
+}
+
+impl Qres {
+    fn into_result(self) -> AuthResult {
+        if self.session_revoked_at.is_some()
+            || self
+                .last_seen_at
+                .is_some_and(|t| t + SESSION_IDLE_LIFETIME <= Utc::now())
+        {
+            return AuthResult::NotAuthenticated(Rejection::SignedOut);
+        }
+        if self.revoked_at.is_some() {
+            return AuthResult::NotAuthenticated(Rejection::Revoked);
+        }
+        if self.expires_at.is_some_and(|t| t <= Utc::now()) {
+            return AuthResult::NotAuthenticated(Rejection::Expired);
+        }
+        match self.role.try_into().map_err(|e: ErrStack| {
+            e.wrap(ErrT::SqlxError).ctx("during Auth::get".into())
+        }) {
+            Ok(role) => AuthResult::Authenticated(Auth {
+                name: self.name,
+                role,
+                token_id: self.token_id,
+                session_id: self.session_id,
+            }),
+            Err(e) => AuthResult::Err(e),
+        }
+    }
+}
+
 impl Auth {
+    /// Authenticate with the session cookie if there is one, or else with
+    /// the token cookie which browsers kept before we had sessions.
     pub async fn from_headers(
         db: impl PgExecutor<'_>,
+        key: &SessionKey,
         headers: &HeaderMap,
     ) -> AuthResult {
+        if let Some(cookie) = SessionCookie::parse_from_headers(headers, key) {
+            return Self::from_session(db, &cookie).await;
+        }
         match Token::parse_from_headers(headers) {
             Ok(token) => Self::get(db, &token).await,
             Err(e) => AuthResult::Err(e),
         }
     }
-    pub async fn get(db: impl PgExecutor<'_>, token: &Token) -> AuthResult {
-        #[derive(Debug)]
-        struct Qres {
-            name: String,
-            role: String,
-            token_id: i32,
+    pub async fn from_session(
+        db: impl PgExecutor<'_>,
+        cookie: &SessionCookie,
+    ) -> AuthResult {
+        let result = query_as!(
+            Qres,
+            r#"select
+                t.id token_id,
+                t.name,
+                r.name as role,
+                t.revoked_at,
+                t.expires_at,
+                s.id "session_id?",
+                s.revoked_at session_revoked_at,
+                s.last_seen_at "last_seen_at?"
+            from session s
+            join token t on t.id = s.token_id
+            join role r on r.id = t.role_id
+            where
+                s.id = $1
+                and (
+                    s.secret_digest = $2
+                    or (
+                        s.previous_secret_digest = $2
+                        and s.rotated_at > now() - make_interval(secs => $3)
+                    )
+                )"#,
+            cookie.session_id,
+            cookie.sha256_hex(),
+            ROTATION_GRACE.num_seconds() as f64
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(|e| ErrStack::sqlx(&e, "Auth::from_session"));
+        match result {
+            Ok(Some(qres)) => qres.into_result(),
+            Ok(None) => AuthResult::NotAuthenticated(Rejection::SignedOut),
+            Err(e) => AuthResult::Err(e),
         }
+    }
+    pub async fn get(db: impl PgExecutor<'_>, token: &Token) -> AuthResult {
         let result = query_as!(
             Qres,
-            "select t.id token_id, t.name, r.name as role
+            r#"select
+                t.id token_id,
+                t.name,
+                r.name as role,
+                t.revoked_at,
+                t.expires_at,
+                null::int "session_id?",
+                null::timestamptz session_revoked_at,
+                null::timestamptz last_seen_at
             from token t
             join role r on r.id = t.role_id
-            where t.token_digest = $1",
+            where t.token_digest = $1"#,
             token.sha256_hex()
         )
         .fetch_optional(db)
@@ -49,23 +161,8 @@ impl Auth {
             ErrStack::new(ErrT::SqlxError).ctx(format!("Auth::get: {e}"))
         });
         match result {
-            Ok(Some(Qres {
-                name,
-                role,
-                token_id,
-            })) => {
-                match role.try_into().map_err(|e: ErrStack| {
-                    e.wrap(ErrT::SqlxError).ctx("during Auth::get".into())
-                }) {
-                    Ok(role) => AuthResult::Authenticated(Auth {
-                        name,
-                        role,
-                        token_id,
-                    }),
-                    Err(e) => AuthResult::Err(e),
-                }
-            }
-            Ok(None) => AuthResult::NotAuthenticated,
+            Ok(Some(qres)) => qres.into_result(),
+            Ok(None) => AuthResult::NotAuthenticated(Rejection::Unknown),
             Err(e) => AuthResult::Err(e),
         }
     }
@@ -87,10 +184,7 @@ impl Token {
         Ok(Self::new(buffer.to_base64()))
     }
     pub fn sha256_hex(&self) -> String {
-        let mut ctx = Context::new(&SHA256);
-        ctx.update(self.0.as_bytes());
-        let digest = ctx.finish();
-        digest.as_ref().to_hex()
+        sha256_hex(&self.0)
     }
     pub fn parse_from_headers(headers: &HeaderMap) -> Result<Self> {
         let cookie = headers.get("Cookie");
@@ -123,6 +217,54 @@ impl Token {
     }
 }
 
+/// Secrets are stored as digests, so that a database leak doesn't let
+/// anybody in.
+pub(crate) fn sha256_hex(secret: &str) -> String {
+    let mut ctx = Context::new(&SHA256);
+    ctx.update(secret.as_bytes());
+    let digest = ctx.finish();
+    digest.as_ref().to_hex()
+}
+
+/// Give `token_id` a new secret, for when the old one has leaked. The old
+/// secret stops working, along with the token's sessions and any invitation
+/// for it which hasn't been used yet; everything the reader has done stays
+/// theirs. Returns `None` if there's no such token, or it's been revoked.
+pub async fn rotate_token(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+) -> Result<Option<Token>> {
+    let token = Token::create()?;
+    let rotated = query!(
+        "with rotated as (
+            update token
+            set token_digest = $2
+            where id = $1 and revoked_at is null
+            returning id
+        ), signed_out as (
+            update session
+            set revoked_at = now()
+            where
+                token_id in (select id from rotated)
+                and revoked_at is null
+        ), voided as (
+            update invitation
+            set expires_at = now()
+            where
+                token_id in (select id from rotated)
+                and used_at is null
+                and expires_at > now()
+        )
+        select id from rotated",
+        token_id,
+        token.sha256_hex()
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "rotate_token"))?;
+    Ok(rotated.map(|_| token))
+}
+
 impl std::fmt::Debug for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Token([sensitive value omitted])")
@@ -0,0 +1,69 @@
+//! Comments left by readers on blocks of the book.
+
+use crate::prelude::*;
+use serde::Deserialize;
+
+/// Where a comment is in the author's review.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommentStatus {
+    Open,
+    Resolved,
+    WontFix,
+}
+
+impl CommentStatus {
+    pub const ALL: [Self; 3] = [Self::Open, Self::Resolved, Self::WontFix];
+
+    /// Identifier used in forms and query strings.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Resolved => "resolved",
+            Self::WontFix => "wont_fix",
+        }
+    }
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Resolved => "resolved",
+            Self::WontFix => "won't fix",
+        }
+    }
+}
+
+impl From<CommentStatus> for i32 {
+    fn from(val: CommentStatus) -> Self {
+        match val {
+            CommentStatus::Open => 1,
+            CommentStatus::Resolved => 2,
+            CommentStatus::WontFix => 3,
+        }
+    }
+}
+
+impl TryInto<CommentStatus> for i32 {
+    type Error = ErrStack;
+    fn try_into(self) -> Result<CommentStatus> {
+        match self {
+            1 => Ok(CommentStatus::Open),
+            2 => Ok(CommentStatus::Resolved),
+            3 => Ok(CommentStatus::WontFix),
+            _ => Err(ErrStack::new(ErrT::Invariant)
+                .ctx(format!("{self} is not a valid i32 for CommentStatus"))),
+        }
+    }
+}
+
+impl TryFrom<&str> for CommentStatus {
+    type Error = ErrStack;
+    fn try_from(value: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|s| s.name() == value)
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::ValidationError)
+                    .ctx(format!("{value} is not a comment status"))
+            })
+    }
+}
@@ -1,6 +1,5 @@
 use crate::prelude::*;
-
-pub const PAGE_SIZE: i32 = 3;
+use std::ops::Range;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Block {
@@ -49,54 +48,314 @@ pub enum Direction {
     Forward,
 }
 
-pub async fn list_blocks(
-    db: impl PgExecutor<'_> + Copy,
-    current_sequence: i32,
-    book_revision_id: i32,
-) -> Result<Vec<SequencedBlock>> {
-    struct Qres {
-        id: i32,
-        content: String,
-        type_id: i32,
+/// Screen area and the amount of characters that look good should scale
+/// linearly. Here are two samples that looked good, and then we'll compute
+/// the character budget for any other screen area from these;
+///
+/// - 717808 1928
+/// - 200552 744
+pub fn char_budget(screen_area: i32) -> usize {
+    let slope = (1928.0 - 744.0) / (717808.0 - 200552.0);
+    let constant = 1928.0 - slope * 717808.0;
+    (slope * f64::from(screen_area) + constant) as usize
+}
+
+/// A section is a run of blocks which begins at a heading. Consecutive
+/// headings (i.e, a section title immediately followed by a chapter heading)
+/// belong to the same section. Pages never span two sections, so each
+/// section can be paginated on its own; page boundaries are then stable no
+/// matter where the reader enters the section.
+pub struct Section {
+    pub blocks: Vec<SequencedBlock>,
+}
+
+impl Section {
+    /// Fetch the section of `book_revision_id` which contains the block at
+    /// `sequence`.
+    pub async fn get(
+        db: impl PgExecutor<'_>,
+        book_revision_id: i32,
         sequence: i32,
+    ) -> Result<Self> {
+        struct Qres {
+            id: i32,
+            content: String,
+            type_id: i32,
+            sequence: i32,
+        }
+        let mut result = query_as!(
+            Qres,
+            "select id, sequence, content, type_id
+            from block
+            where
+                book_revision_id = $1
+                and section = (
+                    select section from block
+                    where book_revision_id = $1 and sequence = $2
+                )
+            order by sequence",
+            book_revision_id,
+            sequence
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| ErrStack::sqlx(&e, "Section::get"))?;
+
+        let mut x = result.drain(..);
+        let blocks = x.try_fold(Vec::new(), |mut acc, row| {
+            acc.push(SequencedBlock {
+                id: row.id,
+                sequence: row.sequence,
+                block: Block {
+                    r#type: row.type_id.try_into().map_err(|e: ErrStack| {
+                        e.wrap(ErrT::BookUi)
+                            .ctx("happened during Section::get".into())
+                    })?,
+                    content: row.content,
+                },
+            });
+            Ok(acc)
+        })?;
+        Ok(Self { blocks })
     }
-    let mut result = query_as!(
-        Qres,
-        "select id, sequence, content, type_id
-        from block
-        where
-            sequence >= $1
-            and sequence <= $2
-            and book_revision_id = $3
-        order by sequence
-        limit $4",
-        current_sequence,
-        current_sequence + PAGE_SIZE,
-        book_revision_id,
-        PAGE_SIZE as i64
-    )
-    .fetch_all(db)
-    .await
-    .map_err(|e| {
-        ErrStack::new(ErrT::SqlxError)
-            .ctx(format!("could not list_blocks: {e}"))
-    })?;
-
-    let mut x = result.drain(..);
-    x.try_fold(Vec::new(), |mut acc, row| {
-        acc.push(SequencedBlock {
-            id: row.id,
-            sequence: row.sequence,
-            block: Block {
-                r#type: row.type_id.try_into().map_err(|e: ErrStack| {
-                    e.wrap(ErrT::BookUi)
-                        .ctx("happened during list_blocks".into())
-                })?,
-                content: row.content,
-            },
-        });
-        Ok(acc)
-    })
+    pub fn first_sequence(&self) -> Option<i32> {
+        self.blocks.first().map(|b| b.sequence)
+    }
+    pub fn paginate(&self, char_budget: usize) -> Vec<Page<'_>> {
+        paginate(&self.blocks, char_budget)
+    }
+}
+
+/// A place in the book; a block, and a character offset into its content.
+/// Positions order naturally through the book.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Position {
+    pub sequence: i32,
+    pub offset: i32,
+}
+
+/// Part of a block, between two character offsets. A paragraph which does
+/// not fit on one page will be rendered as slices on consecutive pages.
+pub struct BlockSlice<'a> {
+    pub block: &'a SequencedBlock,
+    /// Character offset where this slice begins.
+    pub start: usize,
+    /// Character offset where this slice ends (exclusive).
+    pub end: usize,
+}
+
+impl<'a> BlockSlice<'a> {
+    pub fn whole(block: &'a SequencedBlock) -> Self {
+        Self {
+            block,
+            start: 0,
+            end: block.block.content.chars().count(),
+        }
+    }
+    pub fn content(&self) -> &str {
+        char_slice(&self.block.block.content, self.start, self.end)
+    }
+    pub fn is_whole_block(&self) -> bool {
+        self.start == 0 && self.end == self.block.block.content.chars().count()
+    }
+}
+
+pub struct Page<'a> {
+    pub slices: Vec<BlockSlice<'a>>,
+}
+
+impl Page<'_> {
+    pub fn start(&self) -> Option<Position> {
+        self.slices.first().map(|s| Position {
+            sequence: s.block.sequence,
+            offset: s.start as i32,
+        })
+    }
+    pub fn last_sequence(&self) -> Option<i32> {
+        self.slices.last().map(|s| s.block.sequence)
+    }
+}
+
+/// Slice `content` between two character (not byte) offsets.
+pub fn char_slice(content: &str, start: usize, end: usize) -> &str {
+    let byte_index = |n: usize| {
+        content
+            .char_indices()
+            .nth(n)
+            .map(|(i, _)| i)
+            .unwrap_or(content.len())
+    };
+    &content[byte_index(start)..byte_index(end.max(start))]
+}
+
+/// If a page has less than this fraction of its budget left, we'd rather
+/// start the paragraph on a fresh page than leave a sliver of it at the
+/// bottom.
+const MIN_SPLIT_FRACTION: usize = 8;
+
+/// Find where to break `chars[start..]` such that no more than `max_chars`
+/// land before the break. We prefer to break at the end of a sentence, and
+/// fall back to a word boundary if the last sentence ending would leave more
+/// than half of the space unused.
+///
+/// Returns the end of the first slice, and the start of the next one
+/// (whitespace at the break is dropped).
+fn split_point(
+    chars: &[char],
+    start: usize,
+    max_chars: usize,
+) -> Option<(usize, usize)> {
+    let limit = (start + max_chars).min(chars.len());
+    let mut sentence_end = None;
+    let mut word_end = None;
+    for i in (start + 1)..limit {
+        if !chars[i].is_whitespace() || chars[i - 1].is_whitespace() {
+            continue;
+        }
+        word_end = Some(i);
+        let before_quotes = chars[start..i]
+            .iter()
+            .rev()
+            .find(|c| !matches!(c, '"' | '\'' | '”' | '’' | ')'));
+        if matches!(before_quotes, Some('.' | '!' | '?')) {
+            sentence_end = Some(i);
+        }
+    }
+    let end = match sentence_end {
+        Some(i) if i - start >= max_chars / 2 => i,
+        _ => word_end?,
+    };
+    let next_start = end
+        + chars[end..]
+            .iter()
+            .take_while(|c| c.is_whitespace())
+            .count();
+    Some((end, next_start))
+}
+
+/// Greedily fill pages with blocks until the character budget is spent.
+/// Paragraphs which don't fit are split across pages at sentence or word
+/// boundaries. Headings are never split; a heading (or an unbroken run of
+/// text) which is larger than the whole budget will sit alone on its page.
+pub fn paginate(
+    blocks: &[SequencedBlock],
+    char_budget: usize,
+) -> Vec<Page<'_>> {
+    let char_budget = char_budget.max(1);
+    let mut pages = Vec::new();
+    let mut slices = Vec::new();
+    let mut remaining = char_budget;
+    for block in blocks {
+        let chars: Vec<char> = block.block.content.chars().collect();
+        let mut start = 0;
+        loop {
+            let rest = chars.len() - start;
+            if rest <= remaining {
+                slices.push(BlockSlice {
+                    block,
+                    start,
+                    end: chars.len(),
+                });
+                remaining -= rest;
+                break;
+            }
+            let worth_splitting = slices.is_empty()
+                || remaining >= char_budget / MIN_SPLIT_FRACTION;
+            let split = match block.block.r#type {
+                BlockType::Paragraph if worth_splitting => {
+                    split_point(&chars, start, remaining)
+                }
+                _ => None,
+            };
+            match split {
+                Some((end, next_start)) => {
+                    slices.push(BlockSlice { block, start, end });
+                    start = next_start;
+                }
+                None if slices.is_empty() => {
+                    // Nothing fits, and the page is empty. Take the rest
+                    // of the block, or at least what we can fit of one
+                    // giant word.
+                    let end = if block.block.r#type == BlockType::Paragraph {
+                        start + remaining
+                    } else {
+                        chars.len()
+                    };
+                    slices.push(BlockSlice { block, start, end });
+                    start = end;
+                }
+                None => {}
+            };
+            pages.push(Page {
+                slices: std::mem::take(&mut slices),
+            });
+            remaining = char_budget;
+            if start >= chars.len() {
+                break;
+            }
+        }
+    }
+    if !slices.is_empty() {
+        pages.push(Page { slices });
+    }
+    pages
+}
+
+/// Find the index of the page which contains `position`.
+pub fn page_containing(pages: &[Page], position: Position) -> Option<usize> {
+    pages
+        .iter()
+        .rposition(|page| page.start().is_some_and(|start| start <= position))
+}
+
+/// How far through a revision of the book a reader is, by block.
+pub struct Progress {
+    pub sequence: i32,
+    pub block_count: i64,
+}
+
+impl Progress {
+    pub async fn get(
+        db: impl PgExecutor<'_>,
+        book_revision_id: i32,
+        sequence: i32,
+    ) -> Result<Self> {
+        struct Qres {
+            count: i64,
+        }
+        let Qres { count } = query_as!(
+            Qres,
+            r#"select count(*) "count!" from block
+            where book_revision_id = $1"#,
+            book_revision_id
+        )
+        .fetch_one(db)
+        .await
+        .map_err(|e| ErrStack::sqlx(&e, "Progress::get"))?;
+        Ok(Self {
+            sequence,
+            block_count: count,
+        })
+    }
+    /// Percent complete, from 0 to 100. Reaching the last block counts as
+    /// finishing the book.
+    pub fn percent(&self) -> f64 {
+        if self.block_count <= 1 {
+            return 100.0;
+        }
+        let last = (self.block_count - 1) as f64;
+        (f64::from(self.sequence) / last * 100.0).clamp(0.0, 100.0)
+    }
+}
+
+/// The inverse of [Progress::percent]; find the sequence of the block nearest
+/// to `percent` of the way through a revision with `block_count` blocks.
+pub fn sequence_at_percent(block_count: i64, percent: f64) -> i32 {
+    if block_count <= 1 {
+        return 0;
+    }
+    let last = (block_count - 1) as f64;
+    (percent.clamp(0.0, 100.0) / 100.0 * last).round() as i32
 }
 
 #[derive(Debug)]
@@ -110,18 +369,64 @@ pub struct PersistedBook {
     pub book: Book,
 }
 
+/// A block as read from the text the author imported, along with the byte
+/// range of that text which each character of its content came from.
+///
+/// The space which joins the lines of a hard-wrapped paragraph maps to the
+/// line break it replaced.
+pub struct SourceBlock {
+    pub block: Block,
+    pub spans: Vec<Range<usize>>,
+}
+
+/// A run of characters from one line of input, with their byte ranges.
+type SourceChars = [(char, Range<usize>)];
+
+fn trim_source(chars: &SourceChars) -> &SourceChars {
+    let start = chars
+        .iter()
+        .position(|(c, _)| !c.is_whitespace())
+        .unwrap_or(chars.len());
+    let end = chars
+        .iter()
+        .rposition(|(c, _)| !c.is_whitespace())
+        .map_or(start, |i| i + 1);
+    &chars[start..end]
+}
+
 impl Book {
     pub fn from_raw_plain_text(input: &str) -> Self {
+        let (title, blocks) = Self::parse_source(input);
+        Book {
+            title,
+            blocks: blocks.into_iter().map(|b| b.block).collect(),
+        }
+    }
+    /// Parse `input` like [Book::from_raw_plain_text], keeping track of where
+    /// each block came from so that changes to it can be made to `input`.
+    pub fn parse_source(input: &str) -> (String, Vec<SourceBlock>) {
         let mut title = String::new();
-        let mut current_content: Vec<String> = Vec::new();
+        let mut current_content = String::new();
+        let mut current_spans: Vec<Range<usize>> = Vec::new();
         let mut blocks = Vec::new();
 
-        for line in input.lines() {
-            let trimmed = line
-                .trim()
+        let mut offset = 0;
+        for raw_line in input.split_inclusive('\n') {
+            let line_start = offset;
+            offset += raw_line.len();
+            let line = raw_line.strip_suffix('\n').unwrap_or(raw_line);
+            let chars: Vec<(char, Range<usize>)> = line
+                .char_indices()
                 // Remove the form feed character, which is how word will
                 // export page breaks.
-                .replace("\u{000C}", "");
+                .filter(|(_, c)| *c != '\u{000C}')
+                .map(|(i, c)| {
+                    (c, line_start + i..line_start + i + c.len_utf8())
+                })
+                .collect();
+            let trimmed_chars = trim_source(&chars);
+            let trimmed: String =
+                trimmed_chars.iter().map(|(c, _)| *c).collect();
 
             // Potentially detect the end of a paragraph at an empty line.
             // Continue no matter what.
@@ -129,29 +434,40 @@ impl Book {
                 if !current_content.is_empty() {
                     // End the current content and push it into the block list
                     // as a paragraph.
-                    blocks.push(Block {
-                        r#type: BlockType::Paragraph,
-                        content: current_content.join(" "),
+                    blocks.push(SourceBlock {
+                        block: Block {
+                            r#type: BlockType::Paragraph,
+                            content: std::mem::take(&mut current_content),
+                        },
+                        spans: std::mem::take(&mut current_spans),
                     });
-                    current_content.clear();
                 }
                 continue;
             }
 
             // Handle headings
             if trimmed.starts_with('#') {
-                let content = trimmed.trim_start_matches('#').trim();
-                let block_type =
-                    match trimmed.chars().take_while(|c| *c == '#').count() {
-                        1 => Some(BlockType::SectionTitle),
-                        2 => Some(BlockType::H1),
-                        _ => None,
-                    };
+                let level = trimmed.chars().take_while(|c| *c == '#').count();
+                let content = trim_source(&trimmed_chars[level..]);
+                let block_type = match level {
+                    1 => Some(BlockType::SectionTitle),
+                    2 => Some(BlockType::H1),
+                    _ => None,
+                };
                 match (content.is_empty(), block_type) {
                     (false /* is not empty */, Some(block_type)) => {
-                        blocks.push(Block {
-                            r#type: block_type,
-                            content: content.to_string(),
+                        blocks.push(SourceBlock {
+                            block: Block {
+                                r#type: block_type,
+                                content: content
+                                    .iter()
+                                    .map(|(c, _)| *c)
+                                    .collect(),
+                            },
+                            spans: content
+                                .iter()
+                                .map(|(_, span)| span.clone())
+                                .collect(),
                         });
                     }
                     _ => { /* noop; ignore junk */ }
@@ -172,20 +488,41 @@ impl Book {
                 continue;
             }
 
-            current_content.push(trimmed);
+            if let Some(previous) = current_spans.last() {
+                current_content.push(' ');
+                current_spans.push(previous.end..trimmed_chars[0].1.start);
+            }
+            current_content.push_str(&trimmed);
+            current_spans
+                .extend(trimmed_chars.iter().map(|(_, span)| span.clone()));
         }
 
         if !current_content.is_empty() {
             // End the current content and push it into the block list
             // as a paragraph.
-            blocks.push(Block {
-                r#type: BlockType::Paragraph,
-                content: current_content.join(" "),
+            blocks.push(SourceBlock {
+                block: Block {
+                    r#type: BlockType::Paragraph,
+                    content: current_content,
+                },
+                spans: current_spans,
             });
-            current_content.clear();
         }
 
-        Book { title, blocks }
+        (title, blocks)
+    }
+    /// Write the book back out in the format read by
+    /// [Book::from_raw_plain_text], with each paragraph on one line.
+    pub fn to_plain_text(&self) -> String {
+        let mut parts = vec![format!("% {}", self.title)];
+        for block in &self.blocks {
+            parts.push(match block.r#type {
+                BlockType::SectionTitle => format!("# {}", block.content),
+                BlockType::H1 => format!("## {}", block.content),
+                BlockType::Paragraph => block.content.clone(),
+            });
+        }
+        parts.join("\n\n") + "\n"
     }
     pub async fn persist(
         self,
@@ -214,7 +551,10 @@ impl Book {
                 .ctx(format!("failed to create a new book: {e}"))
         })?;
 
-        for (seq, block) in self.blocks.iter().enumerate() {
+        let sections = section_numbers(&self.blocks);
+        for (seq, (block, section)) in
+            self.blocks.iter().zip(sections).enumerate()
+        {
             let block_type_id: i32 = block.r#type.into();
             let seq_i32 = seq as i32;
             query!(
@@ -223,12 +563,14 @@ impl Book {
                     sequence,
                     content,
                     book_revision_id,
-                    type_id
-                ) values ($1, $2, $3, $4)",
+                    type_id,
+                    section
+                ) values ($1, $2, $3, $4, $5)",
                 seq_i32,
                 block.content,
                 revision_id,
-                block_type_id
+                block_type_id,
+                section
             )
             .execute(db)
             .await
@@ -245,10 +587,224 @@ impl Book {
     }
 }
 
+/// The section of each of `blocks`, counting from zero; a section starts at
+/// a heading which follows a paragraph.
+fn section_numbers(blocks: &[Block]) -> Vec<i32> {
+    let mut section = 0;
+    let mut previous = BlockType::Paragraph;
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| {
+            if i > 0
+                && block.r#type != BlockType::Paragraph
+                && previous == BlockType::Paragraph
+            {
+                section += 1;
+            }
+            previous = block.r#type;
+            section
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn paragraphs(lengths: &[usize]) -> Vec<SequencedBlock> {
+        lengths
+            .iter()
+            .enumerate()
+            .map(|(i, len)| SequencedBlock {
+                id: i as i32 + 100,
+                sequence: i as i32,
+                block: Block {
+                    r#type: BlockType::Paragraph,
+                    content: "x".repeat(*len),
+                },
+            })
+            .collect()
+    }
+
+    fn sequences(pages: &[Page]) -> Vec<Vec<i32>> {
+        pages
+            .iter()
+            .map(|p| p.slices.iter().map(|s| s.block.sequence).collect())
+            .collect()
+    }
+
+    fn position(sequence: i32, offset: i32) -> Position {
+        Position { sequence, offset }
+    }
+
+    #[test]
+    fn test_paginate_fills_budget() {
+        let blocks = paragraphs(&[40, 40, 40, 40, 40]);
+        let pages = paginate(&blocks, 100);
+        assert_eq!(sequences(&pages), vec![vec![0, 1], vec![2, 3], vec![4]]);
+    }
+
+    #[test]
+    fn test_paginate_never_skips_blocks() {
+        let blocks = paragraphs(&[10, 300, 20, 20, 500, 5]);
+        let pages = paginate(&blocks, 100);
+        let mut flat: Vec<i32> =
+            sequences(&pages).into_iter().flatten().collect();
+        flat.dedup();
+        assert_eq!(flat, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_paginate_empty() {
+        assert!(paginate(&[], 100).is_empty());
+    }
+
+    #[test]
+    fn test_paginate_splits_at_sentences() {
+        let blocks = vec![SequencedBlock {
+            id: 1,
+            sequence: 0,
+            block: Block {
+                r#type: BlockType::Paragraph,
+                content: "One two three. Four five six. Seven eight nine."
+                    .into(),
+            },
+        }];
+        let pages = paginate(&blocks, 32);
+        let content: Vec<Vec<&str>> = pages
+            .iter()
+            .map(|p| p.slices.iter().map(|s| s.content()).collect())
+            .collect();
+        assert_eq!(
+            content,
+            vec![
+                vec!["One two three. Four five six."],
+                vec!["Seven eight nine."]
+            ]
+        );
+        assert_eq!(pages[1].start(), Some(position(0, 30)));
+    }
+
+    #[test]
+    fn test_paginate_falls_back_to_words() {
+        let blocks = vec![SequencedBlock {
+            id: 1,
+            sequence: 0,
+            block: Block {
+                r#type: BlockType::Paragraph,
+                content: "Short. then a very long sentence which goes on"
+                    .into(),
+            },
+        }];
+        let pages = paginate(&blocks, 30);
+        assert_eq!(pages[0].slices[0].content(), "Short. then a very long");
+        assert_eq!(pages[1].slices[0].content(), "sentence which goes on");
+    }
+
+    #[test]
+    fn test_paginate_split_fills_remaining_space() {
+        let mut blocks = paragraphs(&[20]);
+        blocks.push(SequencedBlock {
+            id: 2,
+            sequence: 1,
+            block: Block {
+                r#type: BlockType::Paragraph,
+                content: "aaaa bbbb cccc dddd eeee ffff".into(),
+            },
+        });
+        let pages = paginate(&blocks, 32);
+        assert_eq!(sequences(&pages), vec![vec![0, 1], vec![1]]);
+        assert_eq!(pages[0].slices[1].content(), "aaaa bbbb");
+        assert_eq!(pages[1].slices[0].content(), "cccc dddd eeee ffff");
+        assert_eq!(pages[1].start(), Some(position(1, 10)));
+    }
+
+    #[test]
+    fn test_paginate_headings_are_not_split() {
+        let blocks = vec![SequencedBlock {
+            id: 1,
+            sequence: 0,
+            block: Block {
+                r#type: BlockType::H1,
+                content: "A very long heading indeed".into(),
+            },
+        }];
+        let pages = paginate(&blocks, 10);
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].slices[0].is_whole_block());
+    }
+
+    #[test]
+    fn test_paginate_unbroken_text() {
+        let blocks = paragraphs(&[250]);
+        let pages = paginate(&blocks, 100);
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[2].start(), Some(position(0, 200)));
+    }
+
+    #[test]
+    fn test_page_containing() {
+        let blocks = paragraphs(&[40, 40, 40, 40, 40]);
+        let pages = paginate(&blocks, 100);
+        assert_eq!(page_containing(&pages, position(0, 0)), Some(0));
+        assert_eq!(page_containing(&pages, position(3, 0)), Some(1));
+        assert_eq!(page_containing(&pages, position(3, 12)), Some(1));
+        assert_eq!(page_containing(&pages, position(4, 0)), Some(2));
+    }
+
+    #[test]
+    fn test_progress_percent() {
+        let progress = |sequence, block_count| Progress {
+            sequence,
+            block_count,
+        };
+        assert_eq!(progress(0, 11).percent(), 0.0);
+        assert_eq!(progress(5, 11).percent(), 50.0);
+        assert_eq!(progress(10, 11).percent(), 100.0);
+        assert_eq!(progress(0, 1).percent(), 100.0);
+    }
+
+    #[test]
+    fn test_sequence_at_percent() {
+        assert_eq!(sequence_at_percent(11, 0.0), 0);
+        assert_eq!(sequence_at_percent(11, 50.0), 5);
+        assert_eq!(sequence_at_percent(11, 100.0), 10);
+        assert_eq!(sequence_at_percent(11, 140.0), 10);
+        assert_eq!(sequence_at_percent(11, -3.0), 0);
+        assert_eq!(sequence_at_percent(4, 50.0), 2);
+        assert_eq!(sequence_at_percent(0, 50.0), 0);
+    }
+
+    #[test]
+    fn test_section_numbers() {
+        let block = |r#type| Block {
+            r#type,
+            content: String::new(),
+        };
+        let blocks = [
+            block(BlockType::SectionTitle),
+            block(BlockType::H1),
+            block(BlockType::Paragraph),
+            block(BlockType::Paragraph),
+            block(BlockType::H1),
+            block(BlockType::Paragraph),
+            block(BlockType::SectionTitle),
+            block(BlockType::H1),
+        ];
+        assert_eq!(section_numbers(&blocks), vec![0, 0, 0, 0, 1, 1, 2, 2]);
+        let blocks = [block(BlockType::Paragraph), block(BlockType::H1)];
+        assert_eq!(section_numbers(&blocks), vec![0, 1]);
+        assert!(section_numbers(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_char_slice() {
+        assert_eq!(char_slice("héllo wörld", 1, 4), "éll");
+        assert_eq!(char_slice("héllo wörld", 6, 100), "wörld");
+        assert_eq!(char_slice("héllo", 4, 2), "");
+    }
+
     #[test]
     fn test_parse_book() {
         let book = Book::from_raw_plain_text(
@@ -265,6 +821,17 @@ mod test {
         assert_eq!(book.blocks[2].content, "Book.");
     }
 
+    #[test]
+    fn test_plain_text_round_trip() {
+        let text = "% Title\n\n# Cool book!\n\n## Great\n\nBook.\n";
+        let book = Book::from_raw_plain_text(text);
+        assert_eq!(book.to_plain_text(), text);
+        assert_eq!(
+            Book::from_raw_plain_text(&book.to_plain_text()).blocks,
+            book.blocks
+        );
+    }
+
     #[test]
     fn test_parse_hard_wrapping() {
         let book = Book::from_raw_plain_text(
@@ -356,6 +923,30 @@ more good content
         assert!(matches!(book.blocks[0].r#type, BlockType::Paragraph));
     }
 
+    #[test]
+    fn test_parse_source_spans() {
+        let source = "% Title\n\n#  Héading\n\n  hard\r\n\u{000C}wrapped \n";
+        let (title, blocks) = Book::parse_source(source);
+        assert_eq!(title, "Title");
+        assert_eq!(
+            blocks.iter().map(|b| &b.block).collect::<Vec<_>>(),
+            Book::from_raw_plain_text(source)
+                .blocks
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        for SourceBlock { block, spans } in &blocks {
+            assert_eq!(block.content.chars().count(), spans.len());
+        }
+        let text =
+            |block: &SourceBlock, i: usize| &source[block.spans[i].clone()];
+        assert_eq!(text(&blocks[0], 1), "é");
+        assert_eq!(blocks[1].block.content, "hard wrapped");
+        // The joining space stands for the line break and the form feed.
+        assert_eq!(text(&blocks[1], 4), "\r\n\u{000C}");
+        assert_eq!(text(&blocks[1], 5), "w");
+    }
+
     #[test]
     fn test_ignore_too_much_header() {
         let book = Book::from_raw_plain_text("##### woah");
@@ -0,0 +1,366 @@
+//! Diffs for suggested edits; a word-level diff for showing the admin what a
+//! reader wants to change, and line-level unified diffs for collecting
+//! accepted suggestions into a patch.
+
+use std::{fmt::Write, ops::Range};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum DiffOp {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// Split text into words and the whitespace between them, so that joining
+/// the tokens gives back the original text.
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut in_space: Option<bool> = None;
+    for (i, c) in text.char_indices() {
+        let space = c.is_whitespace();
+        if in_space.is_some_and(|s| s != space) {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+        in_space = Some(space);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+/// Diff two texts word by word, using the longest common subsequence of
+/// their words. Adjacent operations of the same kind are merged.
+pub fn word_diff(old: &str, new: &str) -> Vec<DiffOp> {
+    let old = tokenize(old);
+    let new = tokenize(new);
+
+    // lcs[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<DiffOp> = Vec::new();
+    let mut push = |op: DiffOp| match (ops.last_mut(), op) {
+        (Some(DiffOp::Same(last)), DiffOp::Same(text))
+        | (Some(DiffOp::Removed(last)), DiffOp::Removed(text))
+        | (Some(DiffOp::Added(last)), DiffOp::Added(text)) => {
+            last.push_str(&text)
+        }
+        (_, op) => ops.push(op),
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            push(DiffOp::Same(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if j == new.len()
+            || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1])
+        {
+            push(DiffOp::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            push(DiffOp::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// A replacement of a byte range of some text.
+pub type TextEdit<'a> = (Range<usize>, &'a str);
+
+/// Lines `old` of a file, replaced with `new`.
+#[derive(Debug)]
+pub struct LineChange {
+    pub old: Range<usize>,
+    pub new: Vec<String>,
+}
+
+/// A unified diff between two versions of a file which have the same number
+/// of lines, where lines may only have been changed in place. Returns an
+/// empty string if nothing changed.
+pub fn unified_diff(
+    path: &str,
+    old: &[String],
+    new: &[String],
+    context: usize,
+) -> String {
+    let changes: Vec<LineChange> = (0..old.len().min(new.len()))
+        .filter(|i| old[*i] != new[*i])
+        .map(|i| LineChange {
+            old: i..i + 1,
+            new: vec![new[i].clone()],
+        })
+        .collect();
+    write_hunks(path, old, &changes, context)
+}
+
+/// A unified diff of `text`, with `edits` made to it. Each edit replaces a
+/// byte range of `text`, and edits must not overlap. Returns an empty string
+/// if there are no edits.
+pub fn patch_text(
+    path: &str,
+    text: &str,
+    edits: &[TextEdit],
+    context: usize,
+) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let starts: Vec<usize> = lines
+        .iter()
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some(start)
+        })
+        .collect();
+    if lines.is_empty() {
+        return String::new();
+    }
+    let line_of = |byte: usize| starts.partition_point(|s| *s <= byte) - 1;
+
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|(range, _)| range.start);
+
+    // Group edits into runs of whole lines. An edit takes in the line where
+    // it ends even if it ends right at the start of it, so that removing a
+    // line break joins the lines on either side.
+    let mut groups: Vec<(Range<usize>, Vec<TextEdit>)> = Vec::new();
+    for (range, replacement) in edits {
+        let first = line_of(range.start);
+        let last = line_of(range.end);
+        match groups.last_mut() {
+            Some((group_lines, group_edits)) if first < group_lines.end => {
+                group_lines.end = group_lines.end.max(last + 1);
+                group_edits.push((range, replacement));
+            }
+            _ => groups.push((first..last + 1, vec![(range, replacement)])),
+        }
+    }
+
+    let without_newline =
+        |line: &str| line.strip_suffix('\n').unwrap_or(line).to_string();
+    let old: Vec<String> = lines.iter().copied().map(without_newline).collect();
+    let changes: Vec<LineChange> = groups
+        .into_iter()
+        .map(|(group_lines, group_edits)| {
+            let end =
+                starts[group_lines.end - 1] + lines[group_lines.end - 1].len();
+            let mut replaced = String::new();
+            let mut cursor = starts[group_lines.start];
+            for (range, replacement) in group_edits {
+                replaced.push_str(&text[cursor..range.start]);
+                replaced.push_str(replacement);
+                cursor = range.end;
+            }
+            replaced.push_str(&text[cursor..end]);
+            let mut new: Vec<String> = replaced
+                .split_inclusive('\n')
+                .map(without_newline)
+                .collect();
+
+            // Leave out lines which an edit took in but did not change.
+            let mut group_lines = group_lines;
+            let kept = &old[group_lines.clone()];
+            let same_start = kept
+                .iter()
+                .zip(&new)
+                .take_while(|(old, new)| old == new)
+                .count();
+            let same_end = kept[same_start..]
+                .iter()
+                .rev()
+                .zip(new[same_start..].iter().rev())
+                .take_while(|(old, new)| old == new)
+                .count();
+            new.drain(new.len() - same_end..);
+            new.drain(..same_start);
+            group_lines.start += same_start;
+            group_lines.end -= same_end;
+            LineChange {
+                old: group_lines,
+                new,
+            }
+        })
+        .filter(|change| !change.old.is_empty() || !change.new.is_empty())
+        .collect();
+    write_hunks(path, &old, &changes, context)
+}
+
+/// Write `changes`, which must be in order and must not overlap, as a
+/// unified diff of `old`.
+fn write_hunks(
+    path: &str,
+    old: &[String],
+    changes: &[LineChange],
+    context: usize,
+) -> String {
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Group changes whose context would overlap into the same hunk.
+    let mut hunks: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    for (i, change) in changes.iter().enumerate() {
+        let start = change.old.start.saturating_sub(context);
+        let end = (change.old.end + context).min(old.len());
+        match hunks.last_mut() {
+            Some((lines, included)) if start <= lines.end => {
+                lines.end = end;
+                included.end = i + 1;
+            }
+            _ => hunks.push((start..end, i..i + 1)),
+        }
+    }
+
+    // Empty ranges are numbered by the line before them.
+    let range = |start: usize, len: usize| {
+        format!("{},{len}", if len == 0 { start } else { start + 1 })
+    };
+    let mut out = format!("--- a/{path}\n+++ b/{path}\n");
+    // How many more lines the new file has than the old one, ahead of the
+    // hunk being written.
+    let mut offset = 0isize;
+    for (lines, included) in hunks {
+        let changes = &changes[included];
+        let added: isize = changes
+            .iter()
+            .map(|c| c.new.len() as isize - c.old.len() as isize)
+            .sum();
+        let old_len = lines.len();
+        let new_len = (old_len as isize + added) as usize;
+        let new_start = (lines.start as isize + offset) as usize;
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(lines.start, old_len),
+            range(new_start, new_len)
+        );
+        let mut cursor = lines.start;
+        for change in changes {
+            for line in &old[cursor..change.old.start] {
+                let _ = writeln!(out, " {line}");
+            }
+            for line in &old[change.old.clone()] {
+                let _ = writeln!(out, "-{line}");
+            }
+            for line in &change.new {
+                let _ = writeln!(out, "+{line}");
+            }
+            cursor = change.old.end;
+        }
+        for line in &old[cursor..lines.end] {
+            let _ = writeln!(out, " {line}");
+        }
+        offset += added;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_round_trips() {
+        let text = "  The cat,  sat\ton the mat. ";
+        assert_eq!(tokenize(text).concat(), text);
+        assert_eq!(tokenize("a b"), vec!["a", " ", "b"]);
+    }
+
+    #[test]
+    fn test_word_diff() {
+        assert_eq!(
+            word_diff("the quick brwon fox", "the quick brown fox"),
+            vec![
+                DiffOp::Same("the quick ".into()),
+                DiffOp::Removed("brwon".into()),
+                DiffOp::Added("brown".into()),
+                DiffOp::Same(" fox".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_word_diff_insertion_and_deletion() {
+        assert_eq!(
+            word_diff("a b c", "a c d"),
+            vec![
+                DiffOp::Same("a ".into()),
+                DiffOp::Removed("b ".into()),
+                DiffOp::Same("c".into()),
+                DiffOp::Added(" d".into()),
+            ]
+        );
+        assert_eq!(word_diff("", ""), vec![]);
+    }
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = lines("a\n\nb\n\nc\n\nd\n\ne");
+        let new = lines("a\n\nB\n\nc\n\nd\n\nE");
+        assert_eq!(
+            unified_diff("book.txt", &old, &new, 1),
+            "--- a/book.txt\n+++ b/book.txt\n\
+            @@ -2,3 +2,3 @@\n \n-b\n+B\n \n\
+            @@ -8,2 +8,2 @@\n \n-e\n+E\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_merges_nearby_hunks() {
+        let old = lines("a\nb\nc");
+        let new = lines("A\nb\nC");
+        assert_eq!(
+            unified_diff("f", &old, &new, 1),
+            "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n-a\n+A\n b\n-c\n+C\n"
+        );
+        assert_eq!(unified_diff("f", &old, &old, 1), "");
+    }
+
+    #[test]
+    fn test_patch_text() {
+        let text = "one\ntwo\nthree\nfour\nfive\n";
+        // Join "two" and "three" by replacing the line break, and add a line
+        // after "five".
+        let patch = patch_text(
+            "f",
+            text,
+            &[(7..8, " "), (text.len()..text.len(), "six\n")],
+            1,
+        );
+        assert_eq!(
+            patch,
+            "--- a/f\n+++ b/f\n\
+            @@ -1,5 +1,5 @@\n one\n-two\n-three\n+two three\n four\n five\n+six\n"
+        );
+        assert_eq!(patch_text("f", text, &[], 1), "");
+    }
+
+    #[test]
+    fn test_patch_text_numbers_later_hunks() {
+        let text = "a\nb\nc\nd\ne\nf\ng\n";
+        let patch = patch_text("f", text, &[(0..0, "new\n"), (12..13, "G")], 1);
+        assert_eq!(
+            patch,
+            "--- a/f\n+++ b/f\n\
+            @@ -1,1 +1,2 @@\n+new\n a\n\
+            @@ -6,2 +7,2 @@\n f\n-g\n+G\n"
+        );
+    }
+}
@@ -9,11 +9,12 @@ pub enum ErrT {
     AuthNonUtf8Cookie,
     AuthNotAuthenticated,
     BookUi,
+    Config,
     DbConnectionFailure,
     DbMigrationFailure,
     DbReturnedErronoeousRole,
-    TokenInUse,
     Invariant,
+    NotFound,
     SqlxError,
     ValidationError,
 }
@@ -102,14 +103,19 @@ impl Display for ErrStack {
 
 impl IntoResponse for ErrStack {
     /// Maps errors into [StatusCode::INTERNAL_SERVER_ERROR], unless the stack
-    /// contains one or more frames of variant [ErrT::AuthNotAuthenticated].
+    /// contains one or more frames of variant [ErrT::AuthNotAuthenticated],
+    /// [ErrT::NotFound] or [ErrT::ValidationError], in that order of
+    /// precedence.
     fn into_response(self) -> axum::response::Response {
         eprintln!("{self}");
-        if self
-            .jenga()
-            .any(|variant| matches!(variant, ErrT::AuthNotAuthenticated))
-        {
+        let has = |target: ErrT| self.jenga().any(|v| *v == target);
+        if has(ErrT::AuthNotAuthenticated) {
             (StatusCode::FORBIDDEN, "not authenticated").into_response()
+        } else if has(ErrT::NotFound) {
+            (StatusCode::NOT_FOUND, "not found").into_response()
+        } else if has(ErrT::ValidationError) {
+            (StatusCode::UNPROCESSABLE_ENTITY, "invalid request")
+                .into_response()
         } else {
             (StatusCode::INTERNAL_SERVER_ERROR, "An error occurred")
                 .into_response()
@@ -0,0 +1,568 @@
+//! Exporting comments and reactions for offline review, as Markdown or CSV.
+//! Comments are grouped by chapter and ordered by their position in the
+//! book, and replies follow the comment they answer; deleted comments are
+//! left out. Reactions are counted for each block. Used by both the admin UI
+//! and the CLI.
+
+use crate::{
+    comment::CommentStatus,
+    prelude::*,
+    reaction::{summarize, tally, ReactionType},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Csv => "text/csv; charset=utf-8",
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Csv => "csv",
+        }
+    }
+    pub fn render(&self, comments: &[ExportComment]) -> String {
+        match self {
+            Self::Markdown => to_markdown(comments),
+            Self::Csv => to_csv(comments),
+        }
+    }
+    pub fn render_reactions(&self, reactions: &[ExportReaction]) -> String {
+        match self {
+            Self::Markdown => reactions_to_markdown(reactions),
+            Self::Csv => reactions_to_csv(reactions),
+        }
+    }
+}
+
+impl TryFrom<&str> for ExportFormat {
+    type Error = ErrStack;
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "csv" => Ok(Self::Csv),
+            _ => Err(ErrStack::new(ErrT::ValidationError)
+                .ctx(format!("{value} is not an export format"))),
+        }
+    }
+}
+
+/// Which comments to export. Filters apply to the comment which starts each
+/// thread; replies are exported along with their thread. Reactions have no
+/// status, so [load_reactions] ignores it; callers should refuse a status
+/// filter for reactions rather than quietly dropping it.
+#[derive(Debug, Default)]
+pub struct ExportFilters {
+    /// Comments are matched on the revision they were written against, even
+    /// if they've since been carried forward into a newer one.
+    pub revision: Option<i32>,
+    /// Inclusive.
+    pub since: Option<NaiveDate>,
+    /// Inclusive.
+    pub until: Option<NaiveDate>,
+    pub status: Option<CommentStatus>,
+}
+
+#[derive(Debug)]
+pub struct ExportComment {
+    pub id: i32,
+    pub parent_id: Option<i32>,
+    pub book_revision_id: i32,
+    pub chapter: Option<String>,
+    pub sequence: i32,
+    pub block_content: String,
+    pub quote: Option<String>,
+    pub reader_name: String,
+    pub comment: String,
+    /// `None` for comments written before we kept track.
+    pub created_at: Option<DateTime<Utc>>,
+    pub status_id: i32,
+}
+
+impl ExportComment {
+    fn status(&self) -> &'static str {
+        self.status_id
+            .try_into()
+            .map(|s: CommentStatus| s.label())
+            .unwrap_or_default()
+    }
+}
+
+pub async fn load_comments(
+    db: impl PgExecutor<'_>,
+    filters: &ExportFilters,
+) -> Result<Vec<ExportComment>> {
+    let status_id: Option<i32> = filters.status.map(|s| s.into());
+    query_as!(
+        ExportComment,
+        r#"with thread as (
+            select c.id
+            from comment c
+            join block ob on ob.id = coalesce(c.original_block_id, c.block_id)
+            where
+                c.parent_id is null
+                and c.deleted_at is null
+                and ($1::int is null or ob.book_revision_id = $1)
+                and ($2::date is null or c.created_at >= $2::date)
+                and ($3::date is null or c.created_at < $3::date + 1)
+                and ($4::int is null or c.status_id = $4)
+        )
+        select
+            c.id,
+            c.parent_id,
+            b.book_revision_id,
+            ch.content "chapter?",
+            b.sequence,
+            b.content block_content,
+            c.quote,
+            t.name reader_name,
+            c.comment,
+            c.created_at,
+            c.status_id
+        from comment c
+        join thread on thread.id = coalesce(c.parent_id, c.id)
+        join token t on t.id = c.token_id
+        join block b on b.id = c.block_id
+        join block_chapter bc on bc.block_id = b.id
+        left join block ch on ch.id = bc.chapter_id
+        where c.deleted_at is null
+        order by
+            b.book_revision_id,
+            b.sequence,
+            coalesce(c.parent_id, c.id),
+            c.parent_id nulls first,
+            c.created_at"#,
+        filters.revision,
+        filters.since,
+        filters.until,
+        status_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "export::load_comments"))
+}
+
+/// The number of reactions of one type on a block.
+#[derive(Debug)]
+pub struct ExportReaction {
+    pub book_revision_id: i32,
+    /// The id of the chapter's heading block.
+    pub chapter_id: Option<i32>,
+    pub chapter: Option<String>,
+    pub sequence: i32,
+    pub block_content: String,
+    pub type_id: i32,
+    pub count: i64,
+}
+
+pub async fn load_reactions(
+    db: impl PgExecutor<'_>,
+    filters: &ExportFilters,
+) -> Result<Vec<ExportReaction>> {
+    query_as!(
+        ExportReaction,
+        r#"select
+            b.book_revision_id,
+            bc.chapter_id,
+            ch.content "chapter?",
+            b.sequence,
+            b.content block_content,
+            r.type_id,
+            count(*) "count!"
+        from reaction r
+        join block b on b.id = r.block_id
+        join block_chapter bc on bc.block_id = b.id
+        left join block ch on ch.id = bc.chapter_id
+        where
+            ($1::int is null or b.book_revision_id = $1)
+            and ($2::date is null or r.created_at >= $2::date)
+            and ($3::date is null or r.created_at < $3::date + 1)
+        group by
+            b.book_revision_id,
+            bc.chapter_id,
+            ch.content,
+            b.sequence,
+            b.content,
+            r.type_id
+        order by b.book_revision_id, b.sequence, r.type_id"#,
+        filters.revision,
+        filters.since,
+        filters.until
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "export::load_reactions"))
+}
+
+/// Indent every line after the first, so that multi-line text stays inside
+/// its Markdown list item.
+fn indent_continuation(text: &str, indent: &str) -> String {
+    text.lines()
+        .collect::<Vec<_>>()
+        .join(&format!("\n{indent}"))
+}
+
+/// Comments must be ordered as by [load_comments].
+pub fn to_markdown(comments: &[ExportComment]) -> String {
+    let many_revisions = comments
+        .windows(2)
+        .any(|w| w[0].book_revision_id != w[1].book_revision_id);
+    let mut out = String::from("# Comments\n");
+    let mut chapter: Option<(i32, Option<&str>)> = None;
+    let mut block: Option<(i32, i32)> = None;
+    for c in comments {
+        let this_chapter = (c.book_revision_id, c.chapter.as_deref());
+        if chapter != Some(this_chapter) {
+            chapter = Some(this_chapter);
+            let title =
+                c.chapter.as_deref().unwrap_or("Before the first chapter");
+            if many_revisions {
+                let _ = write!(
+                    out,
+                    "\n## {title} (revision {})\n",
+                    c.book_revision_id
+                );
+            } else {
+                let _ = write!(out, "\n## {title}\n");
+            }
+        }
+        let this_block = (c.book_revision_id, c.sequence);
+        if block != Some(this_block) {
+            block = Some(this_block);
+            let quote = c
+                .block_content
+                .lines()
+                .map(|l| format!("> {l}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let _ = write!(out, "\n{quote}\n\n");
+        }
+        let date = c
+            .created_at
+            .map(|t| t.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "undated".into());
+        match c.parent_id {
+            None => {
+                let on = c
+                    .quote
+                    .as_ref()
+                    .map(|q| format!(" on \"{q}\""))
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    out,
+                    "- **{}**, {date} ({}){on}: {}",
+                    c.reader_name,
+                    c.status(),
+                    indent_continuation(&c.comment, "  ")
+                );
+            }
+            Some(_) => {
+                let _ = writeln!(
+                    out,
+                    "  - **{}**, {date}: {}",
+                    c.reader_name,
+                    indent_continuation(&c.comment, "    ")
+                );
+            }
+        }
+    }
+    out
+}
+
+/// Spreadsheets run cells which start with one of these as formulas, so a
+/// reader could write a comment which does something when the author opens
+/// the export.
+const CSV_FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(CSV_FORMULA_PREFIXES) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Comments must be ordered as by [load_comments].
+pub fn to_csv(comments: &[ExportComment]) -> String {
+    let mut out = String::from(
+        "id,reply_to,revision,chapter,sequence,reader,created_at,status,quote,block,comment\n",
+    );
+    for c in comments {
+        let row = [
+            c.id.to_string(),
+            c.parent_id.map(|id| id.to_string()).unwrap_or_default(),
+            c.book_revision_id.to_string(),
+            c.chapter.clone().unwrap_or_default(),
+            c.sequence.to_string(),
+            c.reader_name.clone(),
+            c.created_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            c.status().to_string(),
+            c.quote.clone().unwrap_or_default(),
+            c.block_content.clone(),
+            c.comment.clone(),
+        ]
+        .iter()
+        .map(|f| csv_field(f))
+        .collect::<Vec<_>>()
+        .join(",");
+        out.push_str(&row);
+        out.push('\n');
+    }
+    out
+}
+
+/// Group reaction counts into runs which share a key, preserving order.
+pub fn runs<K: PartialEq>(
+    reactions: &[ExportReaction],
+    key: impl Fn(&ExportReaction) -> K,
+) -> Vec<&[ExportReaction]> {
+    let mut result: Vec<&[ExportReaction]> = Vec::new();
+    let mut start = 0;
+    for i in 1..=reactions.len() {
+        if i == reactions.len() || key(&reactions[i]) != key(&reactions[start])
+        {
+            result.push(&reactions[start..i]);
+            start = i;
+        }
+    }
+    result
+}
+
+pub fn summarize_run(run: &[ExportReaction]) -> String {
+    summarize(&tally(run.iter().map(|r| (r.type_id, r.count))))
+}
+
+/// Reactions must be ordered as by [load_reactions]. Each chapter gets a
+/// total, followed by the blocks within it which have reactions.
+pub fn reactions_to_markdown(reactions: &[ExportReaction]) -> String {
+    let many_revisions = reactions
+        .windows(2)
+        .any(|w| w[0].book_revision_id != w[1].book_revision_id);
+    let mut out = String::from("# Reactions\n");
+    for chapter in runs(reactions, |r| (r.book_revision_id, r.chapter.clone()))
+    {
+        let first = &chapter[0];
+        let title = first
+            .chapter
+            .as_deref()
+            .unwrap_or("Before the first chapter");
+        let total = summarize_run(chapter);
+        if many_revisions {
+            let _ = write!(
+                out,
+                "\n## {title} (revision {}): {total}\n",
+                first.book_revision_id
+            );
+        } else {
+            let _ = write!(out, "\n## {title}: {total}\n");
+        }
+        for block in runs(chapter, |r| r.sequence) {
+            let quote = block[0]
+                .block_content
+                .lines()
+                .map(|l| format!("> {l}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let _ = write!(out, "\n{quote}\n\n{}\n", summarize_run(block));
+        }
+    }
+    out
+}
+
+/// Reactions must be ordered as by [load_reactions].
+pub fn reactions_to_csv(reactions: &[ExportReaction]) -> String {
+    let mut out =
+        String::from("revision,chapter,sequence,block,reaction,count\n");
+    for r in reactions {
+        let reaction = r
+            .type_id
+            .try_into()
+            .map(|t: ReactionType| t.name())
+            .unwrap_or_default();
+        let row = [
+            r.book_revision_id.to_string(),
+            r.chapter.clone().unwrap_or_default(),
+            r.sequence.to_string(),
+            r.block_content.clone(),
+            reaction.to_string(),
+            r.count.to_string(),
+        ]
+        .iter()
+        .map(|f| csv_field(f))
+        .collect::<Vec<_>>()
+        .join(",");
+        out.push_str(&row);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn comment(
+        id: i32,
+        parent_id: Option<i32>,
+        chapter: &str,
+        sequence: i32,
+        comment: &str,
+    ) -> ExportComment {
+        ExportComment {
+            id,
+            parent_id,
+            book_revision_id: 1,
+            chapter: Some(chapter.into()),
+            sequence,
+            block_content: format!("Block {sequence}."),
+            quote: None,
+            reader_name: if parent_id.is_some() {
+                "Author"
+            } else {
+                "Robin"
+            }
+            .into(),
+            comment: comment.into(),
+            created_at: Some(
+                Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+            ),
+            status_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_markdown_groups_by_chapter_and_block() {
+        let comments = [
+            comment(1, None, "Chapter 1", 2, "Nice."),
+            comment(3, Some(1), "Chapter 1", 2, "Thanks!"),
+            comment(2, None, "Chapter 1", 2, "Typo here."),
+            comment(4, None, "Chapter 2", 9, "Line one\nline two"),
+        ];
+        assert_eq!(
+            to_markdown(&comments),
+            "# Comments\n\
+            \n## Chapter 1\n\
+            \n> Block 2.\n\n\
+            - **Robin**, 2024-03-01 (open): Nice.\n\
+            \x20 - **Author**, 2024-03-01: Thanks!\n\
+            - **Robin**, 2024-03-01 (open): Typo here.\n\
+            \n## Chapter 2\n\
+            \n> Block 9.\n\n\
+            - **Robin**, 2024-03-01 (open): Line one\n\
+            \x20 line two\n"
+        );
+    }
+
+    #[test]
+    fn test_markdown_quote() {
+        let mut c = comment(1, None, "Chapter 1", 2, "Hmm.");
+        c.quote = Some("Block".into());
+        assert!(to_markdown(&[c]).contains("(open) on \"Block\": Hmm."));
+    }
+
+    #[test]
+    fn test_csv_escapes_fields() {
+        let comments = [comment(
+            1,
+            None,
+            "Chapter 1",
+            2,
+            "He said \"hi\", then\nleft",
+        )];
+        let csv = to_csv(&comments);
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("id,reply_to,revision,chapter,sequence,reader,created_at,status,quote,block,comment")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("1,,1,Chapter 1,2,Robin,2024-03-01T12:00:00+00:00,open,,Block 2.,\"He said \"\"hi\"\", then")
+        );
+        assert_eq!(lines.next(), Some("left\""));
+    }
+
+    #[test]
+    fn test_csv_guards_against_formulas() {
+        assert_eq!(
+            csv_field("=HYPERLINK(\"x\")"),
+            "\"'=HYPERLINK(\"\"x\"\")\""
+        );
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-1"), "'-1");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("a = b"), "a = b");
+    }
+
+    fn reaction(
+        chapter: &str,
+        sequence: i32,
+        type_id: i32,
+        count: i64,
+    ) -> ExportReaction {
+        ExportReaction {
+            book_revision_id: 1,
+            chapter_id: None,
+            chapter: Some(chapter.into()),
+            sequence,
+            block_content: format!("Block {sequence}."),
+            type_id,
+            count,
+        }
+    }
+
+    #[test]
+    fn test_reactions_markdown() {
+        let reactions = [
+            reaction("Chapter 1", 2, 1, 2),
+            reaction("Chapter 1", 2, 3, 1),
+            reaction("Chapter 1", 4, 1, 1),
+            reaction("Chapter 2", 9, 4, 5),
+        ];
+        assert_eq!(
+            reactions_to_markdown(&reactions),
+            "# Reactions\n\
+            \n## Chapter 1: \u{2764}\u{fe0f} 3 · \u{1f615} 1\n\
+            \n> Block 2.\n\n\u{2764}\u{fe0f} 2 · \u{1f615} 1\n\
+            \n> Block 4.\n\n\u{2764}\u{fe0f} 1\n\
+            \n## Chapter 2: \u{1f422} 5\n\
+            \n> Block 9.\n\n\u{1f422} 5\n"
+        );
+    }
+
+    #[test]
+    fn test_reactions_csv() {
+        let csv = reactions_to_csv(&[reaction("Chapter 1", 2, 4, 3)]);
+        assert_eq!(
+            csv,
+            "revision,chapter,sequence,block,reaction,count\n\
+            1,Chapter 1,2,Block 2.,too_slow,3\n"
+        );
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!(
+            ExportFormat::try_from("md").unwrap(),
+            ExportFormat::Markdown
+        );
+        assert_eq!(ExportFormat::try_from("csv").unwrap(), ExportFormat::Csv);
+        assert!(ExportFormat::try_from("pdf").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! Highlights are character ranges within a block. This module holds the
+//! pure parts; splitting a block's text into marked and unmarked segments for
+//! rendering, and finding a highlight's quoted text again after the block has
+//! been edited in a new revision of the book.
+
+/// We'll accept a fuzzy match for a quote if it differs from the original by
+/// at most one in this many characters.
+const MAX_ERROR_FRACTION: usize = 4;
+
+/// A run of text within a block, between two character offsets.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    /// Index into the ranges passed to [segments] of the highlight covering
+    /// this segment, if any. Where highlights overlap, the later one wins.
+    pub mark: Option<usize>,
+}
+
+/// Split the text between `start` and `end` into segments, such that each
+/// segment is either covered by exactly one of `ranges`, or by none of them.
+/// Ranges are `(start, end)` character offsets into the whole block, and may
+/// extend outside of `start..end`.
+pub fn segments(
+    start: usize,
+    end: usize,
+    ranges: &[(usize, usize)],
+) -> Vec<Segment> {
+    let mut bounds = vec![start, end];
+    for (range_start, range_end) in ranges {
+        for bound in [*range_start, *range_end] {
+            if bound > start && bound < end {
+                bounds.push(bound);
+            }
+        }
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut result: Vec<Segment> = Vec::new();
+    for pair in bounds.windows(2) {
+        let (seg_start, seg_end) = (pair[0], pair[1]);
+        let mark = ranges
+            .iter()
+            .rposition(|(s, e)| *s <= seg_start && *e >= seg_end);
+        match result.last_mut() {
+            Some(last) if last.mark == mark => last.end = seg_end,
+            _ => result.push(Segment {
+                start: seg_start,
+                end: seg_end,
+                mark,
+            }),
+        }
+    }
+    result
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct QuoteMatch {
+    /// Character offset where the quote begins.
+    pub start: usize,
+    /// Character offset where the quote ends (exclusive).
+    pub end: usize,
+    /// False if we had to settle for approximately matching text.
+    pub exact: bool,
+}
+
+/// Find `quote` in `content`. If the quote appears verbatim, we take the
+/// occurrence nearest to `hint` (the quote's offset in the old revision).
+/// Otherwise, we look for the passage which most closely resembles it.
+pub fn find_quote(
+    content: &str,
+    quote: &str,
+    hint: usize,
+) -> Option<QuoteMatch> {
+    if quote.is_empty() {
+        return None;
+    }
+    let quote_len = quote.chars().count();
+    let exact = content
+        .match_indices(quote)
+        .map(|(byte_index, _)| content[..byte_index].chars().count())
+        .min_by_key(|start| start.abs_diff(hint));
+    if let Some(start) = exact {
+        return Some(QuoteMatch {
+            start,
+            end: start + quote_len,
+            exact: true,
+        });
+    }
+    fuzzy_find(content, quote, quote_len / MAX_ERROR_FRACTION).map(
+        |(start, end)| QuoteMatch {
+            start,
+            end,
+            exact: false,
+        },
+    )
+}
+
+/// Approximate substring search; find the substring of `haystack` with the
+/// smallest edit distance to `needle`, as long as that distance is no more
+/// than `max_distance`. Returns character offsets `(start, end)`.
+///
+/// This is the usual edit distance dynamic program, except that skipping
+/// characters of the haystack before the match begins is free.
+pub fn fuzzy_find(
+    haystack: &str,
+    needle: &str,
+    max_distance: usize,
+) -> Option<(usize, usize)> {
+    let needle: Vec<char> = needle.chars().collect();
+    if needle.is_empty() {
+        return None;
+    }
+
+    // For each prefix length of the needle, the (cost, start offset) of the
+    // cheapest alignment ending at the current haystack character.
+    let mut prev: Vec<(usize, usize)> =
+        (0..=needle.len()).map(|i| (i, 0)).collect();
+    let mut best: Option<(usize, usize, usize)> = None;
+
+    for (j, h) in haystack.chars().enumerate() {
+        let mut cur = Vec::with_capacity(needle.len() + 1);
+        cur.push((0, j + 1));
+        for i in 1..=needle.len() {
+            let substitution = (
+                prev[i - 1].0 + usize::from(needle[i - 1] != h),
+                prev[i - 1].1,
+            );
+            let extra_haystack_char = (prev[i].0 + 1, prev[i].1);
+            let missing_needle_char = (cur[i - 1].0 + 1, cur[i - 1].1);
+            cur.push(
+                [substitution, extra_haystack_char, missing_needle_char]
+                    .into_iter()
+                    .min_by_key(|(cost, _)| *cost)
+                    .expect("there are three candidates"),
+            );
+        }
+        let (cost, start) = cur[needle.len()];
+        if cost <= max_distance && best.is_none_or(|(c, _, _)| cost < c) {
+            best = Some((cost, start, j + 1));
+        }
+        prev = cur;
+    }
+
+    best.map(|(_, start, end)| (start, end))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_segments_without_ranges() {
+        assert_eq!(
+            segments(0, 10, &[]),
+            vec![Segment {
+                start: 0,
+                end: 10,
+                mark: None
+            }]
+        );
+    }
+
+    #[test]
+    fn test_segments_clip_ranges_to_slice() {
+        assert_eq!(
+            segments(10, 20, &[(5, 12), (18, 30)]),
+            vec![
+                Segment {
+                    start: 10,
+                    end: 12,
+                    mark: Some(0)
+                },
+                Segment {
+                    start: 12,
+                    end: 18,
+                    mark: None
+                },
+                Segment {
+                    start: 18,
+                    end: 20,
+                    mark: Some(1)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segments_overlapping_ranges() {
+        assert_eq!(
+            segments(0, 10, &[(0, 6), (4, 8)]),
+            vec![
+                Segment {
+                    start: 0,
+                    end: 4,
+                    mark: Some(0)
+                },
+                Segment {
+                    start: 4,
+                    end: 8,
+                    mark: Some(1)
+                },
+                Segment {
+                    start: 8,
+                    end: 10,
+                    mark: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_quote_exact_nearest_hint() {
+        let content = "the cat sat on the mat";
+        assert_eq!(
+            find_quote(content, "the", 17),
+            Some(QuoteMatch {
+                start: 15,
+                end: 18,
+                exact: true
+            })
+        );
+        assert_eq!(find_quote(content, "the", 0).unwrap().start, 0);
+    }
+
+    #[test]
+    fn test_find_quote_counts_chars() {
+        let content = "café au lait, s'il vous plaît";
+        assert_eq!(
+            find_quote(content, "lait", 0),
+            Some(QuoteMatch {
+                start: 8,
+                end: 12,
+                exact: true
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_quote_after_edit() {
+        let content = "Before. The quick brown fox jumped over the dog. After.";
+        let m =
+            find_quote(content, "The quick brown fox jumps over the dog.", 0)
+                .unwrap();
+        assert!(!m.exact);
+        assert_eq!(
+            char_range(content, m.start, m.end),
+            "The quick brown fox jumped over the dog."
+        );
+    }
+
+    #[test]
+    fn test_find_quote_gives_up_on_rewrites() {
+        let content = "Something else entirely is written here now.";
+        assert_eq!(find_quote(content, "The quick brown fox.", 0), None);
+    }
+
+    fn char_range(s: &str, start: usize, end: usize) -> String {
+        s.chars().skip(start).take(end - start).collect()
+    }
+}
@@ -0,0 +1,168 @@
+//! Invitation links; a single-use, expiring code which signs a reader in.
+//! Redeeming an invitation gives the reader's token a new secret, which goes
+//! straight into their cookie, so nobody ever has to see or copy it.
+
+use crate::{
+    auth::{sha256_hex, Token},
+    bytes::Bytes,
+    prelude::*,
+};
+use aws_lc_rs::rand::fill;
+use chrono::Duration;
+
+/// How long an invitation link works for.
+pub const INVITATION_LIFETIME: Duration = Duration::days(7);
+
+#[derive(Eq, PartialEq)]
+pub struct InvitationCode(String);
+
+impl InvitationCode {
+    /// Read a code from a URL. Anything which [InvitationCode::create] could
+    /// not have made is [ErrT::NotFound], so that it goes no further.
+    pub fn parse(code: String) -> Result<Self> {
+        if code.len() == 64 && code.chars().all(|c| c.is_ascii_hexdigit()) {
+            Ok(Self(code))
+        } else {
+            Err(ErrStack::new(ErrT::NotFound)
+                .ctx("invitation code is not 64 hex digits".into()))
+        }
+    }
+    /// Codes go in URLs, so they're hex rather than base64.
+    pub fn create() -> Result<Self> {
+        let mut buffer = [0u8; 32];
+        fill(&mut buffer).map_err(|e| {
+            ErrStack::new(ErrT::Invariant)
+                .ctx(format!("aws says no random bytes for you: {e}"))
+        })?;
+        Ok(Self(buffer.to_hex()))
+    }
+    pub fn sha256_hex(&self) -> String {
+        sha256_hex(&self.0)
+    }
+    pub fn display_secret_value(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for InvitationCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "InvitationCode([sensitive value omitted])")
+    }
+}
+
+/// `$PUBLIC_URL`, without a trailing slash. Links we hand out are built on
+/// it, rather than on whatever `Host` a request claims.
+pub fn public_url() -> Result<String> {
+    let url = std::env::var("PUBLIC_URL").map_err(|e| {
+        ErrStack::new(ErrT::Config).ctx(format!("PUBLIC_URL: {e}"))
+    })?;
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return Err(ErrStack::new(ErrT::Config).ctx(format!(
+            "PUBLIC_URL must start with http:// or https://, not {url:?}"
+        )));
+    }
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+/// The absolute URL of an invitation, for the admin to send to a reader. The
+/// path is the website's `Route::Invite`.
+pub fn invite_link(public_url: &str, code: &InvitationCode) -> String {
+    format!("{public_url}/invite/{}", code.display_secret_value())
+}
+
+/// Create an invitation for `token_id`. Only the code's digest is stored, so
+/// this is the only chance to show it to anybody.
+pub async fn create_invitation(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+) -> Result<InvitationCode> {
+    let code = InvitationCode::create()?;
+    query!(
+        "insert into invitation (code_digest, expires_at, token_id)
+        values ($1, now() + make_interval(days => $2), $3)",
+        code.sha256_hex(),
+        INVITATION_LIFETIME.num_days() as i32,
+        token_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "create_invitation"))?;
+    Ok(code)
+}
+
+/// Use up the invitation, if it and its token are still good, and return a
+/// fresh secret for the invited token. Any secret the token had before stops
+/// working. An invitation for a token which has since been revoked or has
+/// expired is left alone.
+pub async fn redeem_invitation(
+    db: impl PgExecutor<'_>,
+    code: &InvitationCode,
+) -> Result<Option<Token>> {
+    let token = Token::create()?;
+    let redeemed = query!(
+        "with used as (
+            update invitation
+            set used_at = now()
+            from token t
+            where
+                code_digest = $1
+                and used_at is null
+                and invitation.expires_at > now()
+                and t.id = invitation.token_id
+                and t.revoked_at is null
+                and (t.expires_at is null or t.expires_at > now())
+            returning invitation.token_id
+        )
+        update token
+        set token_digest = $2
+        from used
+        where token.id = used.token_id
+        returning token.id",
+        code.sha256_hex(),
+        token.sha256_hex()
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "redeem_invitation"))?;
+    Ok(redeemed.map(|_| token))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_url_safe_and_unique() {
+        let a = InvitationCode::create().unwrap();
+        let b = InvitationCode::create().unwrap();
+        assert_eq!(a.display_secret_value().len(), 64);
+        assert!(a
+            .display_secret_value()
+            .chars()
+            .all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+        assert_ne!(a.sha256_hex(), a.display_secret_value());
+    }
+
+    #[test]
+    fn test_parse_rejects_anything_but_codes() {
+        let code = InvitationCode::create().unwrap();
+        let parsed =
+            InvitationCode::parse(code.display_secret_value().to_string());
+        assert!(parsed.is_ok_and(|p| p == code));
+        for bad in
+            ["", "abc", &"g".repeat(64), &format!("{}\"", "a".repeat(63))]
+        {
+            assert!(InvitationCode::parse(bad.to_string()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invite_link() {
+        let code = InvitationCode::parse("ab".repeat(32)).unwrap();
+        assert_eq!(
+            invite_link("https://example.com", &code),
+            format!("https://example.com/invite/{}", "ab".repeat(32))
+        );
+    }
+}
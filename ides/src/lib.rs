@@ -1,7 +1,17 @@
+pub mod analytics;
 pub mod auth;
 pub mod bytes;
+pub mod comment;
 pub mod content;
 pub mod db;
+pub mod diff;
 pub mod error;
+pub mod export;
+pub mod highlight;
+pub mod invitation;
 pub mod models;
 pub mod prelude;
+pub mod reaction;
+pub mod revision;
+pub mod session;
+pub mod suggestion;
@@ -0,0 +1,127 @@
+//! Quick reactions; a reader can tap one of a fixed set of reactions on a
+//! block, rather than writing a comment.
+
+use crate::prelude::*;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReactionType {
+    Love,
+    Funny,
+    Confusing,
+    TooSlow,
+}
+
+impl ReactionType {
+    pub const ALL: [Self; 4] =
+        [Self::Love, Self::Funny, Self::Confusing, Self::TooSlow];
+
+    /// Identifier used in forms.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Love => "love",
+            Self::Funny => "funny",
+            Self::Confusing => "confusing",
+            Self::TooSlow => "too_slow",
+        }
+    }
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            Self::Love => "\u{2764}\u{fe0f}",
+            Self::Funny => "\u{1f602}",
+            Self::Confusing => "\u{1f615}",
+            Self::TooSlow => "\u{1f422}",
+        }
+    }
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Love => "loved this",
+            Self::Funny => "funny",
+            Self::Confusing => "confusing",
+            Self::TooSlow => "too slow",
+        }
+    }
+}
+
+impl From<ReactionType> for i32 {
+    fn from(val: ReactionType) -> Self {
+        match val {
+            ReactionType::Love => 1,
+            ReactionType::Funny => 2,
+            ReactionType::Confusing => 3,
+            ReactionType::TooSlow => 4,
+        }
+    }
+}
+
+impl TryInto<ReactionType> for i32 {
+    type Error = ErrStack;
+    fn try_into(self) -> Result<ReactionType> {
+        match self {
+            1 => Ok(ReactionType::Love),
+            2 => Ok(ReactionType::Funny),
+            3 => Ok(ReactionType::Confusing),
+            4 => Ok(ReactionType::TooSlow),
+            _ => Err(ErrStack::new(ErrT::Invariant)
+                .ctx(format!("{self} is not a valid i32 for ReactionType"))),
+        }
+    }
+}
+
+/// Add up `(type_id, count)` pairs into a count for each reaction type, in
+/// the order of [ReactionType::ALL]. Unknown type IDs are ignored.
+pub fn tally(
+    counts: impl IntoIterator<Item = (i32, i64)>,
+) -> [(ReactionType, i64); 4] {
+    let mut result = ReactionType::ALL.map(|r| (r, 0));
+    for (type_id, count) in counts {
+        if let Ok(reaction) = TryInto::<ReactionType>::try_into(type_id) {
+            if let Some(entry) = result.iter_mut().find(|(r, _)| *r == reaction)
+            {
+                entry.1 += count;
+            }
+        }
+    }
+    result
+}
+
+/// Reaction counts in a short form like "❤️ 3 · 😕 1", leaving out reactions
+/// with no count.
+pub fn summarize(tally: &[(ReactionType, i64)]) -> String {
+    tally
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(r, count)| format!("{} {count}", r.emoji()))
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tally_and_summarize() {
+        let tally = tally([(3, 1), (1, 2), (1, 1), (99, 5)]);
+        assert_eq!(
+            tally,
+            [
+                (ReactionType::Love, 3),
+                (ReactionType::Funny, 0),
+                (ReactionType::Confusing, 1),
+                (ReactionType::TooSlow, 0),
+            ]
+        );
+        assert_eq!(summarize(&tally), "\u{2764}\u{fe0f} 3 · \u{1f615} 1");
+    }
+
+    #[test]
+    fn test_ids_round_trip() {
+        for reaction in ReactionType::ALL {
+            let id: i32 = reaction.into();
+            let back: ReactionType = id.try_into().unwrap();
+            assert_eq!(back, reaction);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Mapping positions from one revision of the book to another, using the
+//! content checksums of blocks. The algorithm is described in detail in the
+//! website's pagination module; in short, we try for a "perfect match" on a
+//! **canonical checksum** (a checksum which exists exactly once in both
+//! revisions), then a "close match" relative to the nearest canonical
+//! checksum, and finally fall back to a "rough match" at the same percentage
+//! of the way through the book.
+//!
+//! A close match looks for the nearest canonical checksum on both sides. If
+//! blocks were inserted or deleted between them, the two disagree about
+//! where the block went; we then take whichever block between them has the
+//! most words in common with it.
+
+use crate::{
+    content::{sequence_at_percent, Progress},
+    prelude::*,
+};
+use sqlx::PgConnection;
+use std::collections::{HashMap, HashSet};
+
+/// How far we'll look in each direction for a canonical checksum before
+/// giving up on a close match.
+const CLOSE_MATCH_DISTANCE: i32 = 30;
+
+#[derive(Debug)]
+pub struct BlockChecksum {
+    pub id: i32,
+    pub sequence: i32,
+    pub checksum: String,
+    pub content: String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchKind {
+    Perfect,
+    Close,
+    Rough,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Relocation {
+    pub block_id: i32,
+    pub sequence: i32,
+    pub kind: MatchKind,
+    /// True if the content of the block we landed on differs from the
+    /// content of the block we started from.
+    pub content_changed: bool,
+}
+
+pub struct RevisionMap {
+    from: Vec<BlockChecksum>,
+    to: Vec<BlockChecksum>,
+    /// Sequence in the old revision => index into `to`, for blocks with
+    /// canonical checksums.
+    canonical: HashMap<i32, usize>,
+}
+
+impl RevisionMap {
+    /// Blocks of each revision must be ordered by sequence.
+    pub fn new(from: Vec<BlockChecksum>, to: Vec<BlockChecksum>) -> Self {
+        let mut counts: HashMap<&str, (usize, usize, Option<usize>)> =
+            HashMap::new();
+        for block in &from {
+            counts.entry(&block.checksum).or_default().0 += 1;
+        }
+        for (i, block) in to.iter().enumerate() {
+            let entry = counts.entry(&block.checksum).or_default();
+            entry.1 += 1;
+            entry.2 = Some(i);
+        }
+        let canonical = from
+            .iter()
+            .filter_map(|block| match counts.get(block.checksum.as_str()) {
+                Some((1, 1, Some(to_index))) => {
+                    Some((block.sequence, *to_index))
+                }
+                _ => None,
+            })
+            .collect();
+        Self {
+            from,
+            to,
+            canonical,
+        }
+    }
+    pub async fn load(
+        db: &mut PgConnection,
+        from_revision_id: i32,
+        to_revision_id: i32,
+    ) -> Result<Self> {
+        let from = list_checksums(&mut *db, from_revision_id).await?;
+        let to = list_checksums(&mut *db, to_revision_id).await?;
+        Ok(Self::new(from, to))
+    }
+    /// Find the best block in the new revision for the block at `sequence`
+    /// in the old revision. Returns `None` only if the new revision is
+    /// empty.
+    pub fn relocate(&self, sequence: i32) -> Option<Relocation> {
+        let from_checksum = self
+            .from
+            .binary_search_by_key(&sequence, |b| b.sequence)
+            .ok()
+            .map(|i| self.from[i].checksum.as_str());
+        let relocation = |to_index: usize, kind| {
+            let block = &self.to[to_index];
+            Relocation {
+                block_id: block.id,
+                sequence: block.sequence,
+                kind,
+                content_changed: from_checksum != Some(&block.checksum),
+            }
+        };
+
+        if let Some(to_index) = self.canonical.get(&sequence) {
+            return Some(relocation(*to_index, MatchKind::Perfect));
+        }
+
+        let before = self.close_match(sequence, -1);
+        let after = self.close_match(sequence, 1);
+        let close = match (before, after) {
+            (Some((before_anchor, b)), Some((after_anchor, a))) if a != b => {
+                // Something was inserted or deleted between the anchors.
+                let between = if before_anchor < after_anchor {
+                    before_anchor + 1..after_anchor
+                } else {
+                    b..b + 1
+                };
+                let from_content = self
+                    .from
+                    .binary_search_by_key(&sequence, |b| b.sequence)
+                    .ok()
+                    .map(|i| self.from[i].content.as_str())
+                    .unwrap_or_default();
+                let mut best = b;
+                let mut best_score =
+                    similarity(from_content, &self.to[b].content);
+                for i in between {
+                    let score = similarity(from_content, &self.to[i].content);
+                    if score > best_score {
+                        best = i;
+                        best_score = score;
+                    }
+                }
+                Some(best)
+            }
+            (Some((_, b)), _) => Some(b),
+            (None, Some((_, a))) => Some(a),
+            (None, None) => None,
+        };
+        if let Some(i) = close {
+            return Some(relocation(i, MatchKind::Close));
+        }
+
+        if self.to.is_empty() {
+            return None;
+        }
+        let percent = Progress {
+            sequence,
+            block_count: self.from.len() as i64,
+        }
+        .percent();
+        let target = sequence_at_percent(self.to.len() as i64, percent);
+        let i = self
+            .to
+            .binary_search_by_key(&target, |b| b.sequence)
+            .unwrap_or_else(|i| i.min(self.to.len() - 1));
+        Some(relocation(i, MatchKind::Rough))
+    }
+    /// The nearest canonical checksum to `sequence` in `direction` (-1 or 1)
+    /// which puts it on a block of the new revision, as indices into `to` of
+    /// the anchor and of that block.
+    fn close_match(
+        &self,
+        sequence: i32,
+        direction: i32,
+    ) -> Option<(usize, usize)> {
+        (1..=CLOSE_MATCH_DISTANCE).find_map(|distance| {
+            let anchor = sequence + direction * distance;
+            let anchor_index = *self.canonical.get(&anchor)?;
+            let target = self.to[anchor_index].sequence + (sequence - anchor);
+            let i =
+                self.to.binary_search_by_key(&target, |b| b.sequence).ok()?;
+            Some((anchor_index, i))
+        })
+    }
+    /// Where each block of the old revision lands, keyed by the old block's
+    /// id.
+    pub fn relocate_all(&self) -> impl Iterator<Item = (i32, Relocation)> + '_ {
+        self.from.iter().filter_map(|block| {
+            Some((block.id, self.relocate(block.sequence)?))
+        })
+    }
+}
+
+/// The share of distinct words which two passages have in common.
+fn similarity(a: &str, b: &str) -> f64 {
+    let words = |s: &str| -> HashSet<String> {
+        s.split_whitespace().map(|w| w.to_lowercase()).collect()
+    };
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+/// The blocks of a revision, ordered by sequence.
+pub async fn list_checksums(
+    db: impl PgExecutor<'_>,
+    book_revision_id: i32,
+) -> Result<Vec<BlockChecksum>> {
+    query_as!(
+        BlockChecksum,
+        r#"select id, sequence, content_checksum "checksum!", content
+        from block
+        where book_revision_id = $1
+        order by sequence"#,
+        book_revision_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "list_checksums"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn revision(checksums: &[&str], first_id: i32) -> Vec<BlockChecksum> {
+        checksums
+            .iter()
+            .enumerate()
+            .map(|(i, checksum)| BlockChecksum {
+                id: first_id + i as i32,
+                sequence: i as i32,
+                checksum: checksum.to_string(),
+                content: checksum.replace('-', " "),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_perfect_match() {
+        let map = RevisionMap::new(
+            revision(&["a", "b", "c"], 0),
+            revision(&["new", "a", "b", "c"], 100),
+        );
+        assert_eq!(
+            map.relocate(1),
+            Some(Relocation {
+                block_id: 102,
+                sequence: 2,
+                kind: MatchKind::Perfect,
+                content_changed: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_close_match_on_edited_block() {
+        let map = RevisionMap::new(
+            revision(&["a", "b", "c", "d"], 0),
+            revision(&["new", "a", "b", "c-edited", "d"], 100),
+        );
+        let relocation = map.relocate(2).unwrap();
+        assert_eq!(relocation.kind, MatchKind::Close);
+        assert_eq!(relocation.sequence, 3);
+        assert!(relocation.content_changed);
+    }
+
+    #[test]
+    fn test_close_match_with_insert_next_to_edit() {
+        let map = RevisionMap::new(
+            revision(&["a", "b", "c", "d"], 0),
+            revision(&["a", "b", "new", "c-edited", "d"], 100),
+        );
+        let relocation = map.relocate(2).unwrap();
+        assert_eq!(relocation.kind, MatchKind::Close);
+        assert_eq!(relocation.block_id, 103);
+
+        let map = RevisionMap::new(
+            revision(&["a", "b", "c", "d"], 0),
+            revision(&["a", "b", "c-edited", "new", "d"], 100),
+        );
+        assert_eq!(map.relocate(2).unwrap().block_id, 102);
+    }
+
+    #[test]
+    fn test_duplicates_are_not_canonical() {
+        let map = RevisionMap::new(
+            revision(&["a", "x", "b"], 0),
+            revision(&["x", "a", "x", "b"], 100),
+        );
+        let relocation = map.relocate(1).unwrap();
+        assert_eq!(relocation.kind, MatchKind::Close);
+        assert_eq!(relocation.sequence, 2);
+        assert!(!relocation.content_changed);
+    }
+
+    #[test]
+    fn test_rough_match() {
+        let map = RevisionMap::new(
+            revision(&["a", "b", "c", "d", "e"], 0),
+            revision(&["v", "w", "x", "y", "z", "zz", "zzz", "zzzz", "q"], 100),
+        );
+        let relocation = map.relocate(2).unwrap();
+        assert_eq!(relocation.kind, MatchKind::Rough);
+        assert_eq!(relocation.sequence, 4);
+    }
+
+    #[test]
+    fn test_relocate_all() {
+        let map = RevisionMap::new(
+            revision(&["a", "b"], 0),
+            revision(&["b", "a"], 100),
+        );
+        let relocations: Vec<_> = map
+            .relocate_all()
+            .map(|(from, to)| (from, to.block_id))
+            .collect();
+        assert_eq!(relocations, vec![(0, 101), (1, 100)]);
+    }
+
+    #[test]
+    fn test_empty_target() {
+        let map = RevisionMap::new(revision(&["a"], 0), vec![]);
+        assert_eq!(map.relocate(0), None);
+    }
+}
@@ -0,0 +1,250 @@
+//! Sessions; instead of keeping a reader's token in their browser, we trade
+//! it for a session once, and the browser keeps a cookie naming the
+//! session. The cookie is `id.secret.mac`, where the mac is an HMAC of the
+//! rest under `$SESSION_SECRET`, so we can turn away forged cookies without
+//! asking the database. Each device gets its own session, which can be
+//! revoked without touching the reader's token.
+
+use crate::{auth::sha256_hex, bytes::Bytes, prelude::*};
+use aws_lc_rs::{hmac, rand::fill};
+use chrono::Duration;
+
+/// A session's secret is replaced once it's this old, the next time the
+/// session is used.
+pub const SESSION_ROTATION: Duration = Duration::hours(24);
+
+/// After rotating, the previous secret keeps working for this long, so that
+/// requests which were already in flight with the old cookie still work.
+pub const ROTATION_GRACE: Duration = Duration::minutes(1);
+
+/// Sessions which go unused for this long stop working, and the cookie
+/// expires at the same time.
+pub const SESSION_IDLE_LIFETIME: Duration = Duration::days(90);
+
+/// How stale a session's `last_seen_at` may get before it's updated, so that
+/// each request doesn't write to the session.
+const LAST_SEEN_RESOLUTION: Duration = Duration::minutes(5);
+
+pub const SESSION_COOKIE: &str = "session";
+
+/// `$SESSION_SECRET` must be at least this long.
+pub const MIN_SESSION_SECRET_LEN: usize = 32;
+
+/// The key session cookies are signed with.
+#[derive(Clone)]
+pub struct SessionKey(hmac::Key);
+
+impl SessionKey {
+    /// Refuses secrets shorter than [MIN_SESSION_SECRET_LEN]; anybody who
+    /// guesses the secret can forge a cookie.
+    pub fn new(secret: &str) -> Result<Self> {
+        if secret.len() < MIN_SESSION_SECRET_LEN {
+            return Err(ErrStack::new(ErrT::Config).ctx(format!(
+                "session secret must be at least {MIN_SESSION_SECRET_LEN} \
+                bytes, not {}",
+                secret.len()
+            )));
+        }
+        Ok(Self(hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())))
+    }
+}
+
+#[derive(Eq, PartialEq)]
+pub struct SessionCookie {
+    pub session_id: i32,
+    secret: String,
+}
+
+impl SessionCookie {
+    fn create(session_id: i32) -> Result<Self> {
+        Ok(Self {
+            session_id,
+            secret: random_secret()?,
+        })
+    }
+    pub fn sha256_hex(&self) -> String {
+        sha256_hex(&self.secret)
+    }
+    fn sign(&self, key: &hmac::Key) -> String {
+        let payload = format!("{}.{}", self.session_id, self.secret);
+        let mac = hmac::sign(key, payload.as_bytes());
+        format!("{payload}.{}", mac.as_ref().to_hex())
+    }
+    fn verify(value: &str, key: &hmac::Key) -> Option<Self> {
+        let (payload, mac) = value.rsplit_once('.')?;
+        let mac = from_hex(mac)?;
+        hmac::verify(key, payload.as_bytes(), &mac).ok()?;
+        let (session_id, secret) = payload.split_once('.')?;
+        Some(Self {
+            session_id: session_id.parse().ok()?,
+            secret: secret.to_string(),
+        })
+    }
+    /// The value to keep in the browser's cookie.
+    pub fn display_secret_value(&self, key: &SessionKey) -> String {
+        self.sign(&key.0)
+    }
+    /// The session cookie in `headers`, if there is one and it was signed by
+    /// us.
+    pub fn parse_from_headers(
+        headers: &HeaderMap,
+        key: &SessionKey,
+    ) -> Option<Self> {
+        let cookie = headers.get("Cookie")?.to_str().ok()?;
+        cookie.split(";").find_map(|item| {
+            let (name, val) = item.trim().split_once("=")?;
+            if name.trim() == SESSION_COOKIE {
+                Self::verify(val.trim(), &key.0)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl std::fmt::Debug for SessionCookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "SessionCookie({}, [sensitive value omitted])",
+            self.session_id
+        )
+    }
+}
+
+fn random_secret() -> Result<String> {
+    let mut buffer = [0u8; 32];
+    fill(&mut buffer).map_err(|e| {
+        ErrStack::new(ErrT::Invariant)
+            .ctx(format!("aws says no random bytes for you: {e}"))
+    })?;
+    Ok(buffer.to_hex())
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Start a session for `token_id`. `user_agent` is kept so that the reader
+/// can tell their devices apart.
+pub async fn create_session(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+    user_agent: Option<&str>,
+) -> Result<SessionCookie> {
+    let secret = random_secret()?;
+    let row = query!(
+        "insert into session (token_id, user_agent, secret_digest)
+        values ($1, $2, $3)
+        returning id",
+        token_id,
+        user_agent,
+        sha256_hex(&secret)
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "create_session"))?;
+    Ok(SessionCookie {
+        session_id: row.id,
+        secret,
+    })
+}
+
+/// Note that the session was used, and give it a new secret if the current
+/// one is due for rotation. Returns the new cookie, if there is one. This
+/// runs on nearly every request, so it only writes when there's something
+/// to change. Only the secret `cookie` presented is ever rotated, so a
+/// request which loses a race to rotate gets no new cookie.
+pub async fn touch_session(
+    db: impl PgExecutor<'_>,
+    cookie: &SessionCookie,
+) -> Result<Option<SessionCookie>> {
+    let rotated = SessionCookie::create(cookie.session_id)?;
+    let result = query!(
+        r#"with due as (
+            select
+                id,
+                rotated_at < now() - make_interval(secs => $4) rotate
+            from session
+            where
+                id = $1
+                and secret_digest = $2
+                and revoked_at is null
+                and (
+                    rotated_at < now() - make_interval(secs => $4)
+                    or last_seen_at < now() - make_interval(secs => $5)
+                )
+        )
+        update session s
+        set
+            previous_secret_digest = case
+                when due.rotate then s.secret_digest
+                else s.previous_secret_digest
+            end,
+            secret_digest = case
+                when due.rotate then $3
+                else s.secret_digest
+            end,
+            rotated_at = case when due.rotate then now() else s.rotated_at end,
+            last_seen_at = now()
+        from due
+        where
+            s.id = due.id
+            -- Checked again here, against the row as it is once locked. Of
+            -- two requests which both found the rotation due, the second
+            -- then changes nothing, and its browser keeps the cookie it has.
+            and s.secret_digest = $2
+            and s.revoked_at is null
+        returning due.rotate "rotate!""#,
+        cookie.session_id,
+        cookie.sha256_hex(),
+        rotated.sha256_hex(),
+        SESSION_ROTATION.num_seconds() as f64,
+        LAST_SEEN_RESOLUTION.num_seconds() as f64
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "touch_session"))?;
+    Ok(result.filter(|r| r.rotate).map(|_| rotated))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key() -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, b"test secret")
+    }
+
+    #[test]
+    fn test_signed_cookie_round_trips() {
+        let cookie = SessionCookie::create(42).unwrap();
+        let value = cookie.sign(&key());
+        assert!(value.starts_with("42."));
+        assert_eq!(SessionCookie::verify(&value, &key()), Some(cookie));
+    }
+
+    #[test]
+    fn test_tampered_cookie_is_rejected() {
+        let cookie = SessionCookie::create(42).unwrap();
+        let value = cookie.sign(&key());
+        let forged = value.replacen("42.", "43.", 1);
+        assert_eq!(SessionCookie::verify(&forged, &key()), None);
+        let other = hmac::Key::new(hmac::HMAC_SHA256, b"another secret");
+        assert_eq!(SessionCookie::verify(&value, &other), None);
+        assert_eq!(SessionCookie::verify("garbage", &key()), None);
+        assert_eq!(SessionCookie::verify("1.2.zz", &key()), None);
+    }
+
+    #[test]
+    fn test_short_secret_is_refused() {
+        assert!(SessionKey::new("superdupersecret").is_err());
+        assert!(SessionKey::new(&"x".repeat(MIN_SESSION_SECRET_LEN)).is_ok());
+    }
+}
@@ -0,0 +1,222 @@
+//! Suggested edits; a reader proposes replacement text for a range of
+//! characters within a block, and the admin accepts or rejects it.
+
+use crate::{
+    content::{char_slice, SourceBlock},
+    diff::TextEdit,
+    prelude::*,
+};
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionStatus {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+impl SuggestionStatus {
+    pub const ALL: [Self; 3] = [Self::Pending, Self::Accepted, Self::Rejected];
+
+    /// Identifier used in forms and query strings.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Accepted => "accepted",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+impl From<SuggestionStatus> for i32 {
+    fn from(val: SuggestionStatus) -> Self {
+        match val {
+            SuggestionStatus::Pending => 1,
+            SuggestionStatus::Accepted => 2,
+            SuggestionStatus::Rejected => 3,
+        }
+    }
+}
+
+impl TryInto<SuggestionStatus> for i32 {
+    type Error = ErrStack;
+    fn try_into(self) -> Result<SuggestionStatus> {
+        match self {
+            1 => Ok(SuggestionStatus::Pending),
+            2 => Ok(SuggestionStatus::Accepted),
+            3 => Ok(SuggestionStatus::Rejected),
+            _ => Err(ErrStack::new(ErrT::Invariant).ctx(format!(
+                "{self} is not a valid i32 for SuggestionStatus"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&str> for SuggestionStatus {
+    type Error = ErrStack;
+    fn try_from(value: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|s| s.name() == value)
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::ValidationError)
+                    .ctx(format!("{value} is not a suggestion status"))
+            })
+    }
+}
+
+/// A replacement of the characters `start..end` of a block.
+#[derive(Debug)]
+pub struct Edit<'a> {
+    pub start: usize,
+    pub end: usize,
+    pub replacement: &'a str,
+}
+
+/// Apply edits to a block's content. Edits which overlap an earlier edit are
+/// skipped; the indices of skipped edits are returned alongside the result.
+pub fn apply_edits(content: &str, edits: &[Edit]) -> (String, Vec<usize>) {
+    let mut order: Vec<usize> = (0..edits.len()).collect();
+    order.sort_by_key(|i| (edits[*i].start, *i));
+
+    let mut result = String::new();
+    let mut skipped = Vec::new();
+    let mut cursor = 0;
+    for i in order {
+        let edit = &edits[i];
+        if edit.start < cursor || edit.end < edit.start {
+            skipped.push(i);
+            continue;
+        }
+        result.push_str(char_slice(content, cursor, edit.start));
+        result.push_str(edit.replacement);
+        cursor = edit.end;
+    }
+    result.push_str(char_slice(content, cursor, content.chars().count()));
+    skipped.sort_unstable();
+    (result, skipped)
+}
+
+/// Where `edits` to `block` fall in the text it was parsed from; the byte
+/// range each edit replaces, along with its replacement. Edits should be
+/// ones which [apply_edits] did not skip.
+pub fn source_edits<'a>(
+    block: &SourceBlock,
+    edits: &[Edit<'a>],
+) -> Vec<TextEdit<'a>> {
+    let spans = &block.spans;
+    let end_of_block = spans.last().map_or(0, |span| span.end);
+    let before = |i: usize| spans.get(i).map_or(end_of_block, |s| s.start);
+    edits
+        .iter()
+        .map(|edit| {
+            let start = before(edit.start);
+            let end = if edit.end > edit.start {
+                spans.get(edit.end - 1).map_or(end_of_block, |s| s.end)
+            } else {
+                start
+            };
+            (start..end.max(start), edit.replacement)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_edits() {
+        let (result, skipped) = apply_edits(
+            "Teh cat sat on teh mat.",
+            &[
+                Edit {
+                    start: 15,
+                    end: 18,
+                    replacement: "the",
+                },
+                Edit {
+                    start: 0,
+                    end: 3,
+                    replacement: "The",
+                },
+            ],
+        );
+        assert_eq!(result, "The cat sat on the mat.");
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_apply_edits_skips_overlaps() {
+        let (result, skipped) = apply_edits(
+            "héllo wörld",
+            &[
+                Edit {
+                    start: 0,
+                    end: 5,
+                    replacement: "hello",
+                },
+                Edit {
+                    start: 4,
+                    end: 11,
+                    replacement: "o world",
+                },
+                Edit {
+                    start: 6,
+                    end: 11,
+                    replacement: "world",
+                },
+            ],
+        );
+        assert_eq!(result, "hello world");
+        assert_eq!(skipped, vec![1]);
+    }
+
+    #[test]
+    fn test_source_edits_patch_the_source() {
+        use crate::{content::Book, diff::patch_text};
+
+        let source = "% Book\n\n  Teh cat\n  sat on\n  teh mat.\n";
+        let (_, blocks) = Book::parse_source(source);
+        let edits = [
+            Edit {
+                start: 0,
+                end: 3,
+                replacement: "The",
+            },
+            // Across the line break, so the lines are joined.
+            Edit {
+                start: 6,
+                end: 9,
+                replacement: "t-s",
+            },
+            Edit {
+                start: 22,
+                end: 22,
+                replacement: " again",
+            },
+        ];
+        let (expected, skipped) = apply_edits(&blocks[0].block.content, &edits);
+        assert!(skipped.is_empty());
+
+        let patched = source_edits(&blocks[0], &edits).into_iter().rev().fold(
+            source.to_string(),
+            |mut text, (range, replacement)| {
+                text.replace_range(range, replacement);
+                text
+            },
+        );
+        assert_eq!(patched, "% Book\n\n  The cat-sat on\n  teh mat again.\n");
+        assert_eq!(
+            Book::from_raw_plain_text(&patched).blocks[0].content,
+            expected
+        );
+        assert_eq!(
+            patch_text("f", source, &source_edits(&blocks[0], &edits), 0),
+            "--- a/f\n+++ b/f\n\
+            @@ -3,3 +3,2 @@\n-  Teh cat\n-  sat on\n+  The cat-sat on\n\
+            -  teh mat.\n+  teh mat again.\n"
+        );
+    }
+}
@@ -15,6 +15,10 @@ terraform {
       source  = "hashicorp/helm"
       version = ">= 2.4.1"
     }
+    random = {
+      source  = "hashicorp/random"
+      version = ">= 3.4.3"
+    }
   }
 }
 
@@ -36,11 +40,27 @@ data "external" "git_sha" {
   ]
 }
 
+locals {
+  domain = "ides.katetell.com"
+}
+
+# Signs session cookies. It lives in the Terraform state; replacing it signs
+# every reader out.
+resource "random_password" "session_secret" {
+  length  = 64
+  special = false
+}
+
 module "basic-deployment" {
   source  = "jdevries3133/basic-deployment/kubernetes"
   version = "3.2.0"
 
   app_name  = "ides"
   container = "jdevries3133/ides:${data.external.git_sha.result.output}"
-  domain    = "ides.katetell.com"
+  domain    = local.domain
+
+  extra_env = {
+    SESSION_SECRET = random_password.session_secret.result
+    PUBLIC_URL     = "https://${local.domain}"
+  }
 }
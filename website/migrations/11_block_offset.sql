@@ -0,0 +1 @@
+alter table current_block add column block_offset int not null default 0;
@@ -0,0 +1,13 @@
+create table reading_mode(
+    id serial primary key not null,
+    name text not null
+);
+
+insert into reading_mode (name) values
+    ('paged'),
+    ('scroll')
+;
+
+alter table token
+    add column reading_mode_id int not null default 1
+    references reading_mode(id);
@@ -0,0 +1,10 @@
+create table bookmark(
+    id serial primary key not null,
+    created_at timestamp with time zone not null default now(),
+    name text not null,
+    block_offset int not null default 0,
+    relocated_roughly boolean not null default false,
+
+    block_id int not null references block(id),
+    token_id int not null references token(id)
+);
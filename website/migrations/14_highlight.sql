@@ -0,0 +1,32 @@
+create table highlight_color(
+    id serial primary key not null,
+    name text not null
+);
+
+insert into highlight_color (name) values
+    ('yellow'),
+    ('green'),
+    ('blue'),
+    ('pink')
+;
+
+create table highlight(
+    id serial primary key not null,
+    created_at timestamp with time zone not null default now(),
+    start_offset int not null,
+    end_offset int not null,
+    -- The highlighted text at the time the highlight was made. We keep it so
+    -- that highlights can be found again in new revisions of the book.
+    quote text not null,
+    note text,
+    relocated_roughly boolean not null default false,
+    -- Set if the quoted text could not be found in a new revision of the
+    -- book.
+    detached boolean not null default false,
+
+    color_id int references highlight_color(id),
+    block_id int not null references block(id),
+    token_id int not null references token(id),
+
+    check (start_offset < end_offset)
+);
@@ -0,0 +1,10 @@
+-- Comments may be about a particular span of text within their block,
+-- rather than the whole block.
+alter table comment
+    add column quote text,
+    add column quote_start int,
+    add column quote_end int,
+    add constraint comment_quote_complete check (
+        (quote is null) = (quote_start is null)
+        and (quote is null) = (quote_end is null)
+    );
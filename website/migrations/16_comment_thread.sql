@@ -0,0 +1,28 @@
+-- Comments form threads; replies point at the comment which started the
+-- thread.
+alter table comment
+    -- Null for comments written before we kept track, where we can't tell.
+    add column created_at timestamp with time zone,
+    add column parent_id int references comment(id),
+    -- For replies; when the reader who started the thread saw this reply.
+    add column seen_at timestamp with time zone;
+
+-- Existing comments were written while reading the page they're on, so the
+-- first time the reader opened that page is the best guess we have; failing
+-- that, the comment can't be older than the reader's first visit.
+update comment c
+set created_at = coalesce(
+    (
+        select min(al.created_at)
+        from access_log al
+        join block bl on bl.sequence = al.page
+        where al.token_id = c.token_id and bl.id = c.block_id
+    ),
+    (
+        select min(al.created_at)
+        from access_log al
+        where al.token_id = c.token_id
+    )
+);
+
+alter table comment alter column created_at set default now();
@@ -0,0 +1,33 @@
+create table comment_status(
+    id serial primary key not null,
+    name text not null
+);
+
+insert into comment_status (name) values
+    ('open'),
+    ('resolved'),
+    ('won''t fix')
+;
+
+alter table comment
+    add column status_id int not null default 1
+    references comment_status(id);
+
+create index block_revision_sequence on block (book_revision_id, sequence);
+
+-- The chapter which each block belongs to; the nearest heading at or before
+-- it in the same revision.
+create view block_chapter as
+select
+    b.id block_id,
+    (
+        select h.id
+        from block h
+        where
+            h.book_revision_id = b.book_revision_id
+            and h.sequence <= b.sequence
+            and h.type_id <> 1
+        order by h.sequence desc
+        limit 1
+    ) chapter_id
+from block b;
@@ -0,0 +1,15 @@
+-- Open comments follow their block into new revisions of the book. We keep
+-- the block which the comment was written against, and flag comments whose
+-- block has since been edited, since the edit may have addressed them.
+alter table comment
+    add column original_block_id int references block(id),
+    add column possibly_addressed boolean not null default false;
+
+-- If a quoted span can't be found in the new revision, we keep the quote but
+-- drop its offsets.
+alter table comment
+    drop constraint comment_quote_complete,
+    add constraint comment_quote_complete check (
+        (quote_start is null) = (quote_end is null)
+        and (quote_start is null or quote is not null)
+    );
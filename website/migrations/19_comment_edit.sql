@@ -0,0 +1,14 @@
+-- Readers may edit and delete their own comments. Each edit keeps the text
+-- it replaced, so that the admin can see what was changed. Deleting a
+-- comment records its final text as an edit, and clears it.
+create table comment_edit(
+    id serial primary key not null,
+    edited_at timestamp with time zone not null default now(),
+    -- The text of the comment before this edit.
+    comment text not null,
+    comment_id int not null references comment(id)
+);
+
+alter table comment
+    add column edited_at timestamp with time zone,
+    add column deleted_at timestamp with time zone;
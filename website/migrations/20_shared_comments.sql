@@ -0,0 +1,32 @@
+-- Readers may opt in to sharing comments with each other. To avoid spoilers,
+-- a reader only sees others' comments on blocks at or before the furthest
+-- point they've read to.
+alter table token
+    add column share_comments boolean not null default false;
+
+create table furthest_block(
+    token_id int not null references token(id),
+    block_id int not null references block(id),
+    unique (token_id),
+    primary key (token_id, block_id)
+);
+
+insert into furthest_block (token_id, block_id)
+select token_id, block_id from current_block;
+
+-- Other readers' comments which each sharing reader may see.
+create view shared_comment as
+select viewer.id viewer_id, c.id comment_id
+from token viewer
+join furthest_block fb on fb.token_id = viewer.id
+join block furthest on furthest.id = fb.block_id
+join comment c on c.token_id <> viewer.id
+join token author on author.id = c.token_id
+join block b on b.id = c.block_id
+where
+    viewer.share_comments
+    and author.share_comments
+    and c.parent_id is null
+    and c.deleted_at is null
+    and b.book_revision_id = furthest.book_revision_id
+    and b.sequence <= furthest.sequence;
@@ -0,0 +1,22 @@
+create table reaction_type(
+    id serial primary key not null,
+    name text not null
+);
+
+insert into reaction_type (name) values
+    ('love'),
+    ('funny'),
+    ('confusing'),
+    ('too slow')
+;
+
+-- Quick reactions from readers to blocks. Each reader can give each
+-- reaction to a block once; tapping it again takes it back.
+create table reaction(
+    id serial primary key not null,
+    created_at timestamp with time zone not null default now(),
+    type_id int not null references reaction_type(id),
+    block_id int not null references block(id),
+    token_id int not null references token(id),
+    unique (token_id, block_id, type_id)
+);
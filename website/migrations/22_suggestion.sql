@@ -0,0 +1,29 @@
+create table suggestion_status(
+    id serial primary key not null,
+    name text not null
+);
+
+insert into suggestion_status (name) values
+    ('pending'),
+    ('accepted'),
+    ('rejected')
+;
+
+-- A reader's proposed replacement for a range of characters within a block.
+create table suggestion(
+    id serial primary key not null,
+    created_at timestamp with time zone not null default now(),
+    start_offset int not null,
+    end_offset int not null,
+    -- The text of the range when the suggestion was made.
+    original text not null,
+    replacement text not null,
+    note text,
+    status_id int not null default 1 references suggestion_status(id),
+    reviewed_at timestamp with time zone,
+
+    block_id int not null references block(id),
+    token_id int not null references token(id),
+
+    check (start_offset <= end_offset)
+);
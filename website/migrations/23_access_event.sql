@@ -0,0 +1,44 @@
+create table access_event_type(
+    id serial primary key not null,
+    name text not null
+);
+
+insert into access_event_type (name) values
+    ('page view'),
+    ('next'),
+    ('previous'),
+    ('jump'),
+    ('comment'),
+    ('scroll')
+;
+
+-- `page` is the sequence of the reader's block, which only means something
+-- alongside the revision it was recorded in. `screen_area` is the client's
+-- `innerHeight * innerWidth`, when the request carried it.
+alter table access_log
+    add column event_type_id int not null default 1
+        references access_event_type(id),
+    add column block_id int references block(id),
+    add column revision_id int references book_revision(id),
+    add column screen_area int;
+
+alter table access_log alter column event_type_id drop default;
+
+-- Older rows have no revision, so assume the reader was reading the newest
+-- revision which existed at the time, or failing that, the current one. We
+-- can't know what kind of event they were, so they stay as page views.
+update access_log a
+set revision_id = coalesce(
+    (
+        select r.id from book_revision r
+        where r.created_at <= a.created_at
+        order by r.created_at desc
+        limit 1
+    ),
+    (select revision_id from current_revision where book_id = 1)
+);
+
+update access_log a
+set block_id = b.id
+from block b
+where b.book_revision_id = a.revision_id and b.sequence = a.page;
@@ -0,0 +1,12 @@
+-- Where each reader was before they jumped somewhere else in the book, so
+-- they can go back. Only the most recent few are kept.
+create table position_history(
+    id serial primary key not null,
+    created_at timestamp with time zone not null default now(),
+    block_offset int not null default 0,
+
+    block_id int not null references block(id),
+    token_id int not null references token(id)
+);
+
+create index on position_history (token_id, created_at);
@@ -0,0 +1,10 @@
+-- Single-use links which sign a reader in; see `ides::invitation`.
+create table invitation(
+    id serial primary key not null,
+    created_at timestamp with time zone not null default now(),
+    expires_at timestamp with time zone not null,
+    used_at timestamp with time zone,
+    code_digest text not null unique,
+
+    token_id int not null references token(id) on delete cascade
+);
@@ -0,0 +1,6 @@
+-- Revoked tokens are kept, rather than deleted, so that the reader's
+-- history, comments and reactions stay with their name. A token can also be
+-- given an expiry, after which it stops working.
+alter table token
+    add column revoked_at timestamp with time zone,
+    add column expires_at timestamp with time zone;
@@ -0,0 +1,17 @@
+-- One row per signed-in device; see `ides::session`. Only digests of the
+-- secrets are stored. The previous secret keeps working briefly after each
+-- rotation.
+create table session(
+    id serial primary key not null,
+    created_at timestamp with time zone not null default now(),
+    last_seen_at timestamp with time zone not null default now(),
+    rotated_at timestamp with time zone not null default now(),
+    revoked_at timestamp with time zone,
+    secret_digest text not null unique,
+    previous_secret_digest text,
+    user_agent text,
+
+    token_id int not null references token(id) on delete cascade
+);
+
+create index on session (token_id);
@@ -0,0 +1,36 @@
+-- Which section each block belongs to, numbered from zero within its
+-- revision. A section starts at a heading which follows a paragraph. This is
+-- worked out when the book is imported, so that finding the blocks of a page
+-- doesn't need a pass over the whole revision.
+alter table block add column section int;
+
+with flagged as (
+    select
+        id,
+        book_revision_id,
+        sequence,
+        case
+            when type_id <> 1
+                and lag(type_id) over (
+                    partition by book_revision_id order by sequence
+                ) = 1
+            then 1
+            else 0
+        end section_break
+    from block
+), sectioned as (
+    select
+        id,
+        sum(section_break) over (
+            partition by book_revision_id order by sequence
+        ) section
+    from flagged
+)
+update block
+set section = sectioned.section
+from sectioned
+where block.id = sectioned.id;
+
+alter table block alter column section set not null;
+
+create index block_revision_section on block (book_revision_id, section);
@@ -0,0 +1,4 @@
+-- The text the author imported for each revision, so that accepted
+-- suggestions can be turned back into a patch against the manuscript.
+-- Revisions imported before this was kept have none.
+alter table book_revision add column source text;
@@ -32,12 +32,12 @@ impl Component for About {
             </p>
             <p>
                 When you're reading the book, use the toolbar at the bottom to
-                change your page. If you click on any paragraph, a dialog will
-                appear for you to share feedback with me or take notes! Swiping
-                to change pages is not supported. Sometimes, the page of text
-                may extend past the bottom of the screen, requiring a bit of
-                horizonal scrolling. Ideally this wouldn't happen, but the code
-                to word count for a given screen size is a bit janky.
+                change your page. If you tap the &#128172; button beside any
+                paragraph, a dialog will appear for you to share feedback with
+                me or take notes! Swiping
+                to change pages is not supported. Long paragraphs are split
+                across pages to fit your screen, and continue at the top of the
+                next page.
             </p>
             <p>
                 For any technical questions about this site, you can reach out
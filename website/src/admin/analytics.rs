@@ -0,0 +1,307 @@
+//! How far readers have gotten, and how they read; built from the access
+//! log, which records the block a reader is on each time they turn a page,
+//! and from the furthest point each reader has read to. Since block
+//! sequences differ between revisions, how far a reader got is measured as a
+//! percentage through whichever revision that point is in.
+
+use super::nav::{nav_helper, AdminNav};
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use ides::{
+    analytics::{funnel, AccessEventType, SESSION_GAP},
+    auth::Role,
+    content::{sequence_at_percent, Progress},
+};
+
+struct Chapter {
+    sequence: i32,
+    content: String,
+}
+
+/// One reader's visits, summarized by the database.
+struct ReaderRow {
+    name: String,
+    /// Sequence of the furthest block the reader has read to, in its own
+    /// revision.
+    furthest_sequence: Option<i32>,
+    /// Size of the revision holding the furthest block.
+    furthest_block_count: Option<i64>,
+    last_read: Option<DateTime<Utc>>,
+    session_count: i64,
+    average_session_minutes: i64,
+    pages_per_day: f64,
+}
+
+struct ReaderStats {
+    name: String,
+    /// Sequence in the current revision at the same percentage as the
+    /// furthest point the reader reached in any revision.
+    furthest: Option<i32>,
+    percent: f64,
+    last_read: Option<DateTime<Utc>>,
+    session_count: i64,
+    average_session_minutes: i64,
+    pages_per_day: f64,
+}
+
+pub async fn analytics(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            struct Revision {
+                revision_id: i32,
+            }
+            let reader_role_id: i32 = Role::Reader.into();
+            let page_views: Vec<i32> =
+                AccessEventType::PAGE_VIEWS.map(i32::from).to_vec();
+            let readers = query_as!(
+                ReaderRow,
+                r#"with visit as (
+                    select
+                        a.token_id,
+                        a.created_at,
+                        coalesce(
+                            a.created_at - lag(a.created_at) over w
+                                > make_interval(secs => $3),
+                            true
+                        ) starts_session
+                    from access_log a
+                    join token t on t.id = a.token_id
+                    where t.role_id = $1 and a.event_type_id = any($2)
+                    window w as (partition by a.token_id order by a.created_at)
+                ),
+                numbered_visit as (
+                    select
+                        token_id,
+                        created_at,
+                        count(*) filter (where starts_session) over (
+                            partition by token_id order by created_at
+                        ) session
+                    from visit
+                ),
+                reading_session as (
+                    select token_id, max(created_at) - min(created_at) duration
+                    from numbered_visit
+                    group by token_id, session
+                ),
+                session_stats as (
+                    select
+                        token_id,
+                        count(*) session_count,
+                        avg(extract(epoch from duration)) average_seconds
+                    from reading_session
+                    group by token_id
+                ),
+                visit_stats as (
+                    select
+                        token_id,
+                        max(created_at) last_read,
+                        count(*)::float8 / count(distinct (
+                            created_at at time zone $4
+                        )::date) pages_per_day
+                    from visit
+                    group by token_id
+                ),
+                revision_size as (
+                    select book_revision_id, count(*) block_count
+                    from block
+                    group by book_revision_id
+                )
+                select
+                    t.name,
+                    f.sequence "furthest_sequence?",
+                    r.block_count "furthest_block_count?",
+                    vs.last_read "last_read?",
+                    coalesce(ss.session_count, 0) "session_count!",
+                    coalesce(
+                        floor(ss.average_seconds / 60), 0
+                    )::int8 "average_session_minutes!",
+                    coalesce(vs.pages_per_day, 0) "pages_per_day!"
+                from token t
+                left join visit_stats vs on vs.token_id = t.id
+                left join session_stats ss on ss.token_id = t.id
+                left join furthest_block fb on fb.token_id = t.id
+                left join block f on f.id = fb.block_id
+                left join revision_size r
+                    on r.book_revision_id = f.book_revision_id
+                where t.role_id = $1
+                order by t.name"#,
+                reader_role_id,
+                &page_views,
+                SESSION_GAP.num_seconds() as f64,
+                Tz::America__New_York.name()
+            )
+            .fetch_all(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "analytics: readers"))?;
+            let Revision { revision_id } = query_as!(
+                Revision,
+                "select revision_id from current_revision where book_id = 1"
+            )
+            .fetch_one(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "analytics: revision"))?;
+            let chapters = query_as!(
+                Chapter,
+                "select sequence, content from block
+                where book_revision_id = $1 and type_id <> 1
+                order by sequence",
+                revision_id
+            )
+            .fetch_all(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "analytics: chapters"))?;
+            let Progress { block_count, .. } =
+                Progress::get(&db, revision_id, 0).await?;
+
+            let stats: Vec<ReaderStats> = readers
+                .into_iter()
+                .map(|reader| {
+                    let percent = reader
+                        .furthest_sequence
+                        .zip(reader.furthest_block_count)
+                        .map(|(sequence, block_count)| {
+                            Progress {
+                                sequence,
+                                block_count,
+                            }
+                            .percent()
+                        });
+                    ReaderStats {
+                        name: reader.name,
+                        furthest: percent
+                            .map(|p| sequence_at_percent(block_count, p)),
+                        percent: percent.unwrap_or(0.0),
+                        last_read: reader.last_read,
+                        session_count: reader.session_count,
+                        average_session_minutes: reader.average_session_minutes,
+                        pages_per_day: reader.pages_per_day,
+                    }
+                })
+                .collect();
+
+            Ok(Page {
+                title: "Reader Analytics",
+                children: &PageContainer {
+                    children: &AnalyticsPage {
+                        stats: &stats,
+                        chapters: &chapters,
+                    },
+                },
+            }
+            .render()
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+/// The chapter containing `sequence`.
+fn chapter_at(chapters: &[Chapter], sequence: i32) -> Option<&Chapter> {
+    chapters.iter().rev().find(|c| c.sequence <= sequence)
+}
+
+struct AnalyticsPage<'a> {
+    stats: &'a [ReaderStats],
+    chapters: &'a [Chapter],
+}
+impl Component for AnalyticsPage<'_> {
+    fn render(&self) -> String {
+        let home = Route::AdminHome;
+        let rows = self.stats.iter().fold(String::new(), |mut acc, s| {
+            let name = clean(&s.name);
+            let chapter = match s.furthest {
+                Some(sequence) => clean(
+                    chapter_at(self.chapters, sequence)
+                        .map(|c| c.content.as_str())
+                        .unwrap_or("Before the first chapter"),
+                ),
+                None => "not started".into(),
+            };
+            let percent = s.percent.round();
+            let last_read = s
+                .last_read
+                .map(|t| {
+                    t.with_timezone(&Tz::America__New_York)
+                        .format("%b %d, %Y %l:%M %p")
+                        .to_string()
+                })
+                .unwrap_or_else(|| "never".into());
+            let sessions = s.session_count;
+            let minutes = s.average_session_minutes;
+            let pages_per_day = format!("{:.1}", s.pages_per_day);
+            acc.push_str(&format!(
+                r#"
+                <tr>
+                    <td class="p-1">{name}</td>
+                    <td class="p-1">{chapter}</td>
+                    <td class="p-1 text-right">{percent}%</td>
+                    <td class="p-1">{last_read}</td>
+                    <td class="p-1 text-right">{sessions} (~{minutes} min)</td>
+                    <td class="p-1 text-right">{pages_per_day}</td>
+                </tr>
+                "#
+            ));
+            acc
+        });
+        let reader_count = self.stats.len();
+        let starts: Vec<i32> =
+            self.chapters.iter().map(|c| c.sequence).collect();
+        let furthest: Vec<i32> =
+            self.stats.iter().filter_map(|s| s.furthest).collect();
+        let funnel = self.chapters.iter().zip(funnel(&starts, &furthest)).fold(
+            String::new(),
+            |mut acc, (chapter, reached)| {
+                let title = clean(&chapter.content);
+                acc.push_str(&format!(
+                    r#"
+                    <div class="flex items-center gap-2">
+                        <p class="w-48 truncate">{title}</p>
+                        <progress
+                            class="flex-grow accent-yellow-500"
+                            max="{reader_count}"
+                            value="{reached}"
+                        ></progress>
+                        <p class="w-16 text-right">{reached} / {reader_count}</p>
+                    </div>
+                    "#
+                ));
+                acc
+            },
+        );
+        format!(
+            r#"
+            <div class="flex flex-col gap-4">
+                <a class="link" href="{home}">Admin Home</a>
+                <h1 class="text-xl">Reader Analytics</h1>
+                <p class="text-sm max-w-prose">
+                    A session ends after {gap} minutes without turning a page.
+                    Pages per day counts only days when the reader read.
+                </p>
+                <table class="table-auto text-left">
+                    <thead>
+                        <tr>
+                            <th class="p-1">Reader</th>
+                            <th class="p-1">Furthest chapter</th>
+                            <th class="p-1">Complete</th>
+                            <th class="p-1">Last read</th>
+                            <th class="p-1">Sessions</th>
+                            <th class="p-1">Pages / day</th>
+                        </tr>
+                    </thead>
+                    <tbody>{rows}</tbody>
+                </table>
+                <h2 class="text-lg">Readers reaching each chapter</h2>
+                <div class="flex flex-col gap-1 max-w-prose">{funnel}</div>
+            </div>
+            "#,
+            gap = SESSION_GAP.num_minutes()
+        )
+    }
+}
@@ -0,0 +1,339 @@
+//! The book as the author sees it; one section of the current revision at a
+//! time, with each block tinted by how long readers lingered on it, and
+//! annotated with its reactions and comments.
+
+use super::nav::{nav_helper, AdminNav};
+use crate::prelude::*;
+use chrono::Duration;
+use ides::{
+    analytics::{AccessEventType, IDLE_GAP, MAX_PAGE_BLOCKS},
+    auth::Role,
+    content::{BlockType, Progress, Section, SequencedBlock},
+    reaction::{summarize, tally},
+};
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+pub struct AuthorViewParams {
+    /// Show the section containing this block; defaults to the first.
+    sequence: Option<i32>,
+}
+
+pub async fn author_view(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    Query(params): Query<AuthorViewParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            struct Revision {
+                revision_id: i32,
+            }
+            let Revision { revision_id } = query_as!(
+                Revision,
+                "select revision_id from current_revision where book_id = 1"
+            )
+            .fetch_one(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "author_view: revision"))?;
+            let Progress { block_count, .. } =
+                Progress::get(&db, revision_id, 0).await?;
+            let sequence = params
+                .sequence
+                .unwrap_or(0)
+                .clamp(0, (block_count - 1).max(0) as i32);
+            let section = Section::get(&db, revision_id, sequence).await?;
+            let dwell = median_dwell(&db, revision_id).await?;
+            let block_ids: Vec<i32> =
+                section.blocks.iter().map(|b| b.id).collect();
+
+            struct ReactionCount {
+                block_id: i32,
+                type_id: i32,
+                count: i64,
+            }
+            let reaction_counts = query_as!(
+                ReactionCount,
+                r#"select block_id, type_id, count(*) "count!"
+                from reaction
+                where block_id = any($1)
+                group by block_id, type_id"#,
+                &block_ids
+            )
+            .fetch_all(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "author_view: reactions"))?;
+            let mut reactions: HashMap<i32, Vec<(i32, i64)>> = HashMap::new();
+            for r in reaction_counts {
+                reactions
+                    .entry(r.block_id)
+                    .or_default()
+                    .push((r.type_id, r.count));
+            }
+
+            struct CommentCount {
+                block_id: i32,
+                count: i64,
+            }
+            let comments: HashMap<i32, i64> = query_as!(
+                CommentCount,
+                r#"select block_id, count(*) "count!"
+                from comment
+                where
+                    block_id = any($1)
+                    and parent_id is null
+                    and deleted_at is null
+                group by block_id"#,
+                &block_ids
+            )
+            .fetch_all(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "author_view: comments"))?
+            .into_iter()
+            .map(|c| (c.block_id, c.count))
+            .collect();
+
+            Ok(Page {
+                title: "Author View",
+                children: &PageContainer {
+                    children: &AuthorView {
+                        blocks: &section.blocks,
+                        block_count,
+                        max_dwell: dwell.values().max().copied(),
+                        dwell: &dwell,
+                        reactions: &reactions,
+                        comments: &comments,
+                    },
+                },
+            }
+            .render()
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+/// For each block sequence of the revision, the median over readers of the
+/// total time each spent on it, among readers who spent any. The time until
+/// a reader's next page view is spent on the page which was shown; it's
+/// shared evenly among the blocks up to where the next page began, or all
+/// spent on the first block if the reader went somewhere else. Gaps longer
+/// than [IDLE_GAP] are ignored.
+async fn median_dwell(
+    db: impl PgExecutor<'_>,
+    revision_id: i32,
+) -> Result<HashMap<i32, Duration>> {
+    struct Qres {
+        sequence: i32,
+        median_ms: f64,
+    }
+    let reader_role_id: i32 = Role::Reader.into();
+    let page_views: Vec<i32> =
+        AccessEventType::PAGE_VIEWS.map(i32::from).to_vec();
+    let rows = query_as!(
+        Qres,
+        r#"with event as (
+            select
+                a.token_id,
+                a.page,
+                lead(a.created_at) over w - a.created_at gap,
+                lead(a.page) over w - a.page ahead
+            from access_log a
+            join token t on t.id = a.token_id
+            where
+                t.role_id = $1
+                and a.revision_id = $2
+                and a.event_type_id = any($3)
+            window w as (partition by a.token_id order by a.created_at)
+        ),
+        stay as (
+            select
+                token_id,
+                page,
+                extract(epoch from gap) * 1000 ms,
+                case when ahead between 1 and $5 then ahead else 1 end blocks
+            from event
+            where gap > interval '0' and gap <= make_interval(secs => $4)
+        ),
+        dwell as (
+            select s.token_id, sequence, sum(s.ms / s.blocks) ms
+            from stay s
+            cross join generate_series(s.page, s.page + s.blocks - 1) sequence
+            group by s.token_id, sequence
+        )
+        select
+            sequence "sequence!",
+            percentile_disc(0.5) within group (order by ms)::float8 "median_ms!"
+        from dwell
+        group by sequence"#,
+        reader_role_id,
+        revision_id,
+        &page_views,
+        IDLE_GAP.num_seconds() as f64,
+        MAX_PAGE_BLOCKS
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "median_dwell"))?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.sequence, Duration::milliseconds(r.median_ms as i64)))
+        .collect())
+}
+
+struct AuthorView<'a> {
+    blocks: &'a [SequencedBlock],
+    block_count: i64,
+    dwell: &'a HashMap<i32, Duration>,
+    /// The longest median dwell anywhere in the revision, which gets the
+    /// strongest tint.
+    max_dwell: Option<Duration>,
+    reactions: &'a HashMap<i32, Vec<(i32, i64)>>,
+    comments: &'a HashMap<i32, i64>,
+}
+impl Component for AuthorView<'_> {
+    fn render(&self) -> String {
+        let home = Route::AdminHome;
+        let route = Route::AdminAuthorView;
+        let (Some(first), Some(last)) =
+            (self.blocks.first(), self.blocks.last())
+        else {
+            return format!(
+                r#"
+                <a class="link" href="{home}">Admin Home</a>
+                <p class="italic">The current revision is empty.</p>
+                "#
+            );
+        };
+        let prev = if first.sequence > 0 {
+            format!(
+                r#"<a class="link" href="{route}?sequence={}">previous section</a>"#,
+                first.sequence - 1
+            )
+        } else {
+            String::new()
+        };
+        let next = if i64::from(last.sequence + 1) < self.block_count {
+            format!(
+                r#"<a class="link" href="{route}?sequence={}">next section</a>"#,
+                last.sequence + 1
+            )
+        } else {
+            String::new()
+        };
+        let engagement = |block_id: i32| -> i64 {
+            let reactions: i64 = self
+                .reactions
+                .get(&block_id)
+                .map(|r| r.iter().map(|(_, count)| count).sum())
+                .unwrap_or(0);
+            reactions + self.comments.get(&block_id).copied().unwrap_or(0)
+        };
+        let max_engagement = self
+            .blocks
+            .iter()
+            .map(|b| engagement(b.id))
+            .max()
+            .unwrap_or(0);
+        let blocks = self.blocks.iter().fold(String::new(), |mut acc, b| {
+            acc.push_str(
+                &AuthorBlock {
+                    block: b,
+                    dwell: self.dwell.get(&b.sequence).copied(),
+                    max_dwell: self.max_dwell,
+                    reactions: self
+                        .reactions
+                        .get(&b.id)
+                        .map(|r| summarize(&tally(r.iter().copied())))
+                        .unwrap_or_default(),
+                    comments: self.comments.get(&b.id).copied().unwrap_or(0),
+                    engagement: engagement(b.id),
+                    max_engagement,
+                }
+                .render(),
+            );
+            acc
+        });
+        format!(
+            r#"
+            <div class="flex flex-col gap-4">
+                <a class="link" href="{home}">Admin Home</a>
+                <h1 class="text-xl">Author View</h1>
+                <p class="text-sm max-w-prose">
+                    The stronger a block's tint, the longer readers lingered
+                    on it; the median of each reader's total time on the
+                    block. Idle time over {idle} minutes isn't counted. The
+                    stronger the bar beside a block, the more reactions and
+                    comments it drew, compared to the rest of this section.
+                </p>
+                <div class="flex gap-4">{prev}{next}</div>
+                <div class="prose dark:text-slate-200">{blocks}</div>
+                <div class="flex gap-4">{prev}{next}</div>
+            </div>
+            "#,
+            idle = IDLE_GAP.num_minutes()
+        )
+    }
+}
+
+struct AuthorBlock<'a> {
+    block: &'a SequencedBlock,
+    dwell: Option<Duration>,
+    max_dwell: Option<Duration>,
+    reactions: String,
+    comments: i64,
+    /// Reactions plus comments, against the most of any block shown.
+    engagement: i64,
+    max_engagement: i64,
+}
+impl Component for AuthorBlock<'_> {
+    fn render(&self) -> String {
+        let text = clean(&self.block.block.content);
+        let content = match self.block.block.r#type {
+            BlockType::SectionTitle => {
+                format!(r#"<h1 class="text-yellow-400">{text}</h1>"#)
+            }
+            BlockType::H1 => {
+                format!(r#"<h2 class="extra-bold text-yellow-400">{text}</h2>"#)
+            }
+            BlockType::Paragraph => format!("<p>{text}</p>"),
+        };
+        let (tint, dwell) = match (self.dwell, self.max_dwell) {
+            (Some(dwell), Some(max)) if max > Duration::zero() => {
+                let alpha = 0.6 * dwell.num_milliseconds() as f64
+                    / max.num_milliseconds() as f64;
+                (
+                    format!("background-color: rgb(234 179 8 / {alpha:.2});"),
+                    format!("{}s", dwell.num_seconds()),
+                )
+            }
+            _ => (String::new(), String::new()),
+        };
+        let border = if self.max_engagement > 0 {
+            let alpha = self.engagement as f64 / self.max_engagement as f64;
+            format!("border-left: 4px solid rgb(59 130 246 / {alpha:.2});")
+        } else {
+            String::new()
+        };
+        let reactions = &self.reactions;
+        let comments = match self.comments {
+            0 => String::new(),
+            count => format!("&#128172; {count}"),
+        };
+        format!(
+            r#"
+            <div class="rounded px-2" style="{tint}{border}">
+                <p class="not-prose float-right ml-2 text-xs flex gap-2">
+                    <span title="median dwell time">{dwell}</span>
+                    <span>{reactions}</span>
+                    <span>{comments}</span>
+                </p>
+                {content}
+            </div>
+            "#
+        )
+    }
+}
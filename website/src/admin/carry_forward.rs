@@ -0,0 +1,576 @@
+//! When the admin changes the current revision, everything which points at a
+//! block of another revision is carried forward into the new one, using the
+//! checksum mapping in [ides::revision].
+//!
+//! This runs inside the transaction which changes the current revision, so
+//! that a failure leaves the book on the old revision with nothing moved,
+//! and the change can simply be tried again. Each kind of row is moved with a
+//! single statement, by joining against every relocation at once.
+
+use crate::prelude::*;
+use ides::{
+    comment::CommentStatus,
+    highlight::{find_quote, QuoteMatch},
+    revision::{list_checksums, BlockChecksum, MatchKind, RevisionMap},
+    suggestion::SuggestionStatus,
+};
+use sqlx::PgConnection;
+use std::collections::HashMap;
+
+/// How many blocks to either side of where a block landed we'll look for a
+/// quote which isn't in the block itself.
+const NEARBY_BLOCKS: usize = 2;
+
+/// Where each block of the revisions we're leaving lands in the new one, as
+/// columns to be `unnest`-ed in the queries below.
+#[derive(Default)]
+struct Relocations {
+    from_block_ids: Vec<i32>,
+    to_block_ids: Vec<i32>,
+    content_changed: Vec<bool>,
+    rough: Vec<bool>,
+}
+
+impl Relocations {
+    fn extend(&mut self, map: &RevisionMap) {
+        for (from_block_id, relocation) in map.relocate_all() {
+            self.from_block_ids.push(from_block_id);
+            self.to_block_ids.push(relocation.block_id);
+            self.content_changed.push(relocation.content_changed);
+            self.rough.push(relocation.kind == MatchKind::Rough);
+        }
+    }
+}
+
+/// The blocks of the revision we're moving to, for finding quotes in them.
+struct TargetBlocks {
+    blocks: Vec<BlockChecksum>,
+    /// Block id => index into `blocks`.
+    index: HashMap<i32, usize>,
+}
+
+impl TargetBlocks {
+    fn new(blocks: Vec<BlockChecksum>) -> Self {
+        let index = blocks.iter().enumerate().map(|(i, b)| (b.id, i)).collect();
+        Self { blocks, index }
+    }
+    /// Find `quote` in the block `block_id`, or word for word in a block
+    /// nearby, in case the mapping landed a block or two off. We settle for
+    /// an approximate match in `block_id` only if no block nearby has the
+    /// quote word for word. Returns the block the quote was found in.
+    fn find(
+        &self,
+        block_id: i32,
+        quote: &str,
+        hint: usize,
+    ) -> Option<(i32, QuoteMatch)> {
+        let i = *self.index.get(&block_id)?;
+        let here = find_quote(&self.blocks[i].content, quote, hint);
+        if here.as_ref().is_some_and(|m| m.exact) {
+            return here.map(|m| (block_id, m));
+        }
+        for distance in 1..=NEARBY_BLOCKS {
+            for j in [i.checked_sub(distance), Some(i + distance)] {
+                let Some(block) = j.and_then(|j| self.blocks.get(j)) else {
+                    continue;
+                };
+                if let Some(m) =
+                    find_quote(&block.content, quote, hint).filter(|m| m.exact)
+                {
+                    return Some((block.id, m));
+                }
+            }
+        }
+        here.map(|m| (block_id, m))
+    }
+}
+
+pub async fn carry_forward(
+    db: &mut PgConnection,
+    to_revision_id: i32,
+) -> Result<()> {
+    struct Qres {
+        book_revision_id: i32,
+    }
+    let open_status_id: i32 = CommentStatus::Open.into();
+    let rejected_status_id: i32 = SuggestionStatus::Rejected.into();
+    let from_revisions = query_as!(
+        Qres,
+        "select distinct bl.book_revision_id
+        from block bl
+        where
+            bl.book_revision_id <> $1
+            and bl.id in (
+                select block_id from current_block
+                union select block_id from furthest_block
+                union select block_id from position_history
+                union select block_id from bookmark
+                union select block_id from highlight
+                union select block_id from reaction
+                union select block_id from comment
+                    where parent_id is null and status_id = $2
+                union select block_id from suggestion where status_id <> $3
+            )",
+        to_revision_id,
+        open_status_id,
+        rejected_status_id
+    )
+    .fetch_all(&mut *db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward: list revisions"))?;
+
+    let mut relocations = Relocations::default();
+    for Qres {
+        book_revision_id: from_revision_id,
+    } in from_revisions
+    {
+        let map = RevisionMap::load(&mut *db, from_revision_id, to_revision_id)
+            .await
+            .map_err(|e| {
+                e.wrap(ErrT::AdminBook)
+                    .ctx(format!("carry_forward from {from_revision_id}"))
+            })?;
+        relocations.extend(&map);
+    }
+    if relocations.from_block_ids.is_empty() {
+        return Ok(());
+    }
+    let target =
+        TargetBlocks::new(list_checksums(&mut *db, to_revision_id).await?);
+    carry_forward_positions(&mut *db, &relocations).await?;
+    carry_forward_furthest(&mut *db, &relocations).await?;
+    carry_forward_position_history(&mut *db, &relocations).await?;
+    carry_forward_bookmarks(&mut *db, &relocations).await?;
+    carry_forward_highlights(&mut *db, &relocations, &target).await?;
+    carry_forward_comments(&mut *db, &relocations, &target).await?;
+    carry_forward_reactions(&mut *db, &relocations).await?;
+    carry_forward_suggestions(&mut *db, &relocations, &target).await?;
+    Ok(())
+}
+
+/// Each reader's place in the book, as described under "Page Update
+/// Algorithm" in the pagination module.
+async fn carry_forward_positions(
+    db: &mut PgConnection,
+    rl: &Relocations,
+) -> Result<()> {
+    query!(
+        "update current_block cb
+        set
+            block_id = rl.to_block_id,
+            block_offset =
+                case when rl.content_changed then 0 else cb.block_offset end
+        from unnest($1::int[], $2::int[], $3::bool[])
+            as rl(from_block_id, to_block_id, content_changed)
+        where cb.block_id = rl.from_block_id",
+        &rl.from_block_ids,
+        &rl.to_block_ids,
+        &rl.content_changed
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_positions"))?;
+    Ok(())
+}
+
+/// Where readers were before their recent jumps, so that they can still go
+/// back after the revision changes.
+async fn carry_forward_position_history(
+    db: &mut PgConnection,
+    rl: &Relocations,
+) -> Result<()> {
+    query!(
+        "update position_history ph
+        set
+            block_id = rl.to_block_id,
+            block_offset =
+                case when rl.content_changed then 0 else ph.block_offset end
+        from unnest($1::int[], $2::int[], $3::bool[])
+            as rl(from_block_id, to_block_id, content_changed)
+        where ph.block_id = rl.from_block_id",
+        &rl.from_block_ids,
+        &rl.to_block_ids,
+        &rl.content_changed
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_position_history"))?;
+    Ok(())
+}
+
+/// The furthest point each reader has read to, which decides which shared
+/// comments they can see.
+async fn carry_forward_furthest(
+    db: &mut PgConnection,
+    rl: &Relocations,
+) -> Result<()> {
+    query!(
+        "update furthest_block fb
+        set block_id = rl.to_block_id
+        from unnest($1::int[], $2::int[])
+            as rl(from_block_id, to_block_id)
+        where fb.block_id = rl.from_block_id",
+        &rl.from_block_ids,
+        &rl.to_block_ids
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_furthest"))?;
+    Ok(())
+}
+
+async fn carry_forward_bookmarks(
+    db: &mut PgConnection,
+    rl: &Relocations,
+) -> Result<()> {
+    query!(
+        "update bookmark bm
+        set
+            block_id = rl.to_block_id,
+            block_offset =
+                case when rl.content_changed then 0 else bm.block_offset end,
+            relocated_roughly = bm.relocated_roughly or rl.rough
+        from unnest($1::int[], $2::int[], $3::bool[], $4::bool[])
+            as rl(from_block_id, to_block_id, content_changed, rough)
+        where bm.block_id = rl.from_block_id",
+        &rl.from_block_ids,
+        &rl.to_block_ids,
+        &rl.content_changed,
+        &rl.rough
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_bookmarks"))?;
+    Ok(())
+}
+
+/// Highlights follow their block into the new revision. If the block was
+/// edited, we search it and its neighbours for the highlighted quote; if
+/// it's gone, the highlight is detached, and will no longer be drawn in the
+/// book.
+async fn carry_forward_highlights(
+    db: &mut PgConnection,
+    rl: &Relocations,
+    target: &TargetBlocks,
+) -> Result<()> {
+    struct Qres {
+        id: i32,
+        start_offset: i32,
+        end_offset: i32,
+        quote: String,
+        to_block_id: i32,
+        content_changed: bool,
+        rough: bool,
+    }
+    let highlights = query_as!(
+        Qres,
+        r#"select
+            h.id,
+            h.start_offset,
+            h.end_offset,
+            h.quote,
+            rl.to_block_id "to_block_id!",
+            rl.content_changed "content_changed!",
+            rl.rough "rough!"
+        from highlight h
+        join unnest($1::int[], $2::int[], $3::bool[], $4::bool[])
+            as rl(from_block_id, to_block_id, content_changed, rough)
+            on rl.from_block_id = h.block_id"#,
+        &rl.from_block_ids,
+        &rl.to_block_ids,
+        &rl.content_changed,
+        &rl.rough
+    )
+    .fetch_all(&mut *db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_highlights: select"))?;
+
+    let mut ids = Vec::with_capacity(highlights.len());
+    let mut block_ids = Vec::with_capacity(highlights.len());
+    let mut starts = Vec::with_capacity(highlights.len());
+    let mut ends = Vec::with_capacity(highlights.len());
+    let mut roughs = Vec::with_capacity(highlights.len());
+    let mut detacheds = Vec::with_capacity(highlights.len());
+    for highlight in highlights {
+        let (block_id, start, end, rough, detached) =
+            if highlight.content_changed {
+                match target.find(
+                    highlight.to_block_id,
+                    &highlight.quote,
+                    highlight.start_offset as usize,
+                ) {
+                    Some((block_id, m)) => (
+                        block_id,
+                        m.start as i32,
+                        m.end as i32,
+                        !m.exact || highlight.rough,
+                        false,
+                    ),
+                    None => (
+                        highlight.to_block_id,
+                        highlight.start_offset,
+                        highlight.end_offset,
+                        true,
+                        true,
+                    ),
+                }
+            } else {
+                (
+                    highlight.to_block_id,
+                    highlight.start_offset,
+                    highlight.end_offset,
+                    highlight.rough,
+                    false,
+                )
+            };
+        ids.push(highlight.id);
+        block_ids.push(block_id);
+        starts.push(start);
+        ends.push(end);
+        roughs.push(rough);
+        detacheds.push(detached);
+    }
+    query!(
+        "update highlight h
+        set
+            block_id = u.block_id,
+            start_offset = u.start_offset,
+            end_offset = u.end_offset,
+            relocated_roughly = h.relocated_roughly or u.rough,
+            detached = h.detached or u.detached
+        from unnest(
+            $1::int[], $2::int[], $3::int[], $4::int[], $5::bool[], $6::bool[]
+        ) as u(id, block_id, start_offset, end_offset, rough, detached)
+        where h.id = u.id",
+        &ids,
+        &block_ids,
+        &starts,
+        &ends,
+        &roughs,
+        &detacheds
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_highlights: update"))?;
+    Ok(())
+}
+
+/// Open comments, with their replies, follow their block into the new
+/// revision; resolved comments stay with the text they were about. If the
+/// block was edited, the comment is flagged as possibly addressed, and any
+/// quoted span is searched for as with highlights.
+async fn carry_forward_comments(
+    db: &mut PgConnection,
+    rl: &Relocations,
+    target: &TargetBlocks,
+) -> Result<()> {
+    struct Qres {
+        id: i32,
+        quote: Option<String>,
+        quote_start: Option<i32>,
+        quote_end: Option<i32>,
+        to_block_id: i32,
+        content_changed: bool,
+    }
+    let open_status_id: i32 = CommentStatus::Open.into();
+    let comments = query_as!(
+        Qres,
+        r#"select
+            c.id,
+            c.quote,
+            c.quote_start,
+            c.quote_end,
+            rl.to_block_id "to_block_id!",
+            rl.content_changed "content_changed!"
+        from comment c
+        join unnest($1::int[], $2::int[], $3::bool[])
+            as rl(from_block_id, to_block_id, content_changed)
+            on rl.from_block_id = c.block_id
+        where c.parent_id is null and c.status_id = $4"#,
+        &rl.from_block_ids,
+        &rl.to_block_ids,
+        &rl.content_changed,
+        open_status_id
+    )
+    .fetch_all(&mut *db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_comments: select"))?;
+
+    let mut ids = Vec::with_capacity(comments.len());
+    let mut block_ids = Vec::with_capacity(comments.len());
+    let mut changed = Vec::with_capacity(comments.len());
+    let mut quote_starts = Vec::with_capacity(comments.len());
+    let mut quote_ends = Vec::with_capacity(comments.len());
+    for comment in comments {
+        let (block_id, quote_start, quote_end) = match (
+            comment.content_changed,
+            &comment.quote,
+            comment.quote_start,
+        ) {
+            (true, Some(quote), Some(start)) => {
+                match target.find(comment.to_block_id, quote, start as usize) {
+                    Some((block_id, m)) => {
+                        (block_id, Some(m.start as i32), Some(m.end as i32))
+                    }
+                    None => (comment.to_block_id, None, None),
+                }
+            }
+            _ => (comment.to_block_id, comment.quote_start, comment.quote_end),
+        };
+        ids.push(comment.id);
+        block_ids.push(block_id);
+        changed.push(comment.content_changed);
+        quote_starts.push(quote_start);
+        quote_ends.push(quote_end);
+    }
+    query!(
+        "update comment c
+        set
+            original_block_id = coalesce(c.original_block_id, c.block_id),
+            block_id = u.block_id,
+            possibly_addressed = c.possibly_addressed or u.content_changed,
+            quote_start = case when c.parent_id is null then u.quote_start end,
+            quote_end = case when c.parent_id is null then u.quote_end end
+        from unnest($1::int[], $2::int[], $3::bool[], $4::int[], $5::int[])
+            as u(id, block_id, content_changed, quote_start, quote_end)
+        where c.id = u.id or c.parent_id = u.id",
+        &ids,
+        &block_ids,
+        &changed,
+        &quote_starts as &[Option<i32>],
+        &quote_ends as &[Option<i32>]
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_comments: update"))?;
+    Ok(())
+}
+
+/// Reactions follow their block only if its text is unchanged; a reaction to
+/// a passage which has since been rewritten stays with the old revision. A
+/// reader can only react to a block once in each way, so where two of their
+/// reactions would land on the same block, only one of them moves.
+async fn carry_forward_reactions(
+    db: &mut PgConnection,
+    rl: &Relocations,
+) -> Result<()> {
+    query!(
+        "update reaction r
+        set block_id = moving.to_block_id
+        from (
+            select distinct on (r.token_id, rl.to_block_id, r.type_id)
+                r.id, rl.to_block_id
+            from reaction r
+            join unnest($1::int[], $2::int[], $3::bool[])
+                as rl(from_block_id, to_block_id, content_changed)
+                on rl.from_block_id = r.block_id
+            where not rl.content_changed
+            order by r.token_id, rl.to_block_id, r.type_id, r.id
+        ) moving
+        where
+            r.id = moving.id
+            and not exists (
+                select 1 from reaction other
+                where
+                    other.token_id = r.token_id
+                    and other.block_id = moving.to_block_id
+                    and other.type_id = r.type_id
+            )",
+        &rl.from_block_ids,
+        &rl.to_block_ids,
+        &rl.content_changed
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_reactions"))?;
+    Ok(())
+}
+
+/// Pending and accepted suggestions follow their block. If the block was
+/// edited, the suggestion only moves if the passage it replaces is still there
+/// word for word, in the block or one nearby; otherwise it no longer applies,
+/// and stays behind.
+async fn carry_forward_suggestions(
+    db: &mut PgConnection,
+    rl: &Relocations,
+    target: &TargetBlocks,
+) -> Result<()> {
+    struct Qres {
+        id: i32,
+        original: String,
+        start_offset: i32,
+        end_offset: i32,
+        to_block_id: i32,
+        content_changed: bool,
+    }
+    let rejected_status_id: i32 = SuggestionStatus::Rejected.into();
+    let suggestions = query_as!(
+        Qres,
+        r#"select
+            s.id,
+            s.original,
+            s.start_offset,
+            s.end_offset,
+            rl.to_block_id "to_block_id!",
+            rl.content_changed "content_changed!"
+        from suggestion s
+        join unnest($1::int[], $2::int[], $3::bool[])
+            as rl(from_block_id, to_block_id, content_changed)
+            on rl.from_block_id = s.block_id
+        where s.status_id <> $4"#,
+        &rl.from_block_ids,
+        &rl.to_block_ids,
+        &rl.content_changed,
+        rejected_status_id
+    )
+    .fetch_all(&mut *db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_suggestions: select"))?;
+
+    let mut ids = Vec::with_capacity(suggestions.len());
+    let mut block_ids = Vec::with_capacity(suggestions.len());
+    let mut starts = Vec::with_capacity(suggestions.len());
+    let mut ends = Vec::with_capacity(suggestions.len());
+    for suggestion in suggestions {
+        let (block_id, start, end) = if suggestion.content_changed {
+            match target.find(
+                suggestion.to_block_id,
+                &suggestion.original,
+                suggestion.start_offset as usize,
+            ) {
+                Some((block_id, m)) if m.exact => {
+                    (block_id, m.start as i32, m.end as i32)
+                }
+                _ => continue,
+            }
+        } else {
+            (
+                suggestion.to_block_id,
+                suggestion.start_offset,
+                suggestion.end_offset,
+            )
+        };
+        ids.push(suggestion.id);
+        block_ids.push(block_id);
+        starts.push(start);
+        ends.push(end);
+    }
+    query!(
+        "update suggestion s
+        set
+            block_id = u.block_id,
+            start_offset = u.start_offset,
+            end_offset = u.end_offset
+        from unnest($1::int[], $2::int[], $3::int[], $4::int[])
+            as u(id, block_id, start_offset, end_offset)
+        where s.id = u.id",
+        &ids,
+        &block_ids,
+        &starts,
+        &ends
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "carry_forward_suggestions: update"))?;
+    Ok(())
+}
@@ -1,14 +1,19 @@
-use super::nav::{nav_helper, AdminNav};
+use super::{
+    carry_forward::carry_forward,
+    nav::{nav_helper, AdminNav},
+};
 use crate::{components::Saved, prelude::*};
 use chrono::{DateTime, Utc};
 use chrono_tz::Tz;
 
 pub async fn change_revision(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
 ) -> Result<Response> {
-    match nav_helper(Auth::from_headers(&db, &headers).await) {
-        AdminNav::IsAdmin => {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
             let ui = revision_change_ui(&db)
                 .await
                 .map_err(|e| e.wrap(ErrT::AdminBook).ctx("GET form".into()))?;
@@ -131,14 +136,19 @@ pub struct Payload {
 }
 
 pub async fn handle_revision_change(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
     Form(Payload {
         revision: revision_id,
     }): Form<Payload>,
 ) -> Result<Response> {
-    match nav_helper(Auth::from_headers(&db, &headers).await) {
-        AdminNav::IsAdmin => {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let mut tx = db.begin().await.map_err(|e| {
+                ErrStack::sqlx(&e, "handle_revision_change: begin")
+            })?;
             query!(
                 "insert into current_revision
                 (
@@ -151,11 +161,15 @@ pub async fn handle_revision_change(
                 revision_id,
                 1
             )
-            .execute(&db)
+            .execute(&mut *tx)
             .await
             .map_err(|e| {
                 ErrStack::sqlx(&e, "handle_revision_change: save new rev")
             })?;
+            carry_forward(&mut tx, revision_id).await?;
+            tx.commit().await.map_err(|e| {
+                ErrStack::sqlx(&e, "handle_revision_change: commit")
+            })?;
 
             let ui = revision_change_ui(&db).await?;
 
@@ -0,0 +1,589 @@
+//! The comment review dashboard; reading, filtering, replying to, and
+//! resolving comments left by readers.
+
+use super::{
+    export::ExportForm,
+    nav::{nav_helper, AdminNav},
+};
+use crate::{
+    book::{insert_reply, threads_by_root, Thread, ThreadView},
+    prelude::*,
+};
+use ides::{
+    comment::CommentStatus,
+    export::{
+        load_reactions, runs, summarize_run, ExportFilters, ExportReaction,
+    },
+};
+use std::collections::HashMap;
+
+/// Filters for the dashboard. These come from a form of `<select>`s, where
+/// an empty string means "any".
+#[derive(Default, Deserialize)]
+pub struct ReviewFilters {
+    /// The id of the chapter's heading block.
+    chapter: Option<String>,
+    reader: Option<String>,
+    revision: Option<String>,
+    status: Option<String>,
+    page: Option<i64>,
+}
+
+impl ReviewFilters {
+    fn non_empty(value: &Option<String>) -> Option<&str> {
+        value.as_deref().filter(|v| !v.is_empty())
+    }
+    fn id(name: &str, value: &Option<String>) -> Result<Option<i32>> {
+        Self::non_empty(value)
+            .map(|v| {
+                v.parse().map_err(|e| {
+                    ErrStack::new(ErrT::ValidationError)
+                        .ctx(format!("bad {name} filter {v}: {e}"))
+                })
+            })
+            .transpose()
+    }
+    fn chapter(&self) -> Result<Option<i32>> {
+        Self::id("chapter", &self.chapter)
+    }
+    fn reader(&self) -> Result<Option<i32>> {
+        Self::id("reader", &self.reader)
+    }
+    fn revision(&self) -> Result<Option<i32>> {
+        Self::id("revision", &self.revision)
+    }
+    fn status(&self) -> Result<Option<CommentStatus>> {
+        Self::non_empty(&self.status)
+            .map(CommentStatus::try_from)
+            .transpose()
+    }
+    fn pagination(&self) -> SqlPagination {
+        PaginationParams { page: self.page }.into()
+    }
+}
+
+struct ReviewComment {
+    id: i32,
+    status_id: i32,
+    chapter: Option<String>,
+    /// The revision the comment was written against, which may be older
+    /// than the one it's on now.
+    written_revision_id: i32,
+}
+
+async fn db_load_comments(
+    db: impl PgExecutor<'_>,
+    filters: &ReviewFilters,
+) -> Result<Vec<ReviewComment>> {
+    let status_id: Option<i32> = filters.status()?.map(|s| s.into());
+    let pagination = filters.pagination();
+    query_as!(
+        ReviewComment,
+        r#"select
+            c.id,
+            c.status_id,
+            ch.content "chapter?",
+            ob.book_revision_id written_revision_id
+        from comment c
+        join block ob on ob.id = coalesce(c.original_block_id, c.block_id)
+        join block_chapter bc on bc.block_id = c.block_id
+        left join block ch on ch.id = bc.chapter_id
+        where
+            c.parent_id is null
+            and ($1::int is null or bc.chapter_id = $1)
+            and ($2::int is null or c.token_id = $2)
+            and ($3::int is null or ob.book_revision_id = $3)
+            and ($4::int is null or c.status_id = $4)
+        order by c.created_at desc nulls last
+        limit $5 offset $6"#,
+        filters.chapter()?,
+        filters.reader()?,
+        filters.revision()?,
+        status_id,
+        pagination.limit,
+        pagination.offset
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "db_load_comments"))
+}
+
+pub struct FilterOption {
+    pub value: String,
+    pub label: String,
+}
+
+/// Readers, revisions, and chapters which have comments, for the filter
+/// dropdowns.
+struct FilterOptions {
+    readers: Vec<FilterOption>,
+    revisions: Vec<FilterOption>,
+    chapters: Vec<FilterOption>,
+}
+
+async fn db_load_filter_options(
+    db: impl PgExecutor<'_> + Copy,
+) -> Result<FilterOptions> {
+    let readers = query_as!(
+        FilterOption,
+        r#"select distinct t.id::text "value!", t.name label
+        from comment c
+        join token t on t.id = c.token_id
+        where c.parent_id is null
+        order by t.name"#
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "filter options: readers"))?;
+    let revisions = query_as!(
+        FilterOption,
+        r#"select distinct
+            ob.book_revision_id::text "value!",
+            'revision ' || ob.book_revision_id "label!"
+        from comment c
+        join block ob on ob.id = coalesce(c.original_block_id, c.block_id)
+        order by 1 desc"#
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "filter options: revisions"))?;
+    let chapters = query_as!(
+        FilterOption,
+        r#"select
+            ch.id::text "value!",
+            ch.content || ' (revision ' || ch.book_revision_id || ')' "label!"
+        from comment c
+        join block_chapter bc on bc.block_id = c.block_id
+        join block ch on ch.id = bc.chapter_id
+        group by ch.id
+        order by ch.book_revision_id desc, ch.sequence"#
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "filter options: chapters"))?;
+    Ok(FilterOptions {
+        readers,
+        revisions,
+        chapters,
+    })
+}
+
+async fn render_dashboard(
+    db: impl PgExecutor<'_> + Copy,
+    filters: &ReviewFilters,
+) -> Result<String> {
+    let comments = db_load_comments(db, filters).await?;
+    let ids: Vec<i32> = comments.iter().map(|c| c.id).collect();
+    let mut threads: HashMap<i32, Thread> = threads_by_root(db, &ids, true)
+        .await?
+        .into_iter()
+        .map(|thread| (thread.root.id, thread))
+        .collect();
+    let cards: Vec<(ReviewComment, Thread)> = comments
+        .into_iter()
+        .filter_map(|comment| {
+            let thread = threads.remove(&comment.id)?;
+            Some((comment, thread))
+        })
+        .collect();
+    let options = db_load_filter_options(db).await?;
+    let chapter = filters.chapter()?;
+    let reactions: Vec<ExportReaction> = load_reactions(
+        db,
+        &ExportFilters {
+            revision: filters.revision()?,
+            ..Default::default()
+        },
+    )
+    .await?
+    .into_iter()
+    .filter(|r| chapter.is_none() || r.chapter_id == chapter)
+    .collect();
+    Ok(Page {
+        title: "Comments",
+        children: &PageContainer {
+            children: &Dashboard {
+                filters,
+                options: &options,
+                cards: &cards,
+                reactions: &reactions,
+            },
+        },
+    }
+    .render())
+}
+
+pub async fn comments(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    Query(filters): Query<ReviewFilters>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            Ok(render_dashboard(&db, &filters).await?.into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReplyPayload {
+    comment: String,
+}
+
+pub async fn handle_comment_reply(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(comment_id): Path<i32>,
+    Form(ReplyPayload { comment }): Form<ReplyPayload>,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(auth) => {
+            insert_reply(&db, auth.token_id, comment_id, None, &comment)
+                .await?;
+            Ok([
+                render_dashboard(&db, &ReviewFilters::default()).await?,
+                Saved {
+                    message: "reply sent",
+                }
+                .render(),
+            ]
+            .join("")
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+/// Set the status of all selected comments. The form is posted as pairs,
+/// because there's one `comment_id` field for each selected comment; the
+/// current filters come along too, so that we can show the same view again.
+pub async fn handle_comment_status(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let mut ids: Vec<i32> = Vec::new();
+            let mut new_status = None;
+            let mut filters = ReviewFilters::default();
+            for (key, value) in pairs {
+                match key.as_str() {
+                    "comment_id" => ids.push(value.parse().map_err(|e| {
+                        ErrStack::new(ErrT::ValidationError)
+                            .ctx(format!("bad comment id {value}: {e}"))
+                    })?),
+                    "new_status" => {
+                        new_status =
+                            Some(CommentStatus::try_from(value.as_str())?)
+                    }
+                    "chapter" => filters.chapter = Some(value),
+                    "reader" => filters.reader = Some(value),
+                    "revision" => filters.revision = Some(value),
+                    "status" => filters.status = Some(value),
+                    _ => {}
+                }
+            }
+            let status_id: i32 = new_status
+                .ok_or_else(|| {
+                    ErrStack::new(ErrT::ValidationError)
+                        .ctx("no status was selected".into())
+                })?
+                .into();
+            query!(
+                "update comment set status_id = $1 where id = any($2)",
+                status_id,
+                &ids
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_comment_status"))?;
+            Ok([
+                render_dashboard(&db, &filters).await?,
+                Saved {
+                    message: &format!("updated {} comments", ids.len()),
+                }
+                .render(),
+            ]
+            .join("")
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+pub struct Select<'a> {
+    pub name: &'a str,
+    pub any_label: &'a str,
+    pub options: &'a [FilterOption],
+    pub selected: Option<&'a str>,
+}
+impl Component for Select<'_> {
+    fn render(&self) -> String {
+        let name = self.name;
+        let any_label = self.any_label;
+        let options =
+            self.options.iter().fold(String::new(), |mut acc, option| {
+                let value = clean_text(&option.value);
+                let label = clean(&option.label);
+                let selected = if Some(option.value.as_str()) == self.selected {
+                    "selected"
+                } else {
+                    ""
+                };
+                acc.push_str(&format!(
+                    r#"<option value="{value}" {selected}>{label}</option>"#
+                ));
+                acc
+            });
+        format!(
+            r#"
+            <select class="dark:text-black" name="{name}">
+                <option value="">{any_label}</option>
+                {options}
+            </select>
+            "#
+        )
+    }
+}
+
+struct Dashboard<'a> {
+    filters: &'a ReviewFilters,
+    options: &'a FilterOptions,
+    /// Each comment on this page, with the thread it starts.
+    cards: &'a [(ReviewComment, Thread)],
+    /// Reactions matching the chapter and revision filters.
+    reactions: &'a [ExportReaction],
+}
+impl Component for Dashboard<'_> {
+    fn render(&self) -> String {
+        let home = Route::AdminHome;
+        let route = Route::AdminComments;
+        let set_status = Route::AdminCommentStatus;
+        let status_options: Vec<FilterOption> = CommentStatus::ALL
+            .iter()
+            .map(|s| FilterOption {
+                value: s.name().into(),
+                label: s.label().into(),
+            })
+            .collect();
+        let filters = [
+            Select {
+                name: "chapter",
+                any_label: "any chapter",
+                options: &self.options.chapters,
+                selected: ReviewFilters::non_empty(&self.filters.chapter),
+            },
+            Select {
+                name: "reader",
+                any_label: "any reader",
+                options: &self.options.readers,
+                selected: ReviewFilters::non_empty(&self.filters.reader),
+            },
+            Select {
+                name: "revision",
+                any_label: "any revision",
+                options: &self.options.revisions,
+                selected: ReviewFilters::non_empty(&self.filters.revision),
+            },
+            Select {
+                name: "status",
+                any_label: "any status",
+                options: &status_options,
+                selected: ReviewFilters::non_empty(&self.filters.status),
+            },
+        ]
+        .iter()
+        .fold(String::new(), |mut acc, s| {
+            acc.push_str(&s.render());
+            acc
+        });
+        let new_status = Select {
+            name: "new_status",
+            any_label: "set status...",
+            options: &status_options,
+            selected: None,
+        }
+        .render();
+        let export = ExportForm {
+            revisions: &self.options.revisions,
+        }
+        .render();
+        let reactions = ReactionSummary {
+            reactions: self.reactions,
+        }
+        .render();
+        let comments = if self.cards.is_empty() {
+            r#"<p class="italic">No comments match these filters.</p>"#
+                .to_string()
+        } else {
+            self.cards.iter().fold(
+                String::new(),
+                |mut acc, (comment, thread)| {
+                    acc.push_str(&CommentCard { comment, thread }.render());
+                    acc
+                },
+            )
+        };
+        let page = self.filters.page.unwrap_or_default();
+        let next_page =
+            if self.cards.len() as i64 == self.filters.pagination().limit {
+                format!(
+                    r##"
+                <button
+                    class="link self-start"
+                    hx-get="{route}"
+                    hx-include="#comment-filters"
+                    hx-vals='{{"page": {}}}'
+                    hx-target="body"
+                    hx-push-url="true"
+                >
+                    older comments
+                </button>
+                "##,
+                    page + 1
+                )
+            } else {
+                String::new()
+            };
+        format!(
+            r##"
+            <div class="flex flex-col gap-4 max-w-prose">
+                <a class="link" href="{home}">Admin Home</a>
+                <h1 class="text-xl">Comments</h1>
+                {export}
+                {reactions}
+                <form
+                    id="comment-filters"
+                    class="flex flex-wrap gap-2"
+                    hx-get="{route}"
+                    hx-trigger="change"
+                    hx-target="body"
+                    hx-push-url="true"
+                >
+                    {filters}
+                </form>
+                <form
+                    id="comment-status"
+                    class="flex gap-2"
+                    hx-post="{set_status}"
+                    hx-include="#comment-filters"
+                    hx-target="body"
+                >
+                    {new_status}
+                    <button
+                        class="bg-orange-500 text-white font-bold p-1 rounded"
+                    >
+                        apply to selected
+                    </button>
+                </form>
+                {comments}
+                {next_page}
+            </div>
+            "##
+        )
+    }
+}
+
+/// Reaction counts for each chapter.
+struct ReactionSummary<'a> {
+    reactions: &'a [ExportReaction],
+}
+impl Component for ReactionSummary<'_> {
+    fn render(&self) -> String {
+        let all_reactions = Route::AdminReactions;
+        let chapters =
+            runs(self.reactions, |r| (r.book_revision_id, r.chapter.clone()))
+                .iter()
+                .fold(String::new(), |mut acc, chapter| {
+                    let title = clean(
+                        chapter[0]
+                            .chapter
+                            .as_deref()
+                            .unwrap_or("Before the first chapter"),
+                    );
+                    let revision = chapter[0].book_revision_id;
+                    let total = summarize_run(chapter);
+                    acc.push_str(&format!(
+                        "<li>{title} (revision {revision}): {total}</li>"
+                    ));
+                    acc
+                });
+        format!(
+            r#"
+            <details>
+                <summary class="cursor-pointer">Reactions by chapter</summary>
+                <ul class="list-disc list-inside">{chapters}</ul>
+                <a class="link" href="{all_reactions}">
+                    reactions for each paragraph
+                </a>
+            </details>
+            "#
+        )
+    }
+}
+
+struct CommentCard<'a> {
+    comment: &'a ReviewComment,
+    thread: &'a Thread,
+}
+impl Component for CommentCard<'_> {
+    fn render(&self) -> String {
+        let root = &self.thread.root;
+        let id = root.id;
+        let reader_name = clean(&root.author_name);
+        let revision =
+            if self.comment.written_revision_id == root.book_revision_id {
+                format!("revision {}", root.book_revision_id)
+            } else {
+                format!(
+                    "revision {}, now on {}",
+                    self.comment.written_revision_id, root.book_revision_id
+                )
+            };
+        let chapter = clean(self.comment.chapter.as_deref().unwrap_or(""));
+        let status = self
+            .comment
+            .status_id
+            .try_into()
+            .map(|s: CommentStatus| s.label())
+            .unwrap_or_default();
+        let thread = ThreadView {
+            root,
+            replies: &self.thread.replies,
+            show_block: true,
+            reply_route: Route::AdminCommentReply {
+                comment_id: Some(id),
+            },
+            viewer_id: None,
+        }
+        .render();
+        format!(
+            r#"
+            <div class="flex flex-col gap-1">
+                <label class="text-sm flex gap-2">
+                    <input
+                        type="checkbox"
+                        form="comment-status"
+                        name="comment_id"
+                        value="{id}"
+                    />
+                    #{id} &middot; {reader_name} &middot; {chapter}
+                    &middot; {revision} &middot; {status}
+                </label>
+                {thread}
+            </div>
+            "#
+        )
+    }
+}
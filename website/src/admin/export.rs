@@ -0,0 +1,173 @@
+//! Downloading comments or reactions as Markdown or CSV; see
+//! [ides::export].
+
+use super::{
+    comments::{FilterOption, Select},
+    nav::{nav_helper, AdminNav},
+};
+use crate::prelude::*;
+use axum::http::{header, HeaderValue};
+use chrono::NaiveDate;
+use ides::{
+    comment::CommentStatus,
+    export::{load_comments, load_reactions, ExportFilters, ExportFormat},
+};
+
+/// Fields from the export form. As with the dashboard filters, empty strings
+/// mean "any".
+#[derive(Deserialize)]
+pub struct ExportParams {
+    format: String,
+    /// `comments` or `reactions`.
+    data: String,
+    revision: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    status: Option<String>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|v| !v.is_empty())
+}
+
+fn parse_date(value: &Option<String>) -> Result<Option<NaiveDate>> {
+    non_empty(value)
+        .map(|v| {
+            NaiveDate::parse_from_str(v, "%Y-%m-%d").map_err(|e| {
+                ErrStack::new(ErrT::ValidationError)
+                    .ctx(format!("{v} is not a date: {e}"))
+            })
+        })
+        .transpose()
+}
+
+impl ExportParams {
+    fn filters(&self) -> Result<ExportFilters> {
+        Ok(ExportFilters {
+            revision: non_empty(&self.revision).and_then(|v| v.parse().ok()),
+            since: parse_date(&self.since)?,
+            until: parse_date(&self.until)?,
+            status: non_empty(&self.status)
+                .map(CommentStatus::try_from)
+                .transpose()?,
+        })
+    }
+}
+
+pub async fn export_comments(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    Query(params): Query<ExportParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let format = ExportFormat::try_from(params.format.as_str())?;
+            let filters = params.filters()?;
+            let (name, body) = match params.data.as_str() {
+                "reactions" if filters.status.is_some() => {
+                    return Err(ErrStack::new(ErrT::ValidationError).ctx(
+                        "reactions can't be filtered by comment status".into(),
+                    ))
+                }
+                "comments" => (
+                    "comments",
+                    format.render(&load_comments(&db, &filters).await?),
+                ),
+                "reactions" => (
+                    "reactions",
+                    format.render_reactions(
+                        &load_reactions(&db, &filters).await?,
+                    ),
+                ),
+                other => {
+                    return Err(ErrStack::new(ErrT::ValidationError)
+                        .ctx(format!("cannot export {other}")))
+                }
+            };
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.content_type()),
+            );
+            headers.insert(
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&format!(
+                    r#"attachment; filename="{name}.{}""#,
+                    format.extension()
+                ))
+                .expect("filename is ASCII"),
+            );
+            Ok((headers, body).into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+pub struct ExportForm<'a> {
+    pub revisions: &'a [FilterOption],
+}
+impl Component for ExportForm<'_> {
+    fn render(&self) -> String {
+        let route = Route::AdminCommentExport;
+        let revision = Select {
+            name: "revision",
+            any_label: "any revision",
+            options: self.revisions,
+            selected: None,
+        }
+        .render();
+        let status_options: Vec<FilterOption> = CommentStatus::ALL
+            .iter()
+            .map(|s| FilterOption {
+                value: s.name().into(),
+                label: s.label().into(),
+            })
+            .collect();
+        let status = Select {
+            name: "status",
+            any_label: "any status (comments only)",
+            options: &status_options,
+            selected: None,
+        }
+        .render();
+        format!(
+            r#"
+            <details>
+                <summary class="cursor-pointer">Export</summary>
+                <form
+                    class="flex flex-wrap gap-2 items-end"
+                    method="get"
+                    action="{route}"
+                >
+                    <select class="dark:text-black" name="data">
+                        <option value="comments">comments</option>
+                        <option value="reactions">reactions</option>
+                    </select>
+                    {revision}
+                    {status}
+                    <label class="flex flex-col text-sm">
+                        from
+                        <input class="dark:text-black" type="date" name="since" />
+                    </label>
+                    <label class="flex flex-col text-sm">
+                        to
+                        <input class="dark:text-black" type="date" name="until" />
+                    </label>
+                    <select class="dark:text-black" name="format">
+                        <option value="markdown">Markdown</option>
+                        <option value="csv">CSV</option>
+                    </select>
+                    <button
+                        class="bg-orange-500 text-white font-bold p-1 rounded"
+                    >
+                        download
+                    </button>
+                </form>
+            </details>
+            "#
+        )
+    }
+}
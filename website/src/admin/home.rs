@@ -2,11 +2,13 @@ use super::nav::{nav_helper, AdminNav};
 use crate::prelude::*;
 
 pub async fn home(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
 ) -> Result<Response> {
-    match nav_helper(Auth::from_headers(&db, &headers).await) {
-        AdminNav::IsAdmin => Ok(Page {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => Ok(Page {
             title: "Admin Home",
             children: &PageContainer { children: &Home {} },
         }
@@ -23,12 +25,22 @@ impl Component for Home {
         let import = Route::AdminImportBook;
         let change_rev = Route::AdminChangeRevision;
         let token = Route::AdminToken;
+        let comments = Route::AdminComments;
+        let reactions = Route::AdminReactions;
+        let suggestions = Route::AdminSuggestions;
+        let analytics = Route::AdminAnalytics;
+        let author_view = Route::AdminAuthorView;
         format!(
             r#"
             <div class="flex flex-col">
                 <a class="link" href="{import}">Import Book</a>
                 <a class="link" href="{change_rev}">Change Current Revision</a>
                 <a class="link" href="{token}">Manage Reader Tokens</a>
+                <a class="link" href="{comments}">Review Comments</a>
+                <a class="link" href="{reactions}">Reactions</a>
+                <a class="link" href="{suggestions}">Suggested Edits</a>
+                <a class="link" href="{analytics}">Reader Analytics</a>
+                <a class="link" href="{author_view}">Author View</a>
             </div>
             "#
         )
@@ -3,11 +3,13 @@ use crate::prelude::*;
 use ides::content::Book;
 
 pub async fn import_book_ui(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    match nav_helper(Auth::from_headers(&db, &headers).await) {
-        AdminNav::IsAdmin => Ok(Page {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => Ok(Page {
             title: "Import Book",
             children: &PageContainer {
                 children: &ImportBook {},
@@ -46,14 +48,24 @@ pub struct Payload {
 }
 
 pub async fn handle_import_book(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
     Form(Payload { content }): Form<Payload>,
 ) -> Result<impl IntoResponse> {
-    match nav_helper(Auth::from_headers(&db, &headers).await) {
-        AdminNav::IsAdmin => {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
             let book = Book::from_raw_plain_text(&content);
             let book = book.persist(&db).await?;
+            query!(
+                "update book_revision set source = $1 where id = $2",
+                content,
+                book.revision_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_import_book: source"))?;
             Ok([
                 Saved {
                     message: &format!(
@@ -1,7 +1,12 @@
 use super::nav::{nav_helper, AdminNav};
 use crate::prelude::*;
-use axum::http::StatusCode;
-use ides::auth::{Role, Token};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use ides::{
+    auth::{rotate_token, Role, Token},
+    content::Progress,
+    invitation::{create_invitation, invite_link, INVITATION_LIFETIME},
+};
 
 struct OverviewPage<'a> {
     existing_tokens: &'a [DisplayToken],
@@ -16,18 +21,38 @@ impl Component for OverviewPage<'_> {
                 .fold(String::new(), |mut acc, tok| {
                     let id = tok.id;
                     let name = clean(&tok.name);
+                    let name_attr = clean_text(&tok.name);
+                    let detail = Route::AdminReader { token_id: Some(id) };
+                    let chapter = clean(tok.chapter());
+                    let percent = tok
+                        .progress()
+                        .map(|p| format!("{}%", p.percent().round()))
+                        .unwrap_or_default();
+                    let last_active = tok.last_active();
                     let revoke = Route::AdminRevokeToken { token_id: Some(id) };
+                    let action = match tok.status() {
+                        Some(status) => format!("<p>{status}</p>"),
+                        None => format!(
+                            r#"
+                            <button
+                                hx-delete="{revoke}"
+                                hx-target="body"
+                                hx-confirm="Revoke {name_attr}'s access?"
+                                class="bg-red-500 hover:bg-red-600 rounded p-2"
+                            >
+                                Revoke
+                            </button>
+                            "#
+                        ),
+                    };
                     acc.push_str(&format!(
                         r#"
                 <p>{id}</p>
-                <p>{name}</p>
-                <button
-                    hx-delete="{revoke}"
-                    hx-target="body"
-                    class="bg-red-500 hover:bg-red-600 rounded p-2"
-                >
-                    Revoke
-                </button>
+                <a class="link" href="{detail}">{name}</a>
+                <p>{chapter}</p>
+                <p>{percent}</p>
+                <p>{last_active}</p>
+                {action}
                 "#
                     ));
                     acc
@@ -52,9 +77,12 @@ impl Component for OverviewPage<'_> {
 
             </form>
             <h2 class="text-xl">Existing Tokens</h2>
-            <div class="grid gap-2 grid-cols-3 max-w-prose">
+            <div class="grid gap-2 grid-cols-6">
                 <p class="bold">Id</p>
                 <p class="bold">Name</p>
+                <p class="bold">Chapter</p>
+                <p class="bold">Complete</p>
+                <p class="bold">Last active</p>
                 <p class="bold"></p>
                 {rendered_tokens}
             </div>
@@ -64,13 +92,15 @@ impl Component for OverviewPage<'_> {
 }
 
 pub async fn manage_tokens(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     Query(params): Query<PaginationParams>,
     headers: HeaderMap,
 ) -> Result<Response> {
-    match nav_helper(Auth::from_headers(&db, &headers).await) {
-        AdminNav::IsAdmin => {
-            let tokens = db_load_tokens(&db, &params.into()).await?;
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let tokens = db_load_tokens(&db, &params.into(), None).await?;
             Ok(Page {
                 title: "Manage Tokens",
                 children: &PageContainer {
@@ -88,24 +118,27 @@ pub async fn manage_tokens(
 }
 
 struct AfterCreatePage<'a> {
+    heading: &'a str,
     name: &'a str,
-    token: &'a Token,
+    invite_link: &'a str,
 }
 impl Component for AfterCreatePage<'_> {
     fn render(&self) -> String {
+        let heading = self.heading;
         let name = clean(self.name);
-        let token = clean(self.token.display_secret_value());
+        let link = clean(self.invite_link);
+        let days = INVITATION_LIFETIME.num_days();
         let back = Route::AdminToken;
         format!(
             r#"
             <div class="prose dark:prose-invert">
-                <h1>Token Created</h1>
-                <p>Here is a token for {name}:</p>
+                <h1>{heading}</h1>
+                <p>Send this invitation link to {name}:</p>
                 <p class="italic text-sm bg-yellow-100 dark:bg-yellow-700 inline-block rounded p-1">
-                    You won't be able to view this token again after you leave
-                    this page.
+                    You won't be able to view this link again after you leave
+                    this page. It works once, for the next {days} days.
                 </p>
-                <pre>{token}</pre>
+                <pre>{link}</pre>
                 <a href="{back}">Go Back</a>
             </div>
             "#
@@ -118,20 +151,28 @@ pub struct Payload {
 }
 
 pub async fn handle_create_token(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db,
+        session_key,
+        public_url,
+    }): State<AppState>,
     headers: HeaderMap,
     Form(Payload { name }): Form<Payload>,
 ) -> Result<Response> {
-    match nav_helper(Auth::from_headers(&db, &headers).await) {
-        AdminNav::IsAdmin => {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            // The reader gets their real secret when they accept the
+            // invitation, so this one is never shown to anybody.
             let token = ides::auth::Token::create()?;
-            db_persist_reader_token(&db, &name, &token).await?;
+            let token_id = db_persist_reader_token(&db, &name, &token).await?;
+            let code = create_invitation(&db, token_id).await?;
             Ok(Page {
                 title: "Manage Tokens",
                 children: &PageContainer {
                     children: &AfterCreatePage {
+                        heading: "Token Created",
                         name: &name,
-                        token: &token,
+                        invite_link: &invite_link(&public_url, &code),
                     },
                 },
             }
@@ -143,76 +184,191 @@ pub async fn handle_create_token(
     }
 }
 
+/// Give a reader's token a new secret, when the old one has leaked, and a
+/// fresh invitation so they can sign back in.
+pub async fn handle_rotate_token(
+    State(AppState {
+        db,
+        session_key,
+        public_url,
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(token_id): Path<i32>,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let token = db_load_tokens(
+                &db,
+                &PaginationParams { page: None }.into(),
+                Some(token_id),
+            )
+            .await?
+            .pop()
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::ValidationError)
+                    .ctx(format!("token {token_id} does not exist"))
+            })?;
+            // Signing in with the invitation would fail, and use it up.
+            if let Some(status) = token.status() {
+                return Err(ErrStack::new(ErrT::ValidationError).ctx(format!(
+                    "token {token_id} no longer works: {status}"
+                )));
+            }
+            // Both or neither; otherwise the reader could be signed out
+            // everywhere with no way back in. The new secret is never shown;
+            // the reader gets another one when they accept the invitation.
+            let mut tx = db.begin().await.map_err(|e| {
+                ErrStack::sqlx(&e, "handle_rotate_token: begin")
+            })?;
+            rotate_token(&mut *tx, token_id).await?.ok_or_else(|| {
+                ErrStack::new(ErrT::ValidationError)
+                    .ctx(format!("token {token_id} was revoked"))
+            })?;
+            let code = create_invitation(&mut *tx, token_id).await?;
+            tx.commit().await.map_err(|e| {
+                ErrStack::sqlx(&e, "handle_rotate_token: commit")
+            })?;
+            Ok(Page {
+                title: "Manage Tokens",
+                children: &PageContainer {
+                    children: &AfterCreatePage {
+                        heading: "Token Rotated",
+                        name: &token.name,
+                        invite_link: &invite_link(&public_url, &code),
+                    },
+                },
+            }
+            .render()
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+/// Revoked tokens stop working, but are kept along with everything the
+/// reader did.
 pub async fn handle_revoke_token(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     Query(params): Query<PaginationParams>,
     Path(token_id): Path<i32>,
     headers: HeaderMap,
 ) -> Result<Response> {
-    match nav_helper(Auth::from_headers(&db, &headers).await) {
-        AdminNav::IsAdmin => {
-            let delete_result = db_delete_token(&db, token_id).await;
-            match delete_result {
-                Ok(_) => {
-                    let tokens = db_load_tokens(&db, &params.into()).await?;
-                    Ok(Page {
-                        title: "Manage Tokens",
-                        children: &PageContainer {
-                            children: &OverviewPage {
-                                existing_tokens: &tokens,
-                            },
-                        },
-                    }
-                    .render()
-                    .into_response())
-                }
-                Err(e) => {
-                    if *e.peek() == ErrT::TokenInUse {
-                        Ok((
-                            StatusCode::NOT_IMPLEMENTED,
-                            [
-                                "Error: revocation of a token that has ",
-                                "been used supported, because the token is ",
-                                "referenced by the access log. The ",
-                                "quickest hack to revoke access is to ",
-                                "change the token value, which Jack can do ",
-                                "on the backend. To fully support this we ",
-                                "need to think about how to modify the ",
-                                "access log after token revocation. ",
-                                "Probably, we need to implement ",
-                                "soft-deletion of tokens (basically flag a ",
-                                "token as revoked but don't delete it), ",
-                                "which is a bit of a pain. Refresh the ",
-                                "page to dismiss this error.",
-                            ]
-                            .join(""),
-                        )
-                            .into_response())
-                    } else {
-                        Ok(e.into_response())
-                    }
-                }
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            query!(
+                "update token set revoked_at = now()
+                where id = $1 and revoked_at is null",
+                token_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_revoke_token"))?;
+            let tokens = db_load_tokens(&db, &params.into(), None).await?;
+            Ok(Page {
+                title: "Manage Tokens",
+                children: &PageContainer {
+                    children: &OverviewPage {
+                        existing_tokens: &tokens,
+                    },
+                },
             }
+            .render()
+            .into_response())
         }
         AdminNav::GetOuttaHere(response) => Ok(response),
         AdminNav::Err(e) => Err(e),
     }
 }
 
-struct DisplayToken {
-    id: i32,
-    name: String,
+/// A token, and where its reader is in the book.
+pub struct DisplayToken {
+    pub id: i32,
+    pub name: String,
+    chapter: Option<String>,
+    sequence: Option<i32>,
+    /// Size of the revision the reader's current block belongs to.
+    block_count: Option<i64>,
+    last_active: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl DisplayToken {
+    pub fn chapter(&self) -> &str {
+        match (&self.chapter, self.sequence) {
+            (Some(chapter), _) => chapter,
+            (None, Some(_)) => "Before the first chapter",
+            (None, None) => "not started",
+        }
+    }
+    pub fn progress(&self) -> Option<Progress> {
+        Some(Progress {
+            sequence: self.sequence?,
+            block_count: self.block_count?,
+        })
+    }
+    pub fn last_active(&self) -> String {
+        self.last_active
+            .map(|t| format_time(&t))
+            .unwrap_or_else(|| "never".into())
+    }
+    /// Whether the token has stopped working, and since when.
+    pub fn status(&self) -> Option<String> {
+        match (self.revoked_at, self.expires_at) {
+            (Some(revoked_at), _) => {
+                Some(format!("revoked {}", format_time(&revoked_at)))
+            }
+            (None, Some(expires_at)) if expires_at <= Utc::now() => {
+                Some(format!("expired {}", format_time(&expires_at)))
+            }
+            _ => None,
+        }
+    }
 }
 
-async fn db_load_tokens(
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.with_timezone(&Tz::America__New_York)
+        .format("%b %d, %Y %l:%M %p")
+        .to_string()
+}
+
+/// Load tokens, or just the one with `token_id`.
+pub async fn db_load_tokens(
     db: impl PgExecutor<'_>,
     pagination: &SqlPagination,
+    token_id: Option<i32>,
 ) -> Result<Vec<DisplayToken>> {
     query_as!(
         DisplayToken,
-        "select id, name from token order by name desc limit $1 offset $2",
+        r#"select
+            t.id,
+            t.name,
+            ch.content "chapter?",
+            b.sequence "sequence?",
+            (
+                select count(*) from block
+                where book_revision_id = b.book_revision_id
+            ) "block_count?",
+            (
+                select max(created_at) from access_log
+                where token_id = t.id
+            ) last_active,
+            t.revoked_at,
+            t.expires_at
+        from token t
+        left join current_block cb on cb.token_id = t.id
+        left join block b on b.id = cb.block_id
+        left join block_chapter bc on bc.block_id = b.id
+        left join block ch on ch.id = bc.chapter_id
+        where $3::int is null or t.id = $3
+        order by t.name desc
+        limit $1 offset $2"#,
         pagination.limit,
-        pagination.offset
+        pagination.offset,
+        token_id
     )
     .fetch_all(db)
     .await
@@ -223,44 +379,22 @@ async fn db_persist_reader_token(
     db: impl PgExecutor<'_>,
     name: &str,
     token: &Token,
-) -> Result<()> {
+) -> Result<i32> {
     let role_id: i32 = Role::Reader.into();
-    query!(
+    let row = query!(
         "insert into token
         (
                 name,
                 token_digest,
                 role_id
-        ) values ($1, $2, $3)",
+        ) values ($1, $2, $3)
+        returning id",
         name,
         token.sha256_hex(),
         role_id
     )
-    .execute(db)
+    .fetch_one(db)
     .await
     .map_err(|e| ErrStack::sqlx(&e, "db_persist_reader_token"))?;
-    Ok(())
-}
-
-async fn db_delete_token(db: impl PgExecutor<'_>, token_id: i32) -> Result<()> {
-    query!("delete from token where id = $1", token_id)
-        .execute(db)
-        .await
-        .map_err(|e| {
-            let stack = ErrStack::sqlx(&e, "db_delete_token");
-            if let Some(db_err) = e.as_database_error() {
-                if let Some(constraint) = db_err.constraint() {
-                    if constraint == "access_log_token_id_fkey" {
-                        stack.wrap(ErrT::TokenInUse)
-                    } else {
-                        stack
-                    }
-                } else {
-                    stack
-                }
-            } else {
-                stack
-            }
-        })?;
-    Ok(())
+    Ok(row.id)
 }
@@ -1,14 +1,34 @@
 //! Admin UI for importing and updating the book, etc.
 
+mod analytics;
+mod author_view;
+mod carry_forward;
 mod change_revision;
+mod comments;
+mod export;
 mod home;
 mod import;
 mod manage_token;
 mod nav;
+mod reactions;
+mod reader;
+mod suggestions;
 
+pub use analytics::analytics;
+pub use author_view::author_view;
 pub use change_revision::{change_revision, handle_revision_change};
+pub use comments::{comments, handle_comment_reply, handle_comment_status};
+pub use export::export_comments;
 pub use home::home;
 pub use import::{handle_import_book, import_book_ui};
 pub use manage_token::{
-    handle_create_token, handle_revoke_token, manage_tokens,
+    handle_create_token, handle_revoke_token, handle_rotate_token,
+    manage_tokens,
+};
+pub use reactions::reactions;
+pub use reader::{
+    handle_reader_expiry, handle_reader_position, handle_revoke_session, reader,
+};
+pub use suggestions::{
+    accepted_suggestions, handle_suggestion_status, suggestions,
 };
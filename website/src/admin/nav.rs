@@ -1,12 +1,12 @@
 use crate::{
-    auth::{AuthResult, Role},
+    auth::{Auth, AuthResult, Role},
     htmx,
     prelude::*,
 };
 use axum::response::Response;
 
 pub enum AdminNav {
-    IsAdmin,
+    IsAdmin(Auth),
     GetOuttaHere(Response),
     Err(ErrStack),
 }
@@ -14,13 +14,13 @@ pub enum AdminNav {
 pub fn nav_helper(auth_result: AuthResult) -> AdminNav {
     match auth_result {
         AuthResult::Authenticated(auth) => match auth.role {
-            Role::Admin => AdminNav::IsAdmin,
+            Role::Admin => AdminNav::IsAdmin(auth),
             Role::Reader => AdminNav::GetOuttaHere(
                 htmx::redirect(HeaderMap::new(), &Route::Book.as_string())
                     .into_response(),
             ),
         },
-        AuthResult::NotAuthenticated => AdminNav::GetOuttaHere(
+        AuthResult::NotAuthenticated(_) => AdminNav::GetOuttaHere(
             htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
                 .into_response(),
         ),
@@ -0,0 +1,166 @@
+//! Reactions to the book, counted for each chapter and block.
+
+use super::{
+    comments::{FilterOption, Select},
+    nav::{nav_helper, AdminNav},
+};
+use crate::{components::QuotedBlock, prelude::*};
+use ides::export::{
+    load_reactions, runs, summarize_run, ExportFilters, ExportReaction,
+};
+
+#[derive(Deserialize)]
+pub struct ReactionParams {
+    /// Defaults to the current revision.
+    revision: Option<String>,
+}
+
+pub async fn reactions(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    Query(params): Query<ReactionParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            struct Qres {
+                revision_id: i32,
+            }
+            let revision_id =
+                match params.revision.as_deref().and_then(|r| r.parse().ok()) {
+                    Some(id) => id,
+                    None => {
+                        query_as!(
+                            Qres,
+                            "select revision_id from current_revision
+                        where book_id = 1"
+                        )
+                        .fetch_one(&db)
+                        .await
+                        .map_err(|e| ErrStack::sqlx(&e, "reactions: revision"))?
+                        .revision_id
+                    }
+                };
+            let revisions = query_as!(
+                FilterOption,
+                r#"select distinct
+                    b.book_revision_id::text "value!",
+                    'revision ' || b.book_revision_id "label!"
+                from reaction r
+                join block b on b.id = r.block_id
+                order by 1 desc"#
+            )
+            .fetch_all(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "reactions: revisions"))?;
+            let reactions = load_reactions(
+                &db,
+                &ExportFilters {
+                    revision: Some(revision_id),
+                    ..Default::default()
+                },
+            )
+            .await?;
+            Ok(Page {
+                title: "Reactions",
+                children: &PageContainer {
+                    children: &ReactionsPage {
+                        revision_id,
+                        revisions: &revisions,
+                        reactions: &reactions,
+                    },
+                },
+            }
+            .render()
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+struct ReactionsPage<'a> {
+    revision_id: i32,
+    revisions: &'a [FilterOption],
+    reactions: &'a [ExportReaction],
+}
+impl Component for ReactionsPage<'_> {
+    fn render(&self) -> String {
+        let comments = Route::AdminComments;
+        let route = Route::AdminReactions;
+        let selected = self.revision_id.to_string();
+        let revision = Select {
+            name: "revision",
+            any_label: "current revision",
+            options: self.revisions,
+            selected: Some(&selected),
+        }
+        .render();
+        let chapters = if self.reactions.is_empty() {
+            r#"<p class="italic">No reactions on this revision yet.</p>"#
+                .to_string()
+        } else {
+            runs(self.reactions, |r| r.chapter.clone()).iter().fold(
+                String::new(),
+                |mut acc, chapter| {
+                    let title = clean(
+                        chapter[0]
+                            .chapter
+                            .as_deref()
+                            .unwrap_or("Before the first chapter"),
+                    );
+                    let total = summarize_run(chapter);
+                    let blocks = runs(chapter, |r| r.sequence).iter().fold(
+                        String::new(),
+                        |mut acc, block| {
+                            let quote = QuotedBlock {
+                                content: &block[0].block_content,
+                                span: None,
+                            }
+                            .render();
+                            let counts = summarize_run(block);
+                            acc.push_str(&format!(
+                                r#"
+                                <div class="flex flex-col">
+                                    {quote}
+                                    <p>{counts}</p>
+                                </div>
+                                "#
+                            ));
+                            acc
+                        },
+                    );
+                    acc.push_str(&format!(
+                        r#"
+                        <details class="flex flex-col gap-2">
+                            <summary class="cursor-pointer text-lg">
+                                {title} &middot; {total}
+                            </summary>
+                            {blocks}
+                        </details>
+                        "#
+                    ));
+                    acc
+                },
+            )
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-4 max-w-prose">
+                <a class="link" href="{comments}">Review Comments</a>
+                <h1 class="text-xl">Reactions</h1>
+                <form
+                    hx-get="{route}"
+                    hx-trigger="change"
+                    hx-target="body"
+                    hx-push-url="true"
+                >
+                    {revision}
+                </form>
+                {chapters}
+            </div>
+            "#
+        )
+    }
+}
@@ -0,0 +1,697 @@
+//! Everything about one reader; where they are, when they've read, and what
+//! they've said about the book.
+
+use super::{
+    manage_token::{db_load_tokens, DisplayToken},
+    nav::{nav_helper, AdminNav},
+};
+use crate::{
+    auth::sessions::{db_load_sessions, DisplaySession, SessionList},
+    book::{position_at, remember_position, CurrentPosition},
+    components::QuotedBlock,
+    prelude::*,
+};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use ides::{
+    analytics::{sessions, SESSION_GAP},
+    content::{BlockType, Position},
+    reaction::ReactionType,
+};
+
+/// How many of the reader's most recent sessions to show.
+const SESSION_LIMIT: usize = 50;
+
+struct Event {
+    created_at: DateTime<Utc>,
+    event: String,
+    chapter: Option<String>,
+}
+
+struct ReaderComment {
+    created_at: Option<DateTime<Utc>>,
+    comment: String,
+    is_reply: bool,
+    deleted: bool,
+    chapter: Option<String>,
+    block_content: String,
+    quote_start: Option<i32>,
+    quote_end: Option<i32>,
+}
+
+struct Chapter {
+    sequence: i32,
+    content: String,
+}
+
+struct ReaderReaction {
+    created_at: DateTime<Utc>,
+    type_id: i32,
+    chapter: Option<String>,
+    block_content: String,
+}
+
+pub async fn reader(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(token_id): Path<i32>,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            Ok(render_reader(&db, token_id).await?.into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+async fn render_reader(
+    db: impl PgExecutor<'_> + Copy,
+    token_id: i32,
+) -> Result<String> {
+    let paragraph_type_id: i32 = BlockType::Paragraph.into();
+    let token = db_load_tokens(
+        db,
+        &PaginationParams { page: None }.into(),
+        Some(token_id),
+    )
+    .await?
+    .pop()
+    .ok_or_else(|| {
+        ErrStack::new(ErrT::ValidationError)
+            .ctx(format!("token {token_id} does not exist"))
+    })?;
+    let events = query_as!(
+        Event,
+        r#"select
+            a.created_at,
+            e.name event,
+            ch.content "chapter?"
+        from access_log a
+        join access_event_type e on e.id = a.event_type_id
+        left join block_chapter bc on bc.block_id = a.block_id
+        left join block ch on ch.id = bc.chapter_id
+        where a.token_id = $1
+        order by a.created_at"#,
+        token_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "reader: events"))?;
+    let comments = query_as!(
+        ReaderComment,
+        r#"select
+            c.created_at,
+            c.comment,
+            c.parent_id is not null "is_reply!",
+            c.deleted_at is not null "deleted!",
+            ch.content "chapter?",
+            b.content block_content,
+            c.quote_start,
+            c.quote_end
+        from comment c
+        join block b on b.id = c.block_id
+        join block_chapter bc on bc.block_id = b.id
+        left join block ch on ch.id = bc.chapter_id
+        where c.token_id = $1
+        order by c.created_at desc nulls last"#,
+        token_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "reader: comments"))?;
+    let reactions = query_as!(
+        ReaderReaction,
+        r#"select
+            r.created_at,
+            r.type_id,
+            ch.content "chapter?",
+            b.content block_content
+        from reaction r
+        join block b on b.id = r.block_id
+        join block_chapter bc on bc.block_id = b.id
+        left join block ch on ch.id = bc.chapter_id
+        where r.token_id = $1
+        order by r.created_at desc"#,
+        token_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "reader: reactions"))?;
+    let chapters = query_as!(
+        Chapter,
+        "select sequence, content from block
+        where
+            type_id <> $1
+            and book_revision_id = (
+                select revision_id from current_revision where book_id = 1
+            )
+        order by sequence",
+        paragraph_type_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "reader: chapters"))?;
+    let sessions = db_load_sessions(db, token_id).await?;
+    Ok(Page {
+        title: &format!("Reader: {}", token.name),
+        children: &PageContainer {
+            children: &ReaderPage {
+                token: &token,
+                events: &events,
+                comments: &comments,
+                reactions: &reactions,
+                chapters: &chapters,
+                sessions: &sessions,
+            },
+        },
+    }
+    .render())
+}
+
+#[derive(Deserialize)]
+pub struct PositionPayload {
+    /// Sequence of a chapter heading; an empty string means none.
+    chapter: String,
+    /// Sequence of any block, which takes precedence over the chapter.
+    block: String,
+}
+
+/// Move a reader to the start of a chapter, or to a particular block, of the
+/// current revision. Their old position goes into their history, so they
+/// can go back to it themselves.
+pub async fn handle_reader_position(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(token_id): Path<i32>,
+    Form(PositionPayload { chapter, block }): Form<PositionPayload>,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let sequence: i32 = [block.trim(), chapter.trim()]
+                .into_iter()
+                .find(|v| !v.is_empty())
+                .ok_or_else(|| {
+                    ErrStack::new(ErrT::ValidationError)
+                        .ctx("choose a chapter or a block".into())
+                })?
+                .parse()
+                .map_err(|e| {
+                    ErrStack::new(ErrT::ValidationError)
+                        .ctx(format!("invalid block number: {e}"))
+                })?;
+            let target = position_at(
+                &db,
+                None,
+                Position {
+                    sequence,
+                    offset: 0,
+                },
+            )
+            .await?
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::ValidationError)
+                    .ctx(format!("there is no block {sequence}"))
+            })?;
+            let current = query_as!(
+                CurrentPosition,
+                "select
+                    bl.id current_block_id,
+                    bl.book_revision_id,
+                    bl.sequence current_block_sequence,
+                    cb.block_offset current_block_offset
+                from current_block cb
+                join block bl on bl.id = cb.block_id
+                where cb.token_id = $1",
+                token_id
+            )
+            .fetch_optional(&db)
+            .await
+            .map_err(|e| {
+                ErrStack::sqlx(&e, "handle_reader_position: current")
+            })?;
+            if let Some(current) = current {
+                remember_position(&db, token_id, &current).await?;
+            }
+            query!(
+                "insert into current_block (token_id, block_id, block_offset)
+                values ($1, $2, 0)
+                on conflict (token_id)
+                do update set block_id = $2, block_offset = 0",
+                token_id,
+                target.current_block_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_reader_position: save"))?;
+            Ok([
+                render_reader(&db, token_id).await?,
+                Saved {
+                    message: "position updated",
+                }
+                .render(),
+            ]
+            .join("")
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExpiryPayload {
+    /// A date as `YYYY-MM-DD`; an empty string means the token never
+    /// expires.
+    expires_on: String,
+}
+
+/// Set the day on which a reader's token stops working, from midnight
+/// Eastern time, or clear it.
+pub async fn handle_reader_expiry(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(token_id): Path<i32>,
+    Form(ExpiryPayload { expires_on }): Form<ExpiryPayload>,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let expires_at = match expires_on.trim() {
+                "" => None,
+                date => {
+                    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                        .map_err(|e| {
+                            ErrStack::new(ErrT::ValidationError)
+                                .ctx(format!("invalid expiry date: {e}"))
+                        })?;
+                    let midnight = Tz::America__New_York
+                        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+                        .earliest()
+                        .ok_or_else(|| {
+                            ErrStack::new(ErrT::ValidationError)
+                                .ctx(format!("no midnight on {date}"))
+                        })?;
+                    Some(midnight.with_timezone(&Utc))
+                }
+            };
+            query!(
+                "update token set expires_at = $2 where id = $1",
+                token_id,
+                expires_at
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_reader_expiry"))?;
+            Ok([
+                render_reader(&db, token_id).await?,
+                Saved {
+                    message: "expiry updated",
+                }
+                .render(),
+            ]
+            .join("")
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+/// Sign one of a reader's devices out.
+pub async fn handle_revoke_session(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(session_id): Path<i32>,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let revoked = query!(
+                "update session set revoked_at = coalesce(revoked_at, now())
+                where id = $1
+                returning token_id",
+                session_id
+            )
+            .fetch_optional(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_revoke_session"))?
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::NotFound)
+                    .ctx(format!("session {session_id} does not exist"))
+            })?;
+            Ok([
+                render_reader(&db, revoked.token_id).await?,
+                Saved {
+                    message: "device signed out",
+                }
+                .render(),
+            ]
+            .join("")
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.with_timezone(&Tz::America__New_York)
+        .format("%b %d, %Y %l:%M %p")
+        .to_string()
+}
+
+fn chapter_name(chapter: &Option<String>) -> String {
+    clean(chapter.as_deref().unwrap_or("Before the first chapter"))
+}
+
+struct ReaderPage<'a> {
+    token: &'a DisplayToken,
+    events: &'a [Event],
+    comments: &'a [ReaderComment],
+    reactions: &'a [ReaderReaction],
+    chapters: &'a [Chapter],
+    sessions: &'a [DisplaySession],
+}
+impl Component for ReaderPage<'_> {
+    fn render(&self) -> String {
+        let tokens = Route::AdminToken;
+        let name = clean(&self.token.name);
+        let chapter = clean(self.token.chapter());
+        let percent = self
+            .token
+            .progress()
+            .map(|p| format!(" &middot; {}% complete", p.percent().round()))
+            .unwrap_or_default();
+        let last_active = self.token.last_active();
+        let status = match (self.token.status(), self.token.expires_at) {
+            (Some(status), _) => {
+                format!(r#"<p class="text-red-500">Token {status}</p>"#)
+            }
+            (None, Some(expires_at)) => {
+                format!("<p>Token expires {}</p>", format_time(&expires_at))
+            }
+            (None, None) => String::new(),
+        };
+        let set_expiry = ExpiryForm { token: self.token }.render();
+        let rotate = Route::AdminReaderRotate {
+            token_id: Some(self.token.id),
+        };
+        let rotate = if self.token.status().is_none() {
+            let name_attr = clean_text(&self.token.name);
+            format!(
+                r#"
+                <button
+                    hx-post="{rotate}"
+                    hx-target="body"
+                    hx-confirm="Sign {name_attr} out everywhere, and make a new invitation link for them?"
+                    class="self-start bg-red-500 hover:bg-red-600 rounded p-2"
+                >
+                    Rotate token
+                </button>
+                "#
+            )
+        } else {
+            String::new()
+        };
+        let set_position = PositionForm {
+            token_id: self.token.id,
+            chapters: self.chapters,
+        }
+        .render();
+        let sessions = SessionList {
+            sessions: self.sessions,
+            current: None,
+            revoke: |session_id| Route::AdminSession {
+                session_id: Some(session_id),
+            },
+        }
+        .render();
+        let timeline = Timeline {
+            events: self.events,
+        }
+        .render();
+        let review =
+            format!("{}?reader={}", Route::AdminComments, self.token.id);
+        let comments = if self.comments.is_empty() {
+            r#"<p class="italic">No comments.</p>"#.to_string()
+        } else {
+            self.comments.iter().fold(String::new(), |mut acc, c| {
+                let time = c
+                    .created_at
+                    .as_ref()
+                    .map(format_time)
+                    .unwrap_or_else(|| "date unknown".into());
+                let chapter = chapter_name(&c.chapter);
+                let kind = if c.is_reply { "reply" } else { "comment" };
+                let quote = QuotedBlock {
+                    content: &c.block_content,
+                    span: c
+                        .quote_start
+                        .zip(c.quote_end)
+                        .filter(|_| !c.deleted)
+                        .map(|(s, e)| {
+                        (s as usize, e as usize)
+                    }),
+                }
+                .render();
+                let text = if c.deleted {
+                    r#"<span class="italic">deleted</span>"#.to_string()
+                } else {
+                    clean(&c.comment)
+                };
+                acc.push_str(&format!(
+                    r#"
+                    <div class="flex flex-col gap-1">
+                        <p class="text-sm">{time} &middot; {chapter} &middot; {kind}</p>
+                        {quote}
+                        <p>{text}</p>
+                    </div>
+                    "#
+                ));
+                acc
+            })
+        };
+        let reactions = if self.reactions.is_empty() {
+            r#"<p class="italic">No reactions.</p>"#.to_string()
+        } else {
+            self.reactions.iter().fold(String::new(), |mut acc, r| {
+                let time = format_time(&r.created_at);
+                let chapter = chapter_name(&r.chapter);
+                let reaction = TryInto::<ReactionType>::try_into(r.type_id)
+                    .map(|r| format!("{} {}", r.emoji(), r.label()))
+                    .unwrap_or_default();
+                let quote = QuotedBlock {
+                    content: &r.block_content,
+                    span: None,
+                }
+                .render();
+                acc.push_str(&format!(
+                    r#"
+                    <div class="flex flex-col gap-1">
+                        <p class="text-sm">{time} &middot; {chapter} &middot; {reaction}</p>
+                        {quote}
+                    </div>
+                    "#
+                ));
+                acc
+            })
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-4 max-w-prose">
+                <a class="link" href="{tokens}">Manage Reader Tokens</a>
+                <h1 class="text-xl">{name}</h1>
+                <p>{chapter}{percent} &middot; last active {last_active}</p>
+                {status}
+                {set_position}
+                {set_expiry}
+                {rotate}
+                <h2 class="text-lg">Signed in on</h2>
+                {sessions}
+                <h2 class="text-lg">Reading timeline</h2>
+                {timeline}
+                <h2 class="text-lg">Comments</h2>
+                <a class="link" href="{review}">review their comments</a>
+                {comments}
+                <h2 class="text-lg">Reactions</h2>
+                {reactions}
+            </div>
+            "#
+        )
+    }
+}
+
+/// The reader's sessions, most recent first, each with the events in it.
+struct Timeline<'a> {
+    events: &'a [Event],
+}
+impl Component for Timeline<'_> {
+    fn render(&self) -> String {
+        if self.events.is_empty() {
+            return r#"<p class="italic">Hasn't read yet.</p>"#.into();
+        }
+        let times: Vec<DateTime<Utc>> =
+            self.events.iter().map(|e| e.created_at).collect();
+        let sessions = sessions(&times, SESSION_GAP);
+        // Sessions are runs of consecutive events, so we can split the
+        // events up by each session's page count.
+        let mut rest = self.events;
+        let mut grouped = Vec::with_capacity(sessions.len());
+        for session in &sessions {
+            let (events, remainder) = rest.split_at(session.pages);
+            grouped.push((session, events));
+            rest = remainder;
+        }
+        grouped.iter().rev().take(SESSION_LIMIT).fold(
+            String::new(),
+            |mut acc, (session, events)| {
+                let start = format_time(&session.start);
+                let minutes = session.duration().num_minutes();
+                let count = session.pages;
+                let from = chapter_name(&events[0].chapter);
+                let to = chapter_name(&events[events.len() - 1].chapter);
+                let chapters = if from == to {
+                    from
+                } else {
+                    format!("{from} &rarr; {to}")
+                };
+                let rows = events.iter().fold(String::new(), |mut acc, e| {
+                    let time = e
+                        .created_at
+                        .with_timezone(&Tz::America__New_York)
+                        .format("%l:%M %p");
+                    let event = clean(&e.event);
+                    let chapter = chapter_name(&e.chapter);
+                    acc.push_str(&format!(
+                        "<li>{time} &middot; {event} &middot; {chapter}</li>"
+                    ));
+                    acc
+                });
+                acc.push_str(&format!(
+                    r#"
+                    <details>
+                        <summary class="cursor-pointer">
+                            {start} &middot; {minutes} min &middot; {count}
+                            events &middot; {chapters}
+                        </summary>
+                        <ul class="text-sm pl-4">{rows}</ul>
+                    </details>
+                    "#
+                ));
+                acc
+            },
+        )
+    }
+}
+
+struct PositionForm<'a> {
+    token_id: i32,
+    chapters: &'a [Chapter],
+}
+impl Component for PositionForm<'_> {
+    fn render(&self) -> String {
+        let route = Route::AdminReaderPosition {
+            token_id: Some(self.token_id),
+        };
+        let options =
+            self.chapters
+                .iter()
+                .fold(String::new(), |mut acc, chapter| {
+                    acc.push_str(&format!(
+                        r#"<option value="{}">{}</option>"#,
+                        chapter.sequence,
+                        clean(&chapter.content)
+                    ));
+                    acc
+                });
+        format!(
+            r#"
+            <details>
+                <summary class="cursor-pointer">move this reader</summary>
+                <form
+                    class="flex flex-col gap-2 p-2"
+                    hx-post="{route}"
+                    hx-target="body"
+                >
+                    <label for="chapter">to the start of</label>
+                    <select id="chapter" name="chapter" class="dark:text-black">
+                        <option value="">choose a chapter</option>
+                        {options}
+                    </select>
+                    <label for="block">or to block number</label>
+                    <input
+                        id="block"
+                        name="block"
+                        type="number"
+                        min="0"
+                        class="dark:text-black"
+                    />
+                    <button
+                        class="bg-orange-500 text-white font-bold p-1 rounded"
+                    >
+                        move
+                    </button>
+                </form>
+            </details>
+            "#
+        )
+    }
+}
+
+struct ExpiryForm<'a> {
+    token: &'a DisplayToken,
+}
+impl Component for ExpiryForm<'_> {
+    fn render(&self) -> String {
+        let route = Route::AdminReaderExpiry {
+            token_id: Some(self.token.id),
+        };
+        let value = self
+            .token
+            .expires_at
+            .map(|t| {
+                t.with_timezone(&Tz::America__New_York)
+                    .format("%Y-%m-%d")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        format!(
+            r#"
+            <details>
+                <summary class="cursor-pointer">set an expiry</summary>
+                <form
+                    class="flex flex-col gap-2 p-2"
+                    hx-post="{route}"
+                    hx-target="body"
+                >
+                    <label for="expires_on">
+                        token stops working at the start of
+                    </label>
+                    <input
+                        id="expires_on"
+                        name="expires_on"
+                        type="date"
+                        value="{value}"
+                        class="dark:text-black"
+                    />
+                    <p class="text-sm italic">
+                        Leave empty for a token which never expires.
+                    </p>
+                    <button
+                        class="bg-orange-500 text-white font-bold p-1 rounded"
+                    >
+                        save
+                    </button>
+                </form>
+            </details>
+            "#
+        )
+    }
+}
@@ -0,0 +1,487 @@
+//! The review queue for suggested edits. Each suggestion is shown as a
+//! word-level diff of its block; accepted suggestions on the current revision
+//! can be downloaded as one patch against the author's manuscript, to apply
+//! when producing the next revision.
+
+use super::nav::{nav_helper, AdminNav};
+use crate::prelude::*;
+use axum::http::{header, HeaderValue};
+use ides::{
+    content::{Block, Book},
+    diff::{patch_text, unified_diff, word_diff, DiffOp},
+    suggestion::{apply_edits, source_edits, Edit, SuggestionStatus},
+};
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+pub struct QueueParams {
+    /// Defaults to pending suggestions.
+    status: Option<String>,
+}
+
+struct Suggestion {
+    id: i32,
+    reader_name: String,
+    chapter: Option<String>,
+    book_revision_id: i32,
+    block_content: String,
+    start_offset: i32,
+    end_offset: i32,
+    replacement: String,
+    note: Option<String>,
+}
+
+async fn db_load_suggestions(
+    db: impl PgExecutor<'_>,
+    status: SuggestionStatus,
+) -> Result<Vec<Suggestion>> {
+    let status_id: i32 = status.into();
+    query_as!(
+        Suggestion,
+        r#"select
+            s.id,
+            t.name reader_name,
+            ch.content "chapter?",
+            b.book_revision_id,
+            b.content block_content,
+            s.start_offset,
+            s.end_offset,
+            s.replacement,
+            s.note
+        from suggestion s
+        join token t on t.id = s.token_id
+        join block b on b.id = s.block_id
+        join block_chapter bc on bc.block_id = b.id
+        left join block ch on ch.id = bc.chapter_id
+        where s.status_id = $1
+        order by b.book_revision_id desc, b.sequence, s.created_at"#,
+        status_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "db_load_suggestions"))
+}
+
+async fn render_queue(
+    db: impl PgExecutor<'_>,
+    status: SuggestionStatus,
+) -> Result<String> {
+    let suggestions = db_load_suggestions(db, status).await?;
+    Ok(Page {
+        title: "Suggestions",
+        children: &PageContainer {
+            children: &Queue {
+                status,
+                suggestions: &suggestions,
+            },
+        },
+    }
+    .render())
+}
+
+pub async fn suggestions(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    Query(params): Query<QueueParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let status = match params.status.as_deref() {
+                Some(s) if !s.is_empty() => SuggestionStatus::try_from(s)?,
+                _ => SuggestionStatus::Pending,
+            };
+            Ok(render_queue(&db, status).await?.into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StatusPayload {
+    status: SuggestionStatus,
+}
+
+/// Accept or reject a suggestion, then show the pending queue again.
+pub async fn handle_suggestion_status(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(suggestion_id): Path<i32>,
+    Form(StatusPayload { status }): Form<StatusPayload>,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let status_id: i32 = status.into();
+            query!(
+                "update suggestion
+                set status_id = $1, reviewed_at = now()
+                where id = $2",
+                status_id,
+                suggestion_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_suggestion_status"))?;
+            Ok([
+                render_queue(&db, SuggestionStatus::Pending).await?,
+                Saved {
+                    message: &format!("suggestion {}", status.name()),
+                }
+                .render(),
+            ]
+            .join("")
+            .into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+/// A patch with every accepted suggestion on the current revision applied,
+/// against the manuscript the author imported for it. Suggestions which
+/// overlap one already applied to the same block are listed above the patch
+/// instead.
+///
+/// Revisions imported before we kept the manuscript fall back to a patch of
+/// the book as [Book::to_plain_text] writes it, with one line per paragraph.
+pub async fn accepted_suggestions(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match nav_helper(Auth::from_headers(&db, &session_key, &headers).await) {
+        AdminNav::IsAdmin(_) => {
+            let patch = build_patch(&db).await?;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/x-diff; charset=utf-8"),
+            );
+            headers.insert(
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_static(
+                    r#"attachment; filename="accepted-suggestions.patch""#,
+                ),
+            );
+            Ok((headers, patch).into_response())
+        }
+        AdminNav::GetOuttaHere(response) => Ok(response),
+        AdminNav::Err(e) => Err(e),
+    }
+}
+
+async fn build_patch(db: impl PgExecutor<'_> + Copy) -> Result<String> {
+    struct BookBlock {
+        id: i32,
+        type_id: i32,
+        content: String,
+    }
+    struct Accepted {
+        id: i32,
+        block_id: i32,
+        start_offset: i32,
+        end_offset: i32,
+        replacement: String,
+    }
+    struct Revision {
+        title: String,
+        source: Option<String>,
+    }
+    let Revision { title, source } = query_as!(
+        Revision,
+        "select b.title, r.source
+        from book b
+        join current_revision cr on cr.book_id = b.id
+        join book_revision r on r.id = cr.revision_id
+        where b.id = 1"
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "build_patch: revision"))?;
+    let blocks = query_as!(
+        BookBlock,
+        "select id, type_id, content from block
+        where book_revision_id = (
+            select revision_id from current_revision where book_id = 1
+        )
+        order by sequence",
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "build_patch: blocks"))?;
+    let accepted_id: i32 = SuggestionStatus::Accepted.into();
+    let accepted = query_as!(
+        Accepted,
+        "select s.id, s.block_id, s.start_offset, s.end_offset, s.replacement
+        from suggestion s
+        join block b on b.id = s.block_id
+        where
+            s.status_id = $1
+            and b.book_revision_id = (
+                select revision_id from current_revision where book_id = 1
+            )
+        order by s.reviewed_at",
+        accepted_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "build_patch: suggestions"))?;
+
+    let mut by_block: HashMap<i32, Vec<&Accepted>> = HashMap::new();
+    for s in &accepted {
+        by_block.entry(s.block_id).or_default().push(s);
+    }
+
+    // The edits which apply to each block, by the block's sequence.
+    let mut skipped: Vec<i32> = Vec::new();
+    let mut edits: Vec<(usize, Vec<Edit>)> = Vec::new();
+    for (sequence, block) in blocks.iter().enumerate() {
+        let Some(suggestions) = by_block.get(&block.id) else {
+            continue;
+        };
+        let block_edits: Vec<Edit> = suggestions
+            .iter()
+            .map(|s| Edit {
+                start: s.start_offset as usize,
+                end: s.end_offset as usize,
+                replacement: &s.replacement,
+            })
+            .collect();
+        let (_, skipped_here) = apply_edits(&block.content, &block_edits);
+        skipped.extend(skipped_here.iter().map(|i| suggestions[*i].id));
+        edits.push((
+            sequence,
+            block_edits
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| !skipped_here.contains(i))
+                .map(|(_, edit)| edit)
+                .collect(),
+        ));
+    }
+
+    // The manuscript only helps if it still parses into the blocks we have.
+    let manuscript = source.and_then(|source| {
+        let (_, parsed) = Book::parse_source(&source);
+        let matches = parsed.len() == blocks.len()
+            && parsed.iter().zip(&blocks).all(|(parsed, block)| {
+                i32::from(parsed.block.r#type) == block.type_id
+                    && parsed.block.content == block.content
+            });
+        matches.then_some((source, parsed))
+    });
+
+    let mut patch = String::from(if manuscript.is_some() {
+        "Accepted suggestions, as a patch against the manuscript. Apply it \
+        with `patch <manuscript> accepted-suggestions.patch`.\n"
+    } else {
+        "Accepted suggestions, as a patch against the book with one line per \
+        paragraph. We don't have the manuscript for this revision; import \
+        it again to get a patch against it.\n"
+    });
+    if !skipped.is_empty() {
+        patch.push_str(&format!(
+            "These accepted suggestions overlap others, and were left out: {}\n",
+            skipped
+                .iter()
+                .map(|id| format!("#{id}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    patch.push('\n');
+
+    let diff = match manuscript {
+        Some((source, parsed)) => {
+            let source_edits: Vec<_> = edits
+                .iter()
+                .flat_map(|(sequence, block_edits)| {
+                    source_edits(&parsed[*sequence], block_edits)
+                })
+                .collect();
+            patch_text("manuscript.txt", &source, &source_edits, 3)
+        }
+        None => {
+            let edits: HashMap<usize, &[Edit]> = edits
+                .iter()
+                .map(|(sequence, block_edits)| {
+                    (*sequence, block_edits.as_slice())
+                })
+                .collect();
+            let mut old_blocks = Vec::with_capacity(blocks.len());
+            let mut new_blocks = Vec::with_capacity(blocks.len());
+            for (sequence, block) in blocks.into_iter().enumerate() {
+                let r#type = block.type_id.try_into()?;
+                let content = match edits.get(&sequence) {
+                    Some(block_edits) => {
+                        apply_edits(&block.content, block_edits).0
+                    }
+                    None => block.content.clone(),
+                };
+                old_blocks.push(Block {
+                    r#type,
+                    content: block.content,
+                });
+                new_blocks.push(Block { r#type, content });
+            }
+            let lines = |blocks: Vec<Block>| -> Vec<String> {
+                Book {
+                    title: title.clone(),
+                    blocks,
+                }
+                .to_plain_text()
+                .lines()
+                .map(String::from)
+                .collect()
+            };
+            unified_diff("book.txt", &lines(old_blocks), &lines(new_blocks), 3)
+        }
+    };
+    patch.push_str(&diff);
+    Ok(patch)
+}
+
+struct Queue<'a> {
+    status: SuggestionStatus,
+    suggestions: &'a [Suggestion],
+}
+impl Component for Queue<'_> {
+    fn render(&self) -> String {
+        let home = Route::AdminHome;
+        let route = Route::AdminSuggestions;
+        let accepted = Route::AdminAcceptedSuggestions;
+        let tabs = SuggestionStatus::ALL.iter().fold(
+            String::new(),
+            |mut acc, status| {
+                let name = status.name();
+                if *status == self.status {
+                    acc.push_str(&format!("<span class=\"font-bold\">{name}</span>"));
+                } else {
+                    acc.push_str(&format!(
+                        r#"<a class="link" href="{route}?status={name}">{name}</a>"#
+                    ));
+                }
+                acc
+            },
+        );
+        let suggestions = if self.suggestions.is_empty() {
+            format!(
+                r#"<p class="italic">No {} suggestions.</p>"#,
+                self.status.name()
+            )
+        } else {
+            self.suggestions.iter().fold(String::new(), |mut acc, s| {
+                acc.push_str(
+                    &SuggestionCard {
+                        suggestion: s,
+                        status: self.status,
+                    }
+                    .render(),
+                );
+                acc
+            })
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-4 max-w-prose">
+                <a class="link" href="{home}">Admin Home</a>
+                <h1 class="text-xl">Suggested Edits</h1>
+                <div class="flex gap-2">{tabs}</div>
+                <a class="link" href="{accepted}">
+                    download accepted suggestions as a patch
+                </a>
+                {suggestions}
+            </div>
+            "#
+        )
+    }
+}
+
+struct SuggestionCard<'a> {
+    suggestion: &'a Suggestion,
+    status: SuggestionStatus,
+}
+impl Component for SuggestionCard<'_> {
+    fn render(&self) -> String {
+        let s = self.suggestion;
+        let id = s.id;
+        let reader_name = clean(&s.reader_name);
+        let chapter = clean(s.chapter.as_deref().unwrap_or(""));
+        let revision = s.book_revision_id;
+        let (suggested, _) = apply_edits(
+            &s.block_content,
+            &[Edit {
+                start: s.start_offset as usize,
+                end: s.end_offset as usize,
+                replacement: &s.replacement,
+            }],
+        );
+        let diff = word_diff(&s.block_content, &suggested).iter().fold(
+            String::new(),
+            |mut acc, op| {
+                match op {
+                    DiffOp::Same(text) => acc.push_str(&clean_text(text)),
+                    DiffOp::Removed(text) => acc.push_str(&format!(
+                        r#"<del class="bg-red-200 dark:bg-red-900">{}</del>"#,
+                        clean_text(text)
+                    )),
+                    DiffOp::Added(text) => acc.push_str(&format!(
+                        r#"<ins class="bg-green-200 dark:bg-green-900">{}</ins>"#,
+                        clean_text(text)
+                    )),
+                };
+                acc
+            },
+        );
+        let note = s
+            .note
+            .as_deref()
+            .map(|n| format!(r#"<p class="text-sm">note: {}</p>"#, clean(n)))
+            .unwrap_or_default();
+        let set_status = Route::AdminSuggestionStatus {
+            suggestion_id: Some(id),
+        };
+        let buttons = SuggestionStatus::ALL
+            .iter()
+            .filter(|status| **status != self.status)
+            .fold(String::new(), |mut acc, status| {
+                let name = status.name();
+                let label = match status {
+                    SuggestionStatus::Accepted => "accept",
+                    SuggestionStatus::Rejected => "reject",
+                    SuggestionStatus::Pending => "reopen",
+                };
+                acc.push_str(&format!(
+                    r##"
+                    <button
+                        class="bg-orange-500 text-white font-bold p-1 rounded"
+                        hx-post="{set_status}"
+                        hx-vals='{{"status": "{name}"}}'
+                        hx-target="body"
+                    >
+                        {label}
+                    </button>
+                    "##
+                ));
+                acc
+            });
+        format!(
+            r#"
+            <div class="flex flex-col gap-2 rounded bg-stone-200 dark:bg-stone-800 p-2">
+                <p class="text-sm">
+                    #{id} &middot; {reader_name} &middot; {chapter}
+                    &middot; revision {revision}
+                </p>
+                <p>{diff}</p>
+                {note}
+                <div class="flex gap-2">{buttons}</div>
+            </div>
+            "#
+        )
+    }
+}
@@ -0,0 +1,105 @@
+//! Following an invitation link signs the reader in; see
+//! [ides::invitation].
+
+use super::ui::{start_session, user_agent};
+use crate::{htmx, prelude::*};
+use ides::invitation::{redeem_invitation, InvitationCode};
+
+/// The invitation is only used up when the reader presses the button. Chat
+/// apps fetch links to show a preview, and that mustn't spend the
+/// invitation.
+pub async fn invite(Path(code): Path<String>) -> Result<impl IntoResponse> {
+    let code = InvitationCode::parse(code)?;
+    Ok(Page {
+        title: "You're invited",
+        children: &PageContainer {
+            children: &InvitePage { code: &code },
+        },
+    }
+    .render())
+}
+
+pub async fn handle_invite(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(code): Path<String>,
+) -> Result<Response> {
+    let auth =
+        match redeem_invitation(&db, &InvitationCode::parse(code)?).await? {
+            Some(token) => Some(Auth::get(&db, &token).await),
+            None => None,
+        };
+    match auth {
+        Some(AuthResult::Authenticated(auth)) => {
+            let cookie = start_session(
+                &db,
+                &session_key,
+                auth.token_id,
+                user_agent(&headers),
+            )
+            .await?;
+            Ok(
+                htmx::redirect(cookie, &Route::Book.as_string())
+                    .into_response(),
+            )
+        }
+        Some(AuthResult::Err(e)) => Err(e),
+        _ => Ok(Page {
+            title: "Invitation expired",
+            children: &PageContainer {
+                children: &ExpiredPage {},
+            },
+        }
+        .render()
+        .into_response()),
+    }
+}
+
+struct InvitePage<'a> {
+    code: &'a InvitationCode,
+}
+impl Component for InvitePage<'_> {
+    fn render(&self) -> String {
+        let route = clean_text(
+            &Route::Invite {
+                code: Some(self.code.display_secret_value().to_string()),
+            }
+            .as_string(),
+        );
+        format!(
+            r#"
+            <div class="flex flex-col items-center justify-center gap-4 h-[80vh]">
+                <h1 class="text-xl">You're invited to read The Ides of August</h1>
+                <form method="POST" action="{route}">
+                    <button
+                        class="bg-yellow-400 dark:bg-yellow-600 rounded p-2 font-bold"
+                    >
+                        start reading
+                    </button>
+                </form>
+            </div>
+            "#
+        )
+    }
+}
+
+struct ExpiredPage;
+impl Component for ExpiredPage {
+    fn render(&self) -> String {
+        let auth = Route::Auth;
+        format!(
+            r#"
+            <div class="flex flex-col items-center justify-center gap-4 h-[80vh]">
+                <h1 class="text-xl">This invitation has expired</h1>
+                <p class="max-w-prose">
+                    Invitation links only work once, and only for a week. Ask
+                    for a new one, or if you've already signed in on this
+                    device, <a class="link" href="{auth}">check your token</a>.
+                </p>
+            </div>
+            "#
+        )
+    }
+}
@@ -1,3 +1,5 @@
 //! Extremely simple auth based on distributing random tokens.
+pub mod invite;
+pub mod sessions;
 pub mod ui;
 pub use ides::auth::{Auth, AuthResult, Role};
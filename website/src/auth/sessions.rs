@@ -0,0 +1,211 @@
+//! The devices a reader is signed in on; see [ides::session]. Readers can
+//! sign any of them out, and so can the admin.
+
+use crate::{components::Saved, htmx, prelude::*};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use ides::session::SESSION_IDLE_LIFETIME;
+
+pub struct DisplaySession {
+    pub id: i32,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    user_agent: Option<String>,
+}
+
+/// The sessions of `token_id` which still work, most recently used first.
+pub async fn db_load_sessions(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+) -> Result<Vec<DisplaySession>> {
+    query_as!(
+        DisplaySession,
+        "select id, created_at, last_seen_at, user_agent
+        from session
+        where
+            token_id = $1
+            and revoked_at is null
+            and last_seen_at > now() - make_interval(days => $2)
+        order by last_seen_at desc",
+        token_id,
+        SESSION_IDLE_LIFETIME.num_days() as i32
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "db_load_sessions"))
+}
+
+/// Sign out `session_id`, as long as it belongs to `token_id`.
+pub async fn db_revoke_session(
+    db: impl PgExecutor<'_>,
+    session_id: i32,
+    token_id: i32,
+) -> Result<()> {
+    query!(
+        "update session set revoked_at = now()
+        where id = $1 and token_id = $2 and revoked_at is null",
+        session_id,
+        token_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "db_revoke_session"))?;
+    Ok(())
+}
+
+pub async fn sessions(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            Ok(render_sessions(&db, &auth).await?.into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+pub async fn handle_revoke_session(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(session_id): Path<i32>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            db_revoke_session(&db, session_id, auth.token_id).await?;
+            if auth.session_id == Some(session_id) {
+                return Ok(htmx::redirect(
+                    HeaderMap::new(),
+                    &Route::Auth.as_string(),
+                )
+                .into_response());
+            }
+            Ok([
+                render_sessions(&db, &auth).await?,
+                Saved {
+                    message: "device signed out",
+                }
+                .render(),
+            ]
+            .join("")
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+async fn render_sessions(
+    db: impl PgExecutor<'_>,
+    auth: &Auth,
+) -> Result<String> {
+    let sessions = db_load_sessions(db, auth.token_id).await?;
+    Ok(Page {
+        title: "Your devices",
+        children: &PageContainer {
+            children: &SessionsPage {
+                sessions: &sessions,
+                current: auth.session_id,
+            },
+        },
+    }
+    .render())
+}
+
+struct SessionsPage<'a> {
+    sessions: &'a [DisplaySession],
+    current: Option<i32>,
+}
+impl Component for SessionsPage<'_> {
+    fn render(&self) -> String {
+        let book = Route::Book;
+        let sessions = SessionList {
+            sessions: self.sessions,
+            current: self.current,
+            revoke: |session_id| Route::AuthSession {
+                session_id: Some(session_id),
+            },
+        }
+        .render();
+        format!(
+            r#"
+            <div class="flex flex-col gap-4 max-w-prose">
+                <a class="link" href="{book}">Back to the book</a>
+                <h1 class="text-xl">Your devices</h1>
+                <p class="text-sm">
+                    You're signed in on these devices. If you don't recognize
+                    one, sign it out. A device which isn't used for
+                    {days} days is signed out by itself.
+                </p>
+                {sessions}
+            </div>
+            "#,
+            days = SESSION_IDLE_LIFETIME.num_days()
+        )
+    }
+}
+
+/// Sessions, each with a button to sign it out at the route given by
+/// `revoke`.
+pub struct SessionList<'a> {
+    pub sessions: &'a [DisplaySession],
+    /// The session being used to look at the list, if any.
+    pub current: Option<i32>,
+    pub revoke: fn(i32) -> Route,
+}
+impl Component for SessionList<'_> {
+    fn render(&self) -> String {
+        if self.sessions.is_empty() {
+            return r#"<p class="italic">Not signed in anywhere.</p>"#.into();
+        }
+        let format_time = |time: &DateTime<Utc>| {
+            time.with_timezone(&Tz::America__New_York)
+                .format("%b %d, %Y %l:%M %p")
+                .to_string()
+        };
+        self.sessions.iter().fold(String::new(), |mut acc, s| {
+            let device =
+                clean_text(s.user_agent.as_deref().unwrap_or("unknown"));
+            let this_device = if self.current == Some(s.id) {
+                r#"<span class="font-bold">this device</span> &middot; "#
+            } else {
+                ""
+            };
+            let created_at = format_time(&s.created_at);
+            let last_seen_at = format_time(&s.last_seen_at);
+            let revoke = (self.revoke)(s.id);
+            acc.push_str(&format!(
+                r#"
+                <div class="flex items-center gap-2">
+                    <div class="flex-grow min-w-0">
+                        <p class="truncate" title="{device}">{device}</p>
+                        <p class="text-sm">
+                            {this_device}signed in {created_at} &middot; last
+                            used {last_seen_at}
+                        </p>
+                    </div>
+                    <button
+                        hx-delete="{revoke}"
+                        hx-target="body"
+                        class="bg-red-500 hover:bg-red-600 rounded p-2"
+                    >
+                        Sign out
+                    </button>
+                </div>
+                "#
+            ));
+            acc
+        })
+    }
+}
@@ -1,25 +1,40 @@
-use crate::{components::Saved, prelude::*};
+use crate::prelude::*;
 use axum::{http::HeaderValue, response::Redirect};
-use chrono::{Days, Utc};
-use ides::auth::{Auth, AuthResult, Token};
+use ides::{
+    auth::{Auth, AuthResult, Rejection, Token},
+    session::{
+        create_session, SessionCookie, SessionKey, SESSION_COOKIE,
+        SESSION_IDLE_LIFETIME,
+    },
+};
 
 #[derive(Default)]
 struct TokenForm<'a> {
     token: Option<&'a Token>,
-    indicate_token_is_invalid: bool,
+    rejection: Option<Rejection>,
 }
 impl Component for TokenForm<'_> {
     fn render(&self) -> String {
         let token_form_route = Route::Auth;
         let token = if let Some(token) = &self.token {
-            token.display_secret_value()
+            clean_text(token.display_secret_value())
         } else {
-            ""
+            String::new()
         };
-        let validation_msg = if self.indicate_token_is_invalid {
-            r#"<p class="text-red-500">token is not valid</p>"#
-        } else {
-            ""
+        let validation_msg = match self.rejection {
+            Some(Rejection::Unknown) => {
+                r#"<p class="text-red-500">token is not valid</p>"#
+            }
+            Some(Rejection::Revoked) => {
+                r#"<p class="text-red-500">token has been revoked</p>"#
+            }
+            Some(Rejection::Expired) => {
+                r#"<p class="text-red-500">token has expired</p>"#
+            }
+            Some(Rejection::SignedOut) => {
+                r#"<p class="text-red-500">you were signed out on this device</p>"#
+            }
+            None => "",
         };
         format!(
             r#"
@@ -41,51 +56,92 @@ pub struct Payload {
     token: String,
 }
 
-pub async fn post_handler(
-    State(AppState { db }): State<AppState>,
-    Form(Payload { token }): Form<Payload>,
-) -> Result<impl IntoResponse> {
+/// Headers which save `cookie` in the browser, and remove the token cookie
+/// which browsers kept before we had sessions.
+pub fn session_cookie(
+    cookie: &SessionCookie,
+    key: &SessionKey,
+) -> Result<HeaderMap> {
     let mut headers = HeaderMap::new();
-    let expiry_date = Utc::now()
-        .checked_add_days(Days::new(365))
-        .expect("heat death of the universe has not happened yet")
-        .format("%a, %d %b %Y %H:%M:%S %Z");
+    let max_age = SESSION_IDLE_LIFETIME.num_seconds();
+    let flags = "Path=/; Secure; HttpOnly; SameSite=Lax";
     let val = HeaderValue::from_str(&format!(
-        "token={token}; Path=/; HttpOnly; Expires={expiry_date}"
+        "{SESSION_COOKIE}={}; Max-Age={max_age}; {flags}",
+        cookie.display_secret_value(key)
     ))
     .map_err(|e| {
         ErrStack::new(ErrT::AuthNonUtf8Cookie)
-            .ctx(format!("submitted auth cookie is not utf-8: {e}"))
+            .ctx(format!("session cookie is not utf-8: {e}"))
     })?;
-    headers.insert("Set-Cookie", val);
+    headers.append("Set-Cookie", val);
+    headers.append(
+        "Set-Cookie",
+        HeaderValue::from_str(&format!("token=; Max-Age=0; {flags}"))
+            .expect("cookie flags are ascii"),
+    );
+    Ok(headers)
+}
+
+/// Start a session on this device, returning the headers which save it.
+pub async fn start_session(
+    db: impl PgExecutor<'_>,
+    key: &SessionKey,
+    token_id: i32,
+    user_agent: Option<&str>,
+) -> Result<HeaderMap> {
+    let cookie = create_session(db, token_id, user_agent).await?;
+    session_cookie(&cookie, key)
+}
 
+pub fn user_agent(headers: &HeaderMap) -> Option<&str> {
+    headers.get("User-Agent").and_then(|ua| ua.to_str().ok())
+}
+
+pub async fn post_handler(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Form(Payload { token }): Form<Payload>,
+) -> Result<impl IntoResponse> {
     let token = Token::new(token);
     match Auth::get(&db, &token).await {
-        AuthResult::Authenticated(_) => {
-            Ok((headers, Redirect::to(&Route::Book.as_string()))
-                .into_response())
+        AuthResult::Authenticated(auth) => {
+            let cookie = start_session(
+                &db,
+                &session_key,
+                auth.token_id,
+                user_agent(&headers),
+            )
+            .await?;
+            Ok(
+                (cookie, Redirect::to(&Route::Book.as_string()))
+                    .into_response(),
+            )
         }
-        AuthResult::NotAuthenticated => Ok((
-            headers,
-            [
-                Saved {
-                    message: "token updated",
-                }
-                .render(),
-                render_token_form(Some(&token), !token.is_empty()),
-            ]
-            .join(""),
+        AuthResult::NotAuthenticated(rejection) => Ok(render_token_form(
+            Some(&token),
+            (!token.is_empty()).then_some(rejection),
         )
-            .into_response()),
+        .into_response()),
         AuthResult::Err(e) => Err(e),
     }
 }
 
-pub async fn get_handler(headers: HeaderMap) -> Result<impl IntoResponse> {
-    match Token::parse_from_headers(&headers) {
-        Ok(token) => Ok(render_token_form(Some(&token), false)),
-        Err(e) => match e.peek() {
-            ErrT::AuthNotAuthenticated => Ok(render_token_form(None, false)),
+/// Readers whose token stops working are sent here, so we say why.
+pub async fn get_handler(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(_) => Ok(render_token_form(None, None)),
+        AuthResult::NotAuthenticated(rejection) => {
+            Ok(render_token_form(None, Some(rejection)))
+        }
+        AuthResult::Err(e) => match e.peek() {
+            ErrT::AuthNotAuthenticated => Ok(render_token_form(None, None)),
             _ => Err(e),
         },
     }
@@ -93,15 +149,12 @@ pub async fn get_handler(headers: HeaderMap) -> Result<impl IntoResponse> {
 
 fn render_token_form(
     token: Option<&Token>,
-    indicate_token_is_invalid: bool,
+    rejection: Option<Rejection>,
 ) -> String {
     Page {
         title: "Configure token",
         children: &PageContainer {
-            children: &TokenForm {
-                token,
-                indicate_token_is_invalid,
-            },
+            children: &TokenForm { token, rejection },
         },
     }
     .render()
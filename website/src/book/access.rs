@@ -1,17 +1,29 @@
 //! Access logging
 
+use super::ui::CurrentPosition;
 use crate::{auth::Auth, prelude::*};
+use ides::analytics::AccessEventType;
 
+/// Record that the reader arrived at `position` by way of `event`.
+/// `screen_area` is only known for requests from the paged reader.
 pub async fn log_access(
     auth: &Auth,
     db: impl PgExecutor<'_> + Copy,
-    page: i32,
+    event: AccessEventType,
+    position: &CurrentPosition,
+    screen_area: Option<i32>,
 ) -> Result<()> {
+    let event_type_id: i32 = event.into();
     query!(
-        "insert into access_log (page, token_id)
-        values ($1, $2)",
-        page,
-        auth.token_id
+        "insert into access_log
+        (page, token_id, event_type_id, block_id, revision_id, screen_area)
+        values ($1, $2, $3, $4, $5, $6)",
+        position.current_block_sequence,
+        auth.token_id,
+        event_type_id,
+        position.current_block_id,
+        position.book_revision_id,
+        screen_area
     )
     .execute(db)
     .await
@@ -0,0 +1,349 @@
+//! Named bookmarks. A reader can save any number of positions in the book
+//! and jump back to them later. Like the reader's current position,
+//! bookmarks are carried forward to new revisions of the book; see
+//! [crate::admin].
+
+use super::{
+    progress::jump_to,
+    ui::{get_current_position, render, CurrentPosition},
+};
+use crate::{book::ui::ScreenAreaParams, htmx, prelude::*};
+use axum::http::HeaderValue;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use ides::{
+    analytics::AccessEventType,
+    content::{char_slice, Progress},
+};
+
+/// How much of the bookmarked text to show in the list of bookmarks.
+const EXCERPT_CHARS: usize = 120;
+
+struct DisplayBookmark {
+    id: i32,
+    name: String,
+    created_at: DateTime<Utc>,
+    relocated_roughly: bool,
+    content: String,
+    block_offset: i32,
+    sequence: i32,
+    block_count: i64,
+}
+
+async fn list_bookmarks(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+) -> Result<Vec<DisplayBookmark>> {
+    query_as!(
+        DisplayBookmark,
+        r#"select
+            bm.id,
+            bm.name,
+            bm.created_at,
+            bm.relocated_roughly,
+            bm.block_offset,
+            bl.content,
+            bl.sequence,
+            (
+                select count(*) from block b
+                where b.book_revision_id = bl.book_revision_id
+            ) "block_count!"
+        from bookmark bm
+        join block bl on bl.id = bm.block_id
+        where bm.token_id = $1
+        order by bl.sequence, bm.block_offset, bm.created_at"#,
+        auth.token_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "list_bookmarks"))
+}
+
+struct BookmarksPage<'a> {
+    bookmarks: &'a [DisplayBookmark],
+}
+impl Component for BookmarksPage<'_> {
+    fn render(&self) -> String {
+        let book = Route::Book;
+        let create = Route::BookBookmarks;
+        let bookmarks = if self.bookmarks.is_empty() {
+            r#"<p class="italic">You don't have any bookmarks yet.</p>"#
+                .to_string()
+        } else {
+            self.bookmarks.iter().fold(String::new(), |mut acc, b| {
+                acc.push_str(&b.render());
+                acc
+            })
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-4 max-w-prose">
+                <a class="link" href="{book}">back to the book</a>
+                <h1 class="text-xl">Bookmarks</h1>
+                <form
+                    class="flex gap-2 items-end"
+                    hx-post="{create}"
+                    hx-target="body"
+                >
+                    <div class="flex flex-col flex-grow">
+                        <label for="name">Bookmark my current page as</label>
+                        <input
+                            class="dark:text-black"
+                            type="text"
+                            id="name"
+                            name="name"
+                            placeholder="Name"
+                        />
+                    </div>
+                    <button
+                        class="bg-orange-500 text-white font-bold p-2
+                        rounded"
+                    >
+                        save
+                    </button>
+                </form>
+                {bookmarks}
+            </div>
+            "#
+        )
+    }
+}
+
+impl Component for DisplayBookmark {
+    fn render(&self) -> String {
+        let jump = Route::BookBookmarkJump {
+            bookmark_id: Some(self.id),
+        };
+        let delete = Route::BookBookmark {
+            bookmark_id: Some(self.id),
+        };
+        let name = clean(&self.name);
+        let created_at = self
+            .created_at
+            .with_timezone(&Tz::America__New_York)
+            .format("%b %d, %Y");
+        let percent = Progress {
+            sequence: self.sequence,
+            block_count: self.block_count,
+        }
+        .percent()
+        .round();
+        let start = self.block_offset as usize;
+        let excerpt =
+            clean(char_slice(&self.content, start, start + EXCERPT_CHARS));
+        let rough = if self.relocated_roughly {
+            r#"<p class="text-sm italic text-yellow-600">
+                The book was revised, and we could only find roughly where
+                this bookmark used to be.
+            </p>"#
+        } else {
+            ""
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-1 rounded bg-stone-200 dark:bg-stone-800 p-2">
+                <div class="flex gap-2 items-baseline">
+                    <h2 class="font-bold flex-grow">{name}</h2>
+                    <p class="text-sm">{percent}% &middot; {created_at}</p>
+                </div>
+                <p class="italic">{excerpt}...</p>
+                {rough}
+                <div class="flex gap-2">
+                    <button
+                        class="link"
+                        hx-post="{jump}"
+                        hx-target="body"
+                    >
+                        jump here
+                    </button>
+                    <button
+                        class="link text-red-500"
+                        hx-delete="{delete}"
+                        hx-target="body"
+                    >
+                        delete
+                    </button>
+                </div>
+            </div>
+            "#
+        )
+    }
+}
+
+async fn render_bookmarks_page(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+) -> Result<String> {
+    let bookmarks = list_bookmarks(auth, db).await?;
+    Ok(Page {
+        title: "Bookmarks",
+        children: &PageContainer {
+            children: &BookmarksPage {
+                bookmarks: &bookmarks,
+            },
+        },
+    }
+    .render())
+}
+
+pub async fn bookmarks(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            Ok(render_bookmarks_page(&auth, &db).await?.into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Payload {
+    name: String,
+}
+
+pub async fn handle_create_bookmark(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Form(Payload { name }): Form<Payload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let position = get_current_position(&auth, &db).await?;
+            let name = if name.trim().is_empty() {
+                let percent = Progress::get(
+                    &db,
+                    position.book_revision_id,
+                    position.current_block_sequence,
+                )
+                .await?
+                .percent()
+                .round();
+                format!("{percent}% of the way through")
+            } else {
+                name.trim().to_string()
+            };
+            query!(
+                "insert into bookmark
+                (
+                    name,
+                    block_id,
+                    block_offset,
+                    token_id
+                ) values ($1, $2, $3, $4)",
+                name,
+                position.current_block_id,
+                position.current_block_offset,
+                auth.token_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_create_bookmark"))?;
+            Ok([
+                render_bookmarks_page(&auth, &db).await?,
+                Saved {
+                    message: "bookmark saved",
+                }
+                .render(),
+            ]
+            .join("")
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+pub async fn handle_delete_bookmark(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(bookmark_id): Path<i32>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            query!(
+                "delete from bookmark where id = $1 and token_id = $2",
+                bookmark_id,
+                auth.token_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_delete_bookmark"))?;
+            Ok(render_bookmarks_page(&auth, &db).await?.into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+pub async fn handle_bookmark_jump(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Query(screen_area): Query<ScreenAreaParams>,
+    Path(bookmark_id): Path<i32>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let position = query_as!(
+                CurrentPosition,
+                "select
+                    bl.id current_block_id,
+                    bl.book_revision_id,
+                    bl.sequence current_block_sequence,
+                    bm.block_offset current_block_offset
+                from bookmark bm
+                join block bl on bl.id = bm.block_id
+                where bm.id = $1 and bm.token_id = $2",
+                bookmark_id,
+                auth.token_id
+            )
+            .fetch_one(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_bookmark_jump"))?;
+            let current = get_current_position(&auth, &db).await?;
+            jump_to(&auth, &db, &current, &position).await?;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "Hx-Push-Url",
+                HeaderValue::from_str(&Route::Book.as_string())
+                    .expect("book route is ASCII"),
+            );
+            Ok((
+                headers,
+                render(
+                    &auth,
+                    &db,
+                    &position,
+                    &screen_area,
+                    AccessEventType::Jump,
+                )
+                .await?,
+            )
+                .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
@@ -1,37 +1,53 @@
-use crate::{book::ui::ScreenAreaParams, htmx, prelude::*};
+use super::{
+    highlight::quote_range,
+    reaction::{BlockReactions, ReactionBar},
+    sharing::{
+        can_see_shared_thread, get_share_comments, shared_threads, ShareSwitch,
+    },
+    thread::{
+        block_threads, insert_reply, mark_replies_seen, reader_threads,
+        render_reader_threads, Thread,
+    },
+};
+use crate::{
+    book::ui::ScreenAreaParams, components::QuotedBlock, htmx, prelude::*,
+};
 use axum::{extract::Query, http::HeaderValue};
+use ides::{analytics::AccessEventType, auth::Role};
+
+/// Comments may optionally be about a span of text within the block; the
+/// client's selection toolbar passes the character offsets of the reader's
+/// selection.
+#[derive(Deserialize)]
+pub struct QuoteParams {
+    start: Option<usize>,
+    end: Option<usize>,
+}
+
+impl QuoteParams {
+    fn span(&self) -> Option<(usize, usize)> {
+        self.start.zip(self.end)
+    }
+}
 
 pub async fn comment(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     Path(block_id): Path<i32>,
+    Query(quote): Query<QuoteParams>,
     headers: HeaderMap,
 ) -> Result<Response> {
-    match Auth::from_headers(&db, &headers).await {
-        AuthResult::Authenticated(_) => {
-            struct Qres {
-                content: String,
-            }
-            let Qres { content } = query_as!(
-                Qres,
-                "select content from block where id = $1",
-                block_id
-            )
-            .fetch_one(&db)
-            .await
-            .map_err(|e| ErrStack::sqlx(&e, "render comment form"))?;
-            Ok(Page {
-                title: "Comment",
-                children: &PageContainer {
-                    children: &CommentForm {
-                        block_id,
-                        block_content: &content,
-                    },
-                },
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            if let Some((start, end)) = quote.span() {
+                quote_range(&db, block_id, start, end).await?;
             }
-            .render()
-            .into_response())
+            Ok(render_comment_page(&auth, &db, block_id, quote.span())
+                .await?
+                .into_response())
         }
-        AuthResult::NotAuthenticated => {
+        AuthResult::NotAuthenticated(_) => {
             Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
                 .into_response())
         }
@@ -39,26 +55,152 @@ pub async fn comment(
     }
 }
 
+/// The comment form for a block, followed by the reader's existing threads
+/// on it, and any threads shared with them by other readers. Viewing the
+/// threads marks replies in them as seen. The admin sees every reader's
+/// threads on the block instead.
+async fn render_comment_page(
+    auth: &Auth,
+    db: impl PgExecutor<'_> + Copy,
+    block_id: i32,
+    span: Option<(usize, usize)>,
+) -> Result<String> {
+    struct Qres {
+        content: String,
+    }
+    let Qres { content } =
+        query_as!(Qres, "select content from block where id = $1", block_id)
+            .fetch_one(db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "render comment form"))?;
+    let reactions = BlockReactions::get(auth, db, block_id).await?;
+    let everyone = auth.role == Role::Admin;
+    let threads = if everyone {
+        block_threads(db, block_id).await?
+    } else {
+        reader_threads(db, auth.token_id, Some(block_id)).await?
+    };
+    let shared = if !everyone && get_share_comments(auth, db).await? {
+        shared_threads(db, auth.token_id, block_id).await?
+    } else {
+        Vec::new()
+    };
+    mark_replies_seen(db, auth.token_id, Some(block_id)).await?;
+    Ok(Page {
+        title: "Comment",
+        children: &PageContainer {
+            children: &CommentPage {
+                form: &CommentForm {
+                    block_id,
+                    block_content: &content,
+                    span,
+                },
+                reactions: &ReactionBar {
+                    block_id,
+                    reactions: &reactions,
+                    compact: false,
+                },
+                threads: &threads,
+                shared: &shared,
+                everyone,
+                viewer_id: auth.token_id,
+            },
+        },
+    }
+    .render())
+}
+
+struct CommentPage<'a> {
+    form: &'a CommentForm<'a>,
+    reactions: &'a ReactionBar<'a>,
+    threads: &'a [Thread],
+    shared: &'a [Thread],
+    /// The threads are everyone's, rather than just the reader's own.
+    everyone: bool,
+    viewer_id: i32,
+}
+impl Component for CommentPage<'_> {
+    fn render(&self) -> String {
+        let book = Route::Book;
+        let form = self.form.render();
+        let reactions = self.reactions.render();
+        let threads = if self.threads.is_empty() {
+            String::new()
+        } else {
+            let heading = if self.everyone {
+                "Comments here"
+            } else {
+                "Your comments here"
+            };
+            format!(
+                r#"
+                <h2 class="text-lg">{heading}</h2>
+                {}
+                "#,
+                render_reader_threads(self.threads, false, self.viewer_id)
+            )
+        };
+        let shared = if self.shared.is_empty() {
+            String::new()
+        } else {
+            format!(
+                r#"
+                <h2 class="text-lg">Other readers' comments</h2>
+                {}
+                "#,
+                render_reader_threads(self.shared, false, self.viewer_id)
+            )
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-2 max-w-prose">
+                <a class="link" href="{book}">back to the book</a>
+                {reactions}
+                {form}
+                {threads}
+                {shared}
+            </div>
+            "#
+        )
+    }
+}
+
 struct CommentForm<'a> {
     block_id: i32,
     block_content: &'a str,
+    span: Option<(usize, usize)>,
 }
 impl Component for CommentForm<'_> {
     fn render(&self) -> String {
         let comment = Route::BookComment {
             block_id: Some(self.block_id),
         };
-        let block_content = clean(self.block_content);
+        let block_content = QuotedBlock {
+            content: self.block_content,
+            span: self.span,
+        }
+        .render();
+        let suggest = Route::BookSuggestion {
+            block_id: Some(self.block_id),
+        };
+        let span_inputs = match self.span {
+            Some((start, end)) => format!(
+                r#"
+                <input type="hidden" name="start" value="{start}" />
+                <input type="hidden" name="end" value="{end}" />
+                "#
+            ),
+            None => String::new(),
+        };
         format!(
             r#"
-            <form class="flex flex-col gap-2" hx-post="{comment}">
+            <form class="flex flex-col gap-2" hx-post="{comment}" hx-target="body">
                 <h1 class="text-xl">Leave a Comment</h1>
-                <blockquote
-                    class="italic border-l-2 border-stone-300
-                    dark:border-stone-700 pl-2"
-                >
-                    {block_content}
-                </blockquote>
+                {block_content}
+                <a class="link text-sm self-start" href="{suggest}">
+                    spotted a typo? suggest an edit instead
+                </a>
+                {span_inputs}
                 <label for="comment">comment</label>
                 <textarea
                     id="comment"
@@ -79,20 +221,44 @@ impl Component for CommentForm<'_> {
 #[derive(Debug, Deserialize)]
 pub struct Payload {
     pub comment: String,
+    pub start: Option<usize>,
+    pub end: Option<usize>,
 }
 
 pub async fn handle_comment(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
     Query(screen_area): Query<ScreenAreaParams>,
     Path(block_id): Path<i32>,
     Form(payload): Form<Payload>,
 ) -> Result<Response> {
-    match Auth::from_headers(&db, &headers).await {
+    match Auth::from_headers(&db, &session_key, &headers).await {
         AuthResult::Authenticated(auth) => {
+            let (quote, quote_start, quote_end) =
+                match payload.start.zip(payload.end) {
+                    Some((start, end)) => (
+                        Some(quote_range(&db, block_id, start, end).await?),
+                        Some(start as i32),
+                        Some(end as i32),
+                    ),
+                    None => (None, None, None),
+                };
             query!(
-                "insert into comment (comment, block_id, token_id) values ($1, $2, $3)",
+                "insert into comment
+                (
+                    comment,
+                    quote,
+                    quote_start,
+                    quote_end,
+                    block_id,
+                    token_id
+                ) values ($1, $2, $3, $4, $5, $6)",
                 payload.comment,
+                quote,
+                quote_start,
+                quote_end,
                 block_id,
                 auth.token_id
             )
@@ -115,6 +281,7 @@ pub async fn handle_comment(
                         &db,
                         &super::ui::get_current_position(&auth, &db).await?,
                         &screen_area,
+                        AccessEventType::Comment,
                     )
                     .await?,
                     Saved {
@@ -126,10 +293,371 @@ pub async fn handle_comment(
             )
                 .into_response())
         }
-        AuthResult::NotAuthenticated => {
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReplyPayload {
+    comment: String,
+}
+
+/// Readers may reply within threads they started, or which other readers
+/// have shared with them; the admin may reply in any thread.
+pub async fn handle_comment_reply(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(comment_id): Path<i32>,
+    Form(ReplyPayload { comment }): Form<ReplyPayload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let owner_id = if auth.role == Role::Admin
+                || can_see_shared_thread(&db, auth.token_id, comment_id).await?
+            {
+                None
+            } else {
+                Some(auth.token_id)
+            };
+            let block_id = insert_reply(
+                &db,
+                auth.token_id,
+                comment_id,
+                owner_id,
+                &comment,
+            )
+            .await?;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "Hx-Push-Url",
+                HeaderValue::from_str(
+                    &Route::BookComment {
+                        block_id: Some(block_id),
+                    }
+                    .as_string(),
+                )
+                .expect("comment route is ASCII"),
+            );
+            Ok((
+                headers,
+                render_comment_page(&auth, &db, block_id, None).await?,
+            )
+                .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+/// Show the comment page for `block_id` with a confirmation, after changing
+/// one of the reader's comments on it.
+async fn render_comment_changed(
+    auth: &Auth,
+    db: impl PgExecutor<'_> + Copy,
+    block_id: i32,
+    message: &str,
+) -> Result<Response> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Hx-Push-Url",
+        HeaderValue::from_str(
+            &Route::BookComment {
+                block_id: Some(block_id),
+            }
+            .as_string(),
+        )
+        .expect("comment route is ASCII"),
+    );
+    Ok((
+        headers,
+        [
+            render_comment_page(auth, db, block_id, None).await?,
+            Saved { message }.render(),
+        ]
+        .join(""),
+    )
+        .into_response())
+}
+
+pub async fn comment_edit(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    Path(comment_id): Path<i32>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            struct Qres {
+                comment: String,
+                block_id: i32,
+            }
+            let Qres { comment, block_id } = query_as!(
+                Qres,
+                "select comment, block_id from comment
+                where
+                    id = $1
+                    and token_id = $2
+                    and deleted_at is null",
+                comment_id,
+                auth.token_id
+            )
+            .fetch_optional(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "comment_edit"))?
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::NotFound).ctx(format!(
+                    "comment_edit: no comment {comment_id} of token {}",
+                    auth.token_id
+                ))
+            })?;
+            Ok(Page {
+                title: "Edit Comment",
+                children: &PageContainer {
+                    children: &CommentEditForm {
+                        comment_id,
+                        block_id,
+                        comment: &comment,
+                    },
+                },
+            }
+            .render()
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+struct CommentEditForm<'a> {
+    comment_id: i32,
+    block_id: i32,
+    comment: &'a str,
+}
+impl Component for CommentEditForm<'_> {
+    fn render(&self) -> String {
+        let edit = Route::BookCommentEdit {
+            comment_id: Some(self.comment_id),
+        };
+        let back = Route::BookComment {
+            block_id: Some(self.block_id),
+        };
+        let comment = clean(self.comment);
+        format!(
+            r#"
+            <form
+                class="flex flex-col gap-2 max-w-prose"
+                hx-post="{edit}"
+                hx-target="body"
+            >
+                <a class="link" href="{back}">cancel</a>
+                <h1 class="text-xl">Edit Comment</h1>
+                <textarea
+                    class="dark:text-black"
+                    name="comment"
+                    rows="4"
+                    required
+                >{comment}</textarea>
+                <button
+                    class="bg-orange-500 text-white font-bold self-start p-2 m-2
+                    rounded"
+                >
+                    save
+                </button>
+            </form>
+            "#
+        )
+    }
+}
+
+/// Replace the text of one of the reader's own comments, keeping the old
+/// text in its edit history.
+pub async fn handle_comment_edit(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(comment_id): Path<i32>,
+    Form(ReplyPayload { comment }): Form<ReplyPayload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            struct Qres {
+                block_id: i32,
+            }
+            let Qres { block_id } = query_as!(
+                Qres,
+                "with old as (
+                    select id, comment from comment
+                    where
+                        id = $1
+                        and token_id = $2
+                        and deleted_at is null
+                ),
+                history as (
+                    insert into comment_edit (comment_id, comment)
+                    select id, comment from old
+                )
+                update comment c
+                set comment = $3, edited_at = now()
+                from old
+                where c.id = old.id
+                returning c.block_id",
+                comment_id,
+                auth.token_id,
+                comment
+            )
+            .fetch_optional(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_comment_edit"))?
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::NotFound).ctx(format!(
+                    "handle_comment_edit: no comment {comment_id} of token {}",
+                    auth.token_id
+                ))
+            })?;
+            render_comment_changed(&auth, &db, block_id, "comment updated")
+                .await
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+/// Delete one of the reader's own comments. The comment stays in place so
+/// that replies to it still make sense, but its text is moved into the edit
+/// history, where only the admin can see it.
+pub async fn handle_delete_comment(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(comment_id): Path<i32>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            struct Qres {
+                block_id: i32,
+            }
+            let Qres { block_id } = query_as!(
+                Qres,
+                "with old as (
+                    select id, comment from comment
+                    where
+                        id = $1
+                        and token_id = $2
+                        and deleted_at is null
+                ),
+                history as (
+                    insert into comment_edit (comment_id, comment)
+                    select id, comment from old
+                )
+                update comment c
+                set comment = '', deleted_at = now()
+                from old
+                where c.id = old.id
+                returning c.block_id",
+                comment_id,
+                auth.token_id
+            )
+            .fetch_optional(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_delete_comment"))?
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::NotFound).ctx(format!(
+                    "handle_delete_comment: no comment {comment_id} of token {}",
+                    auth.token_id
+                ))
+            })?;
+            render_comment_changed(&auth, &db, block_id, "comment deleted")
+                .await
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+/// All of the reader's threads, with the most recent activity first.
+pub async fn my_comments(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let threads = reader_threads(&db, auth.token_id, None).await?;
+            mark_replies_seen(&db, auth.token_id, None).await?;
+            let sharing = get_share_comments(&auth, &db).await?;
+            Ok(Page {
+                title: "My Comments",
+                children: &PageContainer {
+                    children: &MyComments {
+                        threads: &threads,
+                        viewer_id: auth.token_id,
+                        sharing,
+                    },
+                },
+            }
+            .render()
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
             Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
                 .into_response())
         }
         AuthResult::Err(e) => Err(e),
     }
 }
+
+struct MyComments<'a> {
+    threads: &'a [Thread],
+    viewer_id: i32,
+    sharing: bool,
+}
+impl Component for MyComments<'_> {
+    fn render(&self) -> String {
+        let book = Route::Book;
+        let share_switch = ShareSwitch {
+            sharing: self.sharing,
+        }
+        .render();
+        let threads = if self.threads.is_empty() {
+            r#"<p class="italic">
+                You haven't left any comments yet. Tap the &#128172; button
+                beside a paragraph in the book to comment on it.
+            </p>"#
+                .to_string()
+        } else {
+            render_reader_threads(self.threads, true, self.viewer_id)
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-4 max-w-prose">
+                <a class="link" href="{book}">back to the book</a>
+                <h1 class="text-xl">My Comments</h1>
+                {share_switch}
+                {threads}
+            </div>
+            "#
+        )
+    }
+}
@@ -0,0 +1,591 @@
+//! Highlights; a reader can mark a range of text within a block, optionally
+//! with a note. The client-side selection toolbar in `htmx_extras.js` sends
+//! the reader here with the character offsets of their selection. Highlights
+//! are carried forward to new revisions of the book by searching for the
+//! quoted text; see [ides::highlight].
+
+use super::{
+    progress::jump_to,
+    ui::{get_current_position, render, CurrentPosition, ScreenAreaParams},
+};
+use crate::{htmx, prelude::*};
+use axum::http::HeaderValue;
+use ides::{
+    analytics::AccessEventType,
+    content::{char_slice, Progress, SequencedBlock},
+};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightColor {
+    #[default]
+    Yellow,
+    Green,
+    Blue,
+    Pink,
+}
+
+impl HighlightColor {
+    const ALL: [Self; 4] = [Self::Yellow, Self::Green, Self::Blue, Self::Pink];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Yellow => "yellow",
+            Self::Green => "green",
+            Self::Blue => "blue",
+            Self::Pink => "pink",
+        }
+    }
+    fn class(&self) -> &'static str {
+        match self {
+            Self::Yellow => "bg-yellow-200 dark:bg-yellow-700",
+            Self::Green => "bg-green-200 dark:bg-green-800",
+            Self::Blue => "bg-sky-200 dark:bg-sky-800",
+            Self::Pink => "bg-pink-200 dark:bg-pink-800",
+        }
+    }
+}
+
+impl From<HighlightColor> for i32 {
+    fn from(val: HighlightColor) -> Self {
+        match val {
+            HighlightColor::Yellow => 1,
+            HighlightColor::Green => 2,
+            HighlightColor::Blue => 3,
+            HighlightColor::Pink => 4,
+        }
+    }
+}
+
+impl TryInto<HighlightColor> for i32 {
+    type Error = ErrStack;
+    fn try_into(self) -> Result<HighlightColor> {
+        match self {
+            1 => Ok(HighlightColor::Yellow),
+            2 => Ok(HighlightColor::Green),
+            3 => Ok(HighlightColor::Blue),
+            4 => Ok(HighlightColor::Pink),
+            _ => Err(ErrStack::new(ErrT::Invariant)
+                .ctx(format!("{self} is not a valid i32 for HighlightColor"))),
+        }
+    }
+}
+
+/// Highlights have no color until the reader picks one; they're drawn in the
+/// default color.
+fn color_of(color_id: Option<i32>) -> Result<HighlightColor> {
+    color_id.map_or(Ok(HighlightColor::default()), |id| id.try_into())
+}
+
+pub struct Highlight {
+    pub id: i32,
+    pub block_id: i32,
+    pub start_offset: i32,
+    pub end_offset: i32,
+    pub color_id: Option<i32>,
+}
+
+impl Highlight {
+    pub fn color(&self) -> Result<HighlightColor> {
+        color_of(self.color_id)
+    }
+    pub fn class(&self) -> &'static str {
+        self.color().unwrap_or_default().class()
+    }
+}
+
+/// The reader's highlights within some set of blocks, for rendering.
+pub struct Highlights(Vec<Highlight>);
+
+impl Highlights {
+    pub async fn get(
+        auth: &Auth,
+        db: impl PgExecutor<'_>,
+        blocks: &[SequencedBlock],
+    ) -> Result<Self> {
+        let block_ids: Vec<i32> = blocks.iter().map(|b| b.id).collect();
+        query_as!(
+            Highlight,
+            "select id, block_id, start_offset, end_offset, color_id
+            from highlight
+            where
+                token_id = $1
+                and block_id = any($2)
+                and not detached
+            order by created_at",
+            auth.token_id,
+            &block_ids
+        )
+        .fetch_all(db)
+        .await
+        .map(Self)
+        .map_err(|e| ErrStack::sqlx(&e, "Highlights::get"))
+    }
+    pub fn for_block(&self, block_id: i32) -> Vec<&Highlight> {
+        self.0.iter().filter(|h| h.block_id == block_id).collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RangeParams {
+    start: usize,
+    end: usize,
+}
+
+struct BlockContent {
+    content: String,
+}
+
+/// The text between `start` and `end` in the given block, or a validation
+/// error if the range does not fit within it.
+pub async fn quote_range(
+    db: impl PgExecutor<'_>,
+    block_id: i32,
+    start: usize,
+    end: usize,
+) -> Result<String> {
+    let BlockContent { content } = query_as!(
+        BlockContent,
+        "select content from block where id = $1",
+        block_id
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "quote_range"))?;
+    if start >= end || end > content.chars().count() {
+        return Err(ErrStack::new(ErrT::ValidationError).ctx(format!(
+            "{start}..{end} is not a valid range in block {block_id}"
+        )));
+    }
+    Ok(char_slice(&content, start, end).to_string())
+}
+
+pub async fn highlight(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(block_id): Path<i32>,
+    Query(RangeParams { start, end }): Query<RangeParams>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(_) => {
+            let quote = quote_range(&db, block_id, start, end).await?;
+            Ok(Page {
+                title: "Highlight",
+                children: &PageContainer {
+                    children: &HighlightForm {
+                        block_id,
+                        start,
+                        end,
+                        quote: &quote,
+                    },
+                },
+            }
+            .render()
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+struct HighlightForm<'a> {
+    block_id: i32,
+    start: usize,
+    end: usize,
+    quote: &'a str,
+}
+impl Component for HighlightForm<'_> {
+    fn render(&self) -> String {
+        let route = Route::BookHighlight {
+            block_id: Some(self.block_id),
+        };
+        let book = Route::Book;
+        let start = self.start;
+        let end = self.end;
+        let quote = clean(self.quote);
+        let colors =
+            HighlightColor::ALL
+                .iter()
+                .fold(String::new(), |mut acc, color| {
+                    let name = color.name();
+                    let class = color.class();
+                    let checked = if *color == HighlightColor::default() {
+                        "checked"
+                    } else {
+                        ""
+                    };
+                    acc.push_str(&format!(
+                        r#"
+                        <label class="{class} rounded px-2">
+                            <input
+                                type="radio"
+                                name="color"
+                                value="{name}"
+                                {checked}
+                            />
+                            {name}
+                        </label>
+                        "#
+                    ));
+                    acc
+                });
+        format!(
+            r#"
+            <form class="flex flex-col gap-2" hx-post="{route}">
+                <a class="link" href="{book}">back to the book</a>
+                <h1 class="text-xl">Highlight</h1>
+                <input type="hidden" name="start" value="{start}" />
+                <input type="hidden" name="end" value="{end}" />
+                <blockquote
+                    class="italic border-l-2 border-stone-300
+                    dark:border-stone-700 pl-2"
+                >
+                    {quote}
+                </blockquote>
+                <div class="flex gap-2">{colors}</div>
+                <label for="note">note (optional)</label>
+                <textarea id="note" name="note"></textarea>
+                <button
+                    class="bg-orange-500 text-white font-bold self-start p-2 m-2
+                    rounded"
+                >
+                    save
+                </button>
+            </form>
+            "#
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Payload {
+    start: usize,
+    end: usize,
+    color: HighlightColor,
+    note: String,
+}
+
+pub async fn handle_highlight(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Query(screen_area): Query<ScreenAreaParams>,
+    Path(block_id): Path<i32>,
+    Form(payload): Form<Payload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let quote =
+                quote_range(&db, block_id, payload.start, payload.end).await?;
+            let color_id: i32 = payload.color.into();
+            let note = payload.note.trim();
+            let note = if note.is_empty() { None } else { Some(note) };
+            query!(
+                "insert into highlight
+                (
+                    start_offset,
+                    end_offset,
+                    quote,
+                    note,
+                    color_id,
+                    block_id,
+                    token_id
+                ) values ($1, $2, $3, $4, $5, $6, $7)",
+                payload.start as i32,
+                payload.end as i32,
+                quote,
+                note,
+                color_id,
+                block_id,
+                auth.token_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_highlight"))?;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "Hx-Push-Url",
+                HeaderValue::from_str(&Route::Book.as_string())
+                    .expect("book route is ASCII"),
+            );
+            Ok((
+                headers,
+                [
+                    render(
+                        &auth,
+                        &db,
+                        &get_current_position(&auth, &db).await?,
+                        &screen_area,
+                        AccessEventType::PageView,
+                    )
+                    .await?,
+                    Saved {
+                        message: "highlight saved",
+                    }
+                    .render(),
+                ]
+                .join(""),
+            )
+                .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+struct DisplayHighlight {
+    id: i32,
+    quote: String,
+    note: Option<String>,
+    color_id: Option<i32>,
+    relocated_roughly: bool,
+    detached: bool,
+    sequence: i32,
+    block_count: i64,
+}
+
+async fn list_highlights(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+) -> Result<Vec<DisplayHighlight>> {
+    query_as!(
+        DisplayHighlight,
+        r#"select
+            h.id,
+            h.quote,
+            h.note,
+            h.color_id,
+            h.relocated_roughly,
+            h.detached,
+            bl.sequence,
+            (
+                select count(*) from block b
+                where b.book_revision_id = bl.book_revision_id
+            ) "block_count!"
+        from highlight h
+        join block bl on bl.id = h.block_id
+        where h.token_id = $1
+        order by bl.sequence, h.start_offset, h.created_at"#,
+        auth.token_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "list_highlights"))
+}
+
+struct HighlightsPage<'a> {
+    highlights: &'a [DisplayHighlight],
+}
+impl Component for HighlightsPage<'_> {
+    fn render(&self) -> String {
+        let book = Route::Book;
+        let highlights = if self.highlights.is_empty() {
+            r#"<p class="italic">
+                You don't have any highlights yet. Select some text in the
+                book to highlight it.
+            </p>"#
+                .to_string()
+        } else {
+            self.highlights.iter().fold(String::new(), |mut acc, h| {
+                acc.push_str(&h.render());
+                acc
+            })
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-4 max-w-prose">
+                <a class="link" href="{book}">back to the book</a>
+                <h1 class="text-xl">My Highlights</h1>
+                {highlights}
+            </div>
+            "#
+        )
+    }
+}
+
+impl Component for DisplayHighlight {
+    fn render(&self) -> String {
+        let jump = Route::BookHighlightJump {
+            highlight_id: Some(self.id),
+        };
+        let delete = Route::BookHighlightDelete {
+            highlight_id: Some(self.id),
+        };
+        let class = color_of(self.color_id).unwrap_or_default().class();
+        let quote = clean(&self.quote);
+        let note = self
+            .note
+            .as_ref()
+            .map(|n| format!("<p>{}</p>", clean(n)))
+            .unwrap_or_default();
+        let percent = Progress {
+            sequence: self.sequence,
+            block_count: self.block_count,
+        }
+        .percent()
+        .round();
+        let status = if self.detached {
+            r#"<p class="text-sm italic text-red-500">
+                The book was revised, and we couldn't find this passage in
+                the new version.
+            </p>"#
+        } else if self.relocated_roughly {
+            r#"<p class="text-sm italic text-yellow-600">
+                The book was revised, and this passage changed a little.
+            </p>"#
+        } else {
+            ""
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-1 rounded bg-stone-200 dark:bg-stone-800 p-2">
+                <p class="text-sm">{percent}%</p>
+                <blockquote><mark class="{class}">{quote}</mark></blockquote>
+                {note}
+                {status}
+                <div class="flex gap-2">
+                    <button
+                        class="link"
+                        hx-post="{jump}"
+                        hx-target="body"
+                    >
+                        jump here
+                    </button>
+                    <button
+                        class="link text-red-500"
+                        hx-delete="{delete}"
+                        hx-target="body"
+                    >
+                        delete
+                    </button>
+                </div>
+            </div>
+            "#
+        )
+    }
+}
+
+async fn render_highlights_page(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+) -> Result<String> {
+    let highlights = list_highlights(auth, db).await?;
+    Ok(Page {
+        title: "My Highlights",
+        children: &PageContainer {
+            children: &HighlightsPage {
+                highlights: &highlights,
+            },
+        },
+    }
+    .render())
+}
+
+pub async fn highlights(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            Ok(render_highlights_page(&auth, &db).await?.into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+pub async fn handle_delete_highlight(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(highlight_id): Path<i32>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            query!(
+                "delete from highlight where id = $1 and token_id = $2",
+                highlight_id,
+                auth.token_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_delete_highlight"))?;
+            Ok(render_highlights_page(&auth, &db).await?.into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+pub async fn handle_highlight_jump(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Query(screen_area): Query<ScreenAreaParams>,
+    Path(highlight_id): Path<i32>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let position = query_as!(
+                CurrentPosition,
+                "select
+                    bl.id current_block_id,
+                    bl.book_revision_id,
+                    bl.sequence current_block_sequence,
+                    h.start_offset current_block_offset
+                from highlight h
+                join block bl on bl.id = h.block_id
+                where h.id = $1 and h.token_id = $2",
+                highlight_id,
+                auth.token_id
+            )
+            .fetch_one(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_highlight_jump"))?;
+            let current = get_current_position(&auth, &db).await?;
+            jump_to(&auth, &db, &current, &position).await?;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "Hx-Push-Url",
+                HeaderValue::from_str(&Route::Book.as_string())
+                    .expect("book route is ASCII"),
+            );
+            Ok((
+                headers,
+                render(
+                    &auth,
+                    &db,
+                    &position,
+                    &screen_area,
+                    AccessEventType::Jump,
+                )
+                .await?,
+            )
+                .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
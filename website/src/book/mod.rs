@@ -1,10 +1,37 @@
 //! The book!
 
 mod access;
+mod bookmark;
 mod comment;
+mod highlight;
 mod page;
+mod progress;
+mod reaction;
+mod scroll;
+mod sharing;
+mod suggestion;
+mod thread;
 mod ui;
 
-pub use comment::{comment, handle_comment};
+pub use bookmark::{
+    bookmarks, handle_bookmark_jump, handle_create_bookmark,
+    handle_delete_bookmark,
+};
+pub use comment::{
+    comment, comment_edit, handle_comment, handle_comment_edit,
+    handle_comment_reply, handle_delete_comment, my_comments,
+};
+pub use highlight::{
+    handle_delete_highlight, handle_highlight, handle_highlight_jump,
+    highlight, highlights,
+};
 pub use page::{next_page, prev_page};
-pub use ui::ui;
+pub use progress::{
+    handle_jump, handle_jump_back, handle_start_over, remember_position,
+};
+pub use reaction::handle_reaction;
+pub use scroll::{handle_reading_mode, handle_scroll_position, scroll_section};
+pub use sharing::handle_share_comments;
+pub use suggestion::{handle_suggestion, suggest};
+pub use thread::{insert_reply, threads_by_root, Thread, ThreadView};
+pub use ui::{position_at, ui, CurrentPosition};
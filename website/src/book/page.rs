@@ -19,7 +19,19 @@
 //! # Normal Pagination Strategy
 //!
 //! Normally, we can simply iterate through blocks in sequence order; easy
-//! peasy.
+//! peasy. How many blocks fit on a page depends on the reader's screen, so
+//! page boundaries are computed on the server from the same character budget
+//! that we render with (see [ides::content::char_budget]).
+//!
+//! Pages are laid out greedily from the start of each section (see
+//! [ides::content::Section]), so the same screen always produces the same
+//! page boundaries. Paragraphs which don't fit are split across pages at a
+//! sentence or word boundary, so the reader's position is a block plus a
+//! character offset into it. "Next page" begins right after the last text
+//! which was shown, and "previous page" lands on the start of the page before
+//! the one containing the reader's position. If the reader's position is
+//! somewhere in the middle of a page (for example, because they resized their
+//! window), we show the page which contains it.
 //!
 //! # Content Update Strategy
 //!
@@ -85,28 +97,41 @@
 //! Otherwise, we simply calculate the % of progress through the whole book,
 //! and grab a block from revision B at the same position.
 //!
+//! This is implemented by [ides::revision::RevisionMap]. Reader positions
+//! (and everything else which points at a block, like bookmarks) are carried
+//! forward when the admin changes the current revision.
+//!
 //! # Content Update Notification
 //!
 //! No matter which type of page update we perform, we'll provide the readers
 //! with notifications after content updates, letting them know which type
 //! of page-mapping was performed.
 
-use super::ui::{
-    get_current_position, render, CurrentPosition, ScreenAreaParams,
+use super::{
+    sharing::record_furthest,
+    ui::{
+        get_current_position, position_at, render, save_position,
+        CurrentPosition, ScreenAreaParams,
+    },
 };
 use crate::{htmx, prelude::*};
-use ides::content::{Direction, PAGE_SIZE};
+use ides::{
+    analytics::AccessEventType,
+    content::{char_budget, page_containing, Direction, Position, Section},
+};
 
 pub async fn next_page(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
     Query(params): Query<ScreenAreaParams>,
 ) -> Result<Response> {
-    match Auth::from_headers(&db, &headers).await {
+    match Auth::from_headers(&db, &session_key, &headers).await {
         AuthResult::Authenticated(auth) => {
             change_page(&auth, &db, Direction::Forward, params).await
         }
-        AuthResult::NotAuthenticated => {
+        AuthResult::NotAuthenticated(_) => {
             Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
                 .into_response())
         }
@@ -115,15 +140,17 @@ pub async fn next_page(
 }
 
 pub async fn prev_page(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
     Query(params): Query<ScreenAreaParams>,
 ) -> Result<Response> {
-    match Auth::from_headers(&db, &headers).await {
+    match Auth::from_headers(&db, &session_key, &headers).await {
         AuthResult::Authenticated(auth) => {
             change_page(&auth, &db, Direction::Back, params).await
         }
-        AuthResult::NotAuthenticated => {
+        AuthResult::NotAuthenticated(_) => {
             Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
                 .into_response())
         }
@@ -138,32 +165,18 @@ async fn change_page(
     screen_area: ScreenAreaParams,
 ) -> Result<Response> {
     let position = get_current_position(auth, db).await?;
-    let diff = match direction {
-        Direction::Back => -PAGE_SIZE,
-        Direction::Forward => PAGE_SIZE,
+    let new_start = match direction {
+        Direction::Back => prev_page_start(db, &position, &screen_area).await?,
+        Direction::Forward => {
+            next_page_start(db, &position, &screen_area).await?
+        }
+    };
+    let new_position = match new_start {
+        Some(start) => {
+            position_at(db, Some(position.book_revision_id), start).await?
+        }
+        None => None,
     };
-    let new_seq = position.current_block_sequence + diff;
-    let new_position = query_as!(
-        CurrentPosition,
-        "select
-            id current_block_id,
-            book_revision_id,
-            sequence current_block_sequence
-        from block
-        where
-            sequence = $1
-            and book_revision_id = (
-                select revision_id
-                from current_revision
-                where book_id = 1
-            )
-        order by sequence
-        limit 1",
-        new_seq
-    )
-    .fetch_optional(db)
-    .await
-    .map_err(|e| ErrStack::sqlx(&e, "change_page"))?;
 
     match new_position {
         None => {
@@ -171,24 +184,79 @@ async fn change_page(
             Ok("done".into_response())
         }
         Some(new_position) => {
-            query!(
-                "insert into current_block (token_id, block_id)
-                values ($1, $2)
-                on conflict (token_id)
-                do update set
-                block_id = $2",
-                auth.token_id,
-                new_position.current_block_id
-            )
-            .execute(db)
-            .await
-            .map_err(|e| {
-                ErrStack::sqlx(&e, "change_page; saving new position")
+            save_position(auth, db, &new_position).await.map_err(|e| {
+                e.wrap(ErrT::BookUi)
+                    .ctx("change_page; saving new position".into())
             })?;
+            record_furthest(auth, db, &new_position).await?;
 
-            Ok(render(auth, db, &new_position, &screen_area)
+            let event = match direction {
+                Direction::Forward => AccessEventType::Next,
+                Direction::Back => AccessEventType::Previous,
+            };
+            Ok(render(auth, db, &new_position, &screen_area, event)
                 .await?
                 .into_response())
         }
     }
 }
+
+/// The next page begins immediately after the last text which was shown on
+/// the reader's current page. Since pages never span sections, the page after
+/// the last page of a section is the first block of the next section.
+async fn next_page_start(
+    db: impl PgExecutor<'_> + Copy,
+    position: &CurrentPosition,
+    screen_area: &ScreenAreaParams,
+) -> Result<Option<Position>> {
+    let section = Section::get(
+        db,
+        position.book_revision_id,
+        position.current_block_sequence,
+    )
+    .await?;
+    let pages = section.paginate(char_budget(screen_area.screen_area));
+    let Some(i) = page_containing(&pages, position.as_position()) else {
+        return Ok(None);
+    };
+    Ok(match pages.get(i + 1) {
+        Some(next) => next.start(),
+        None => pages[i].last_sequence().map(|seq| Position {
+            sequence: seq + 1,
+            offset: 0,
+        }),
+    })
+}
+
+/// The previous page begins where the page before the reader's current page
+/// began. On the first page of a section, that's the last page of the
+/// section before.
+async fn prev_page_start(
+    db: impl PgExecutor<'_> + Copy,
+    position: &CurrentPosition,
+    screen_area: &ScreenAreaParams,
+) -> Result<Option<Position>> {
+    let budget = char_budget(screen_area.screen_area);
+    let section = Section::get(
+        db,
+        position.book_revision_id,
+        position.current_block_sequence,
+    )
+    .await?;
+    let pages = section.paginate(budget);
+    match page_containing(&pages, position.as_position()) {
+        Some(i) if i > 0 => Ok(pages[i - 1].start()),
+        _ => match section.first_sequence() {
+            Some(first) if first > 0 => {
+                let prev_section =
+                    Section::get(db, position.book_revision_id, first - 1)
+                        .await?;
+                Ok(prev_section
+                    .paginate(budget)
+                    .last()
+                    .and_then(|page| page.start()))
+            }
+            _ => Ok(None),
+        },
+    }
+}
@@ -0,0 +1,319 @@
+//! Progress through the book, and jumping to an arbitrary point in it.
+//! Before each jump, we remember where the reader was, so that they can go
+//! back.
+
+use super::ui::{
+    get_current_position, position_at, render, save_position, CurrentPosition,
+    ScreenAreaParams,
+};
+use crate::{htmx, prelude::*};
+use ides::{
+    analytics::AccessEventType,
+    content::{sequence_at_percent, Position, Progress},
+};
+
+/// How many earlier positions we keep for each reader.
+const POSITION_HISTORY_LIMIT: i64 = 10;
+
+/// Remember that the reader `token_id` was at `position`, before they're
+/// moved somewhere else.
+pub async fn remember_position(
+    db: impl PgExecutor<'_> + Copy,
+    token_id: i32,
+    position: &CurrentPosition,
+) -> Result<()> {
+    query!(
+        "insert into position_history (token_id, block_id, block_offset)
+        values ($1, $2, $3)",
+        token_id,
+        position.current_block_id,
+        position.current_block_offset
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "remember_position: insert"))?;
+    query!(
+        "delete from position_history
+        where
+            token_id = $1
+            and id not in (
+                select id from position_history
+                where token_id = $1
+                order by created_at desc, id desc
+                limit $2
+            )",
+        token_id,
+        POSITION_HISTORY_LIMIT
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "remember_position: prune"))?;
+    Ok(())
+}
+
+pub async fn has_position_history(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+) -> Result<bool> {
+    struct Qres {
+        exists: bool,
+    }
+    let Qres { exists } = query_as!(
+        Qres,
+        r#"select exists (
+            select 1 from position_history where token_id = $1
+        ) "exists!""#,
+        auth.token_id
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "has_position_history"))?;
+    Ok(exists)
+}
+
+/// Move the reader from `current` to `target`, remembering where they were.
+pub async fn jump_to(
+    auth: &Auth,
+    db: impl PgExecutor<'_> + Copy,
+    current: &CurrentPosition,
+    target: &CurrentPosition,
+) -> Result<()> {
+    if current.current_block_id != target.current_block_id
+        || current.current_block_offset != target.current_block_offset
+    {
+        remember_position(db, auth.token_id, current).await?;
+    }
+    save_position(auth, db, target).await
+}
+
+pub struct ProgressBar<'a> {
+    pub progress: &'a Progress,
+    /// Whether the reader has a position to go back to.
+    pub can_go_back: bool,
+}
+impl Component for ProgressBar<'_> {
+    fn render(&self) -> String {
+        let jump = Route::BookJump;
+        let start_over = Route::BookStartOver;
+        let jump_back = Route::BookJumpBack;
+        let back = if self.can_go_back {
+            format!(
+                r##"
+                <button
+                    type="button"
+                    class="link text-sm"
+                    hx-post="{jump_back}"
+                    hx-target="#reader-container"
+                >
+                    back to before my last jump
+                </button>
+                "##
+            )
+        } else {
+            String::new()
+        };
+        let percent = self.progress.percent().round();
+        format!(
+            r##"
+            <progress
+                class="block w-full h-1 accent-yellow-500"
+                max="100"
+                value="{percent}"
+            >{percent}%</progress>
+            <form
+                class="flex items-center gap-2 bg-stone-300 dark:bg-stone-700
+                px-2"
+                hx-post="{jump}"
+                hx-target="#reader-container"
+                hx-trigger="change"
+            >
+                <label class="sr-only" for="percent">jump to percentage</label>
+                <input
+                    class="flex-grow accent-yellow-500"
+                    id="percent"
+                    name="percent"
+                    type="range"
+                    min="0"
+                    max="100"
+                    step="0.5"
+                    value="{percent}"
+                />
+                <p class="text-sm w-12 text-right">{percent}%</p>
+                {back}
+                <button
+                    type="button"
+                    class="link text-sm"
+                    hx-post="{start_over}"
+                    hx-target="#reader-container"
+                    hx-confirm="Go back to the start of the book?"
+                >
+                    start over
+                </button>
+            </form>
+            "##
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JumpPayload {
+    percent: f64,
+}
+
+/// Move the reader to the block nearest to `percent` of the way through the
+/// revision they're reading.
+pub async fn handle_jump(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Query(screen_area): Query<ScreenAreaParams>,
+    Form(JumpPayload { percent }): Form<JumpPayload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let current = get_current_position(&auth, &db).await?;
+            let Progress { block_count, .. } = Progress::get(
+                &db,
+                current.book_revision_id,
+                current.current_block_sequence,
+            )
+            .await?;
+            let target = position_at(
+                &db,
+                Some(current.book_revision_id),
+                Position {
+                    sequence: sequence_at_percent(block_count, percent),
+                    offset: 0,
+                },
+            )
+            .await?;
+            let position = match target {
+                Some(target) => {
+                    jump_to(&auth, &db, &current, &target).await?;
+                    target
+                }
+                None => current,
+            };
+            Ok(render(
+                &auth,
+                &db,
+                &position,
+                &screen_area,
+                AccessEventType::Jump,
+            )
+            .await?
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+/// Go back to the beginning of the revision being read.
+pub async fn handle_start_over(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Query(screen_area): Query<ScreenAreaParams>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let current = get_current_position(&auth, &db).await?;
+            let position = match position_at(
+                &db,
+                Some(current.book_revision_id),
+                Position {
+                    sequence: 0,
+                    offset: 0,
+                },
+            )
+            .await?
+            {
+                Some(start) => {
+                    jump_to(&auth, &db, &current, &start).await?;
+                    start
+                }
+                None => current,
+            };
+            Ok(render(
+                &auth,
+                &db,
+                &position,
+                &screen_area,
+                AccessEventType::Jump,
+            )
+            .await?
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+/// Return the reader to where they were before their most recent jump. The
+/// position is used up, so going back again goes to the jump before that.
+pub async fn handle_jump_back(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Query(screen_area): Query<ScreenAreaParams>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let previous = query_as!(
+                CurrentPosition,
+                "with popped as (
+                    delete from position_history
+                    where id = (
+                        select id from position_history
+                        where token_id = $1
+                        order by created_at desc, id desc
+                        limit 1
+                    )
+                    returning block_id, block_offset
+                )
+                select
+                    bl.id current_block_id,
+                    bl.book_revision_id,
+                    bl.sequence current_block_sequence,
+                    p.block_offset current_block_offset
+                from popped p
+                join block bl on bl.id = p.block_id",
+                auth.token_id
+            )
+            .fetch_optional(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_jump_back"))?;
+            let position = match previous {
+                Some(previous) => {
+                    save_position(&auth, &db, &previous).await?;
+                    previous
+                }
+                None => get_current_position(&auth, &db).await?,
+            };
+            Ok(render(
+                &auth,
+                &db,
+                &position,
+                &screen_area,
+                AccessEventType::Jump,
+            )
+            .await?
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
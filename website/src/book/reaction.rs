@@ -0,0 +1,225 @@
+//! Quick reactions to a block; see [ides::reaction]. Reactions are given
+//! from buttons beside each block in the book, or from the block's comment
+//! page, and tapping a reaction again takes it back.
+
+use crate::{htmx, prelude::*};
+use ides::{
+    auth::Role,
+    content::SequencedBlock,
+    reaction::{tally, ReactionType},
+};
+use std::collections::HashMap;
+
+/// The reactions on a block which the reader has given, and for the admin,
+/// how many of each reaction the block has in total.
+#[derive(Default)]
+pub struct BlockReactions {
+    mine: Vec<ReactionType>,
+    totals: Option<[(ReactionType, i64); 4]>,
+}
+
+impl BlockReactions {
+    pub async fn get(
+        auth: &Auth,
+        db: impl PgExecutor<'_>,
+        block_id: i32,
+    ) -> Result<Self> {
+        let mut reactions = load(auth, db, &[block_id]).await?;
+        Ok(reactions.remove(&block_id).unwrap_or_default())
+    }
+}
+
+/// [BlockReactions] for each block on a page of the book.
+pub struct PageReactions(HashMap<i32, BlockReactions>);
+
+impl PageReactions {
+    pub async fn get(
+        auth: &Auth,
+        db: impl PgExecutor<'_>,
+        blocks: &[SequencedBlock],
+    ) -> Result<Self> {
+        let block_ids: Vec<i32> = blocks.iter().map(|b| b.id).collect();
+        Ok(Self(load(auth, db, &block_ids).await?))
+    }
+    pub fn for_block(&self, block_id: i32) -> Option<&BlockReactions> {
+        self.0.get(&block_id)
+    }
+}
+
+/// Blocks without any reactions are left out.
+async fn load(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+    block_ids: &[i32],
+) -> Result<HashMap<i32, BlockReactions>> {
+    struct Qres {
+        block_id: i32,
+        type_id: i32,
+        count: i64,
+        mine: bool,
+    }
+    let rows = query_as!(
+        Qres,
+        r#"select
+            block_id,
+            type_id,
+            count(*) "count!",
+            bool_or(token_id = $2) "mine!"
+        from reaction
+        where block_id = any($1)
+        group by block_id, type_id"#,
+        block_ids,
+        auth.token_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "reaction::load"))?;
+    let mut by_block: HashMap<i32, Vec<Qres>> = HashMap::new();
+    for row in rows {
+        by_block.entry(row.block_id).or_default().push(row);
+    }
+    by_block
+        .into_iter()
+        .map(|(block_id, rows)| {
+            let mine = rows
+                .iter()
+                .filter(|r| r.mine)
+                .map(|r| r.type_id.try_into())
+                .collect::<Result<Vec<ReactionType>>>()?;
+            let totals = (auth.role == Role::Admin)
+                .then(|| tally(rows.iter().map(|r| (r.type_id, r.count))));
+            Ok((block_id, BlockReactions { mine, totals }))
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct ReactionPayload {
+    reaction: ReactionType,
+    /// Set by the buttons beside blocks in the book, which are smaller.
+    #[serde(default)]
+    compact: bool,
+}
+
+/// Toggle one of the reader's reactions on a block. If two taps arrive at
+/// once, the second one's insert finds the first one's row and takes the
+/// reaction back, just as if they'd arrived one after the other.
+pub async fn handle_reaction(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(block_id): Path<i32>,
+    Form(ReactionPayload { reaction, compact }): Form<ReactionPayload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let type_id: i32 = reaction.into();
+            let added = query!(
+                "insert into reaction (token_id, block_id, type_id)
+                values ($1, $2, $3)
+                on conflict (token_id, block_id, type_id) do nothing",
+                auth.token_id,
+                block_id,
+                type_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_reaction: add"))?
+            .rows_affected()
+                > 0;
+            if !added {
+                query!(
+                    "delete from reaction
+                    where token_id = $1 and block_id = $2 and type_id = $3",
+                    auth.token_id,
+                    block_id,
+                    type_id
+                )
+                .execute(&db)
+                .await
+                .map_err(|e| ErrStack::sqlx(&e, "handle_reaction: remove"))?;
+            }
+            let reactions = BlockReactions::get(&auth, &db, block_id).await?;
+            Ok(ReactionBar {
+                block_id,
+                reactions: &reactions,
+                compact,
+            }
+            .render()
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+pub struct ReactionBar<'a> {
+    pub block_id: i32,
+    pub reactions: &'a BlockReactions,
+    /// Small, faint buttons to sit beside the block in the book.
+    pub compact: bool,
+}
+impl Component for ReactionBar<'_> {
+    fn render(&self) -> String {
+        let block_id = self.block_id;
+        let route = Route::BookReaction {
+            block_id: Some(block_id),
+        };
+        let compact = self.compact;
+        let size = if compact {
+            "text-xs px-1 opacity-60 hover:opacity-100"
+        } else {
+            "px-2 py-1"
+        };
+        let buttons = ReactionType::ALL.iter().fold(
+            String::new(),
+            |mut acc, reaction| {
+                let name = reaction.name();
+                let emoji = reaction.emoji();
+                let label = reaction.label();
+                let pressed = self.reactions.mine.contains(reaction);
+                let style = if pressed {
+                    "bg-orange-200 dark:bg-orange-800"
+                } else {
+                    "bg-stone-200 dark:bg-stone-800"
+                };
+                let total = self
+                    .reactions
+                    .totals
+                    .and_then(|totals| {
+                        totals.iter().find(|(r, _)| r == reaction).copied()
+                    })
+                    .map(|(_, count)| format!(" {count}"))
+                    .unwrap_or_default();
+                acc.push_str(&format!(
+                    r##"
+                        <button
+                            class="rounded {size} {style}"
+                            title="{label}"
+                            aria-pressed="{pressed}"
+                            hx-post="{route}"
+                            hx-vals='{{"reaction": "{name}", "compact": {compact}}}'
+                            hx-target="#reactions-{block_id}"
+                            hx-swap="outerHTML"
+                        >
+                            {emoji}{total}
+                        </button>
+                        "##
+                ));
+                acc
+            },
+        );
+        let gap = if compact { "gap-1" } else { "gap-2" };
+        format!(
+            r#"
+            <div id="reactions-{block_id}" class="flex {gap} items-center">
+                {buttons}
+            </div>
+            "#
+        )
+    }
+}
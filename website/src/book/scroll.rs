@@ -0,0 +1,336 @@
+//! Continuous scroll reading mode. Instead of turning pages, the reader gets
+//! whole sections in one scrollable column, and the next section is fetched
+//! as they near the bottom; or the previous one, as they near the top. The
+//! block at the top of their screen is recorded as their position, so
+//! switching back to paged mode resumes at the same spot.
+
+use super::{
+    access::log_access,
+    highlight::Highlights,
+    reaction::PageReactions,
+    sharing::record_furthest,
+    thread::CommentMarkers,
+    ui::{
+        get_current_position, render, render_slices, save_position,
+        CurrentPosition, ScreenAreaParams,
+    },
+};
+use crate::{htmx, prelude::*};
+use ides::{
+    analytics::AccessEventType,
+    content::{BlockSlice, Section, SequencedBlock},
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadingMode {
+    Paged,
+    Scroll,
+}
+
+impl From<ReadingMode> for i32 {
+    fn from(val: ReadingMode) -> Self {
+        match val {
+            ReadingMode::Paged => 1,
+            ReadingMode::Scroll => 2,
+        }
+    }
+}
+
+impl TryInto<ReadingMode> for i32 {
+    type Error = ErrStack;
+    fn try_into(self) -> Result<ReadingMode> {
+        match self {
+            1 => Ok(ReadingMode::Paged),
+            2 => Ok(ReadingMode::Scroll),
+            _ => Err(ErrStack::new(ErrT::Invariant)
+                .ctx(format!("{self} is not a valid i32 for ReadingMode"))),
+        }
+    }
+}
+
+pub async fn get_reading_mode(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+) -> Result<ReadingMode> {
+    struct Qres {
+        reading_mode_id: i32,
+    }
+    let Qres { reading_mode_id } = query_as!(
+        Qres,
+        "select reading_mode_id from token where id = $1",
+        auth.token_id
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "get_reading_mode"))?;
+    reading_mode_id.try_into()
+}
+
+#[derive(Deserialize)]
+pub struct ModePayload {
+    mode: ReadingMode,
+}
+
+pub async fn handle_reading_mode(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Query(screen_area): Query<ScreenAreaParams>,
+    Form(ModePayload { mode }): Form<ModePayload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let mode_id: i32 = mode.into();
+            query!(
+                "update token set reading_mode_id = $1 where id = $2",
+                mode_id,
+                auth.token_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_reading_mode"))?;
+            let position = get_current_position(&auth, &db).await?;
+            Ok(render(
+                &auth,
+                &db,
+                &position,
+                &screen_area,
+                AccessEventType::PageView,
+            )
+            .await?
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollDirection {
+    Up,
+    #[default]
+    Down,
+}
+
+#[derive(Deserialize)]
+pub struct ScrollParams {
+    #[serde(default)]
+    direction: ScrollDirection,
+}
+
+/// Fetch the section containing `sequence`, for the reader to scroll into
+/// from the given direction.
+pub async fn scroll_section(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(sequence): Path<i32>,
+    Query(ScrollParams { direction }): Query<ScrollParams>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let position = get_current_position(&auth, &db).await?;
+            let section =
+                Section::get(&db, position.book_revision_id, sequence).await?;
+            let highlights =
+                Highlights::get(&auth, &db, &section.blocks).await?;
+            let markers =
+                CommentMarkers::get(&auth, &db, &section.blocks).await?;
+            let reactions =
+                PageReactions::get(&auth, &db, &section.blocks).await?;
+            Ok(ScrollSection {
+                blocks: &section.blocks,
+                highlights: &highlights,
+                markers: &markers,
+                reactions: &reactions,
+                load_previous: direction == ScrollDirection::Up,
+                load_next: direction == ScrollDirection::Down,
+            }
+            .render()
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PositionPayload {
+    block_id: i32,
+}
+
+/// The client reports the block at the top of the reader's screen as they
+/// scroll. Reports are throttled on the client, so we just save them; but
+/// we only log an access event when the reader scrolls into another section,
+/// which is the nearest thing to turning a page.
+pub async fn handle_scroll_position(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Form(PositionPayload { block_id }): Form<PositionPayload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let current = get_current_position(&auth, &db).await?;
+            struct Qres {
+                sequence: i32,
+                section: i32,
+                previous_section: Option<i32>,
+            }
+            let target = query_as!(
+                Qres,
+                "select
+                    b.sequence,
+                    b.section,
+                    (
+                        select section from block where id = $3
+                    ) previous_section
+                from block b
+                where b.id = $1 and b.book_revision_id = $2",
+                block_id,
+                current.book_revision_id,
+                current.current_block_id
+            )
+            .fetch_optional(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_scroll_position"))?
+            .ok_or_else(|| {
+                ErrStack::new(ErrT::ValidationError).ctx(format!(
+                    "block {block_id} is not in the revision being read"
+                ))
+            })?;
+            let position = CurrentPosition {
+                current_block_id: block_id,
+                book_revision_id: current.book_revision_id,
+                current_block_sequence: target.sequence,
+                current_block_offset: 0,
+            };
+            save_position(&auth, &db, &position).await?;
+            record_furthest(&auth, &db, &position).await?;
+            if target.previous_section != Some(target.section) {
+                log_access(
+                    &auth,
+                    &db,
+                    AccessEventType::Scroll,
+                    &position,
+                    None,
+                )
+                .await?;
+            }
+            Ok("".into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+pub struct ScrollColumn<'a> {
+    pub blocks: &'a [SequencedBlock],
+    pub highlights: &'a Highlights,
+    pub markers: &'a CommentMarkers,
+    pub reactions: &'a PageReactions,
+    /// The client scrolls this block into view when the column loads.
+    pub current_block_id: i32,
+}
+impl Component for ScrollColumn<'_> {
+    fn render(&self) -> String {
+        let current_block_id = self.current_block_id;
+        let position = Route::BookScrollPosition;
+        let section = ScrollSection {
+            blocks: self.blocks,
+            highlights: self.highlights,
+            markers: self.markers,
+            reactions: self.reactions,
+            load_previous: true,
+            load_next: true,
+        }
+        .render();
+        format!(
+            r#"
+            <div
+                id="scroll-column"
+                data-current-block="{current_block_id}"
+                data-position-url="{position}"
+            >
+                {section}
+            </div>
+            "#
+        )
+    }
+}
+
+/// A section of blocks, with placeholders which fetch the previous and next
+/// sections once they scroll into view.
+struct ScrollSection<'a> {
+    blocks: &'a [SequencedBlock],
+    highlights: &'a Highlights,
+    markers: &'a CommentMarkers,
+    reactions: &'a PageReactions,
+    load_previous: bool,
+    load_next: bool,
+}
+impl Component for ScrollSection<'_> {
+    fn render(&self) -> String {
+        let (Some(first), Some(last)) =
+            (self.blocks.first(), self.blocks.last())
+        else {
+            return String::new();
+        };
+        let slices: Vec<BlockSlice> =
+            self.blocks.iter().map(BlockSlice::whole).collect();
+        let blocks = render_slices(
+            &slices,
+            self.highlights,
+            self.markers,
+            self.reactions,
+        );
+        let previous = if self.load_previous && first.sequence > 0 {
+            let route = Route::BookScroll {
+                sequence: Some(first.sequence - 1),
+            };
+            format!(
+                r#"
+                <div
+                    hx-get="{route}?direction=up"
+                    hx-trigger="intersect once"
+                    data-scroll-up="true"
+                >
+                    <p class="italic text-sm">loading...</p>
+                </div>
+                "#
+            )
+        } else {
+            String::new()
+        };
+        // The server returns nothing past the end of the book.
+        let next = if self.load_next {
+            let route = Route::BookScroll {
+                sequence: Some(last.sequence + 1),
+            };
+            format!(
+                r#"
+                <div hx-get="{route}" hx-trigger="intersect once">
+                    <p class="italic text-sm">loading...</p>
+                </div>
+                "#
+            )
+        } else {
+            String::new()
+        };
+        format!("{previous}{blocks}{next}")
+    }
+}
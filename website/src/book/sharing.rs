@@ -0,0 +1,184 @@
+//! Shared comments. Readers who opt in can see each other's comments, and
+//! reply to them; but only on blocks at or before the furthest point they
+//! have read to, so that nobody is spoiled by comments on later chapters. The
+//! `shared_comment` view decides which comments each reader may see.
+
+use super::{
+    thread::{threads_by_root, Thread},
+    ui::CurrentPosition,
+};
+use crate::{htmx, prelude::*};
+
+pub async fn get_share_comments(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+) -> Result<bool> {
+    struct Qres {
+        share_comments: bool,
+    }
+    query_as!(
+        Qres,
+        "select share_comments from token where id = $1",
+        auth.token_id
+    )
+    .fetch_one(db)
+    .await
+    .map(|r| r.share_comments)
+    .map_err(|e| ErrStack::sqlx(&e, "get_share_comments"))
+}
+
+/// Record that the reader has read up to `position`, unless they've already
+/// read further. Only called as the reader turns pages or scrolls; jumping
+/// ahead doesn't count as reading.
+pub async fn record_furthest(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+    position: &CurrentPosition,
+) -> Result<()> {
+    query!(
+        "insert into furthest_block (token_id, block_id)
+        values ($1, $2)
+        on conflict (token_id)
+        do update set block_id = $2
+        where (
+            select (b.book_revision_id <> $3 or b.sequence < $4)
+            from block b
+            where b.id = furthest_block.block_id
+        )",
+        auth.token_id,
+        position.current_block_id,
+        position.book_revision_id,
+        position.current_block_sequence
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "record_furthest"))?;
+    Ok(())
+}
+
+/// Other readers' threads on a block which the reader may see.
+pub async fn shared_threads(
+    db: impl PgExecutor<'_> + Copy,
+    token_id: i32,
+    block_id: i32,
+) -> Result<Vec<Thread>> {
+    struct Qres {
+        id: i32,
+    }
+    let ids: Vec<i32> = query_as!(
+        Qres,
+        "select c.id
+        from shared_comment sc
+        join comment c on c.id = sc.comment_id
+        where sc.viewer_id = $1 and c.block_id = $2
+        order by c.created_at",
+        token_id,
+        block_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "shared_threads"))?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+    threads_by_root(db, &ids, false).await
+}
+
+/// Whether the reader may reply in the thread started by `root_id` of
+/// another reader.
+pub async fn can_see_shared_thread(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+    root_id: i32,
+) -> Result<bool> {
+    struct Qres {
+        visible: bool,
+    }
+    query_as!(
+        Qres,
+        r#"select exists (
+            select 1 from shared_comment
+            where viewer_id = $1 and comment_id = $2
+        ) "visible!""#,
+        token_id,
+        root_id
+    )
+    .fetch_one(db)
+    .await
+    .map(|r| r.visible)
+    .map_err(|e| ErrStack::sqlx(&e, "can_see_shared_thread"))
+}
+
+#[derive(Deserialize)]
+pub struct SharePayload {
+    share: bool,
+}
+
+pub async fn handle_share_comments(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Form(SharePayload { share }): Form<SharePayload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            query!(
+                "update token set share_comments = $1 where id = $2",
+                share,
+                auth.token_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_share_comments"))?;
+            Ok(ShareSwitch { sharing: share }.render().into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+pub struct ShareSwitch {
+    pub sharing: bool,
+}
+impl Component for ShareSwitch {
+    fn render(&self) -> String {
+        let route = Route::BookCommentSharing;
+        let (share, status, label) = if self.sharing {
+            (
+                false,
+                "You're sharing comments with other readers who share theirs.
+                You'll only see comments on parts of the book you've already
+                read.",
+                "stop sharing",
+            )
+        } else {
+            (
+                true,
+                "Your comments are private. If you share them, you'll also see
+                comments from other readers who share theirs, on parts of the
+                book you've already read.",
+                "share my comments",
+            )
+        };
+        format!(
+            r##"
+            <div id="share-switch" class="flex flex-col gap-1">
+                <p class="text-sm">{status}</p>
+                <button
+                    class="link self-start"
+                    hx-post="{route}"
+                    hx-vals='{{"share": {share}}}'
+                    hx-target="#share-switch"
+                    hx-swap="outerHTML"
+                >
+                    {label}
+                </button>
+            </div>
+            "##
+        )
+    }
+}
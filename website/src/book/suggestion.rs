@@ -0,0 +1,225 @@
+//! Suggested edits; a reader can propose replacement text for a block, or
+//! for a range within it, from the selection toolbar or the comment page.
+//! The admin reviews them in `admin::suggestions`; see [ides::suggestion].
+
+use super::{
+    highlight::quote_range,
+    ui::{get_current_position, render, ScreenAreaParams},
+};
+use crate::{components::QuotedBlock, htmx, prelude::*};
+use axum::http::HeaderValue;
+use ides::analytics::AccessEventType;
+
+/// The range to replace. If it's missing, the suggestion is for the whole
+/// block.
+#[derive(Deserialize)]
+pub struct SuggestParams {
+    start: Option<usize>,
+    end: Option<usize>,
+}
+
+async fn block_content(
+    db: impl PgExecutor<'_>,
+    block_id: i32,
+) -> Result<String> {
+    struct Qres {
+        content: String,
+    }
+    query_as!(Qres, "select content from block where id = $1", block_id)
+        .fetch_one(db)
+        .await
+        .map(|r| r.content)
+        .map_err(|e| ErrStack::sqlx(&e, "suggestion: block content"))
+}
+
+pub async fn suggest(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(block_id): Path<i32>,
+    Query(params): Query<SuggestParams>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(_) => {
+            let content = block_content(&db, block_id).await?;
+            let (start, end) = params
+                .start
+                .zip(params.end)
+                .unwrap_or((0, content.chars().count()));
+            let original = quote_range(&db, block_id, start, end).await?;
+            Ok(Page {
+                title: "Suggest an Edit",
+                children: &PageContainer {
+                    children: &SuggestionForm {
+                        block_id,
+                        block_content: &content,
+                        start,
+                        end,
+                        original: &original,
+                    },
+                },
+            }
+            .render()
+            .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
+
+struct SuggestionForm<'a> {
+    block_id: i32,
+    block_content: &'a str,
+    start: usize,
+    end: usize,
+    original: &'a str,
+}
+impl Component for SuggestionForm<'_> {
+    fn render(&self) -> String {
+        let book = Route::Book;
+        let suggest = Route::BookSuggestion {
+            block_id: Some(self.block_id),
+        };
+        let (start, end) = (self.start, self.end);
+        let block = QuotedBlock {
+            content: self.block_content,
+            span: Some((start, end)),
+        }
+        .render();
+        let original = clean(self.original);
+        format!(
+            r#"
+            <form
+                class="flex flex-col gap-2 max-w-prose"
+                hx-post="{suggest}"
+                hx-target="body"
+            >
+                <a class="link" href="{book}">back to the book</a>
+                <h1 class="text-xl">Suggest an Edit</h1>
+                {block}
+                <input type="hidden" name="start" value="{start}" />
+                <input type="hidden" name="end" value="{end}" />
+                <label for="replacement">replace the marked text with</label>
+                <textarea
+                    id="replacement"
+                    class="dark:text-black"
+                    name="replacement"
+                    rows="4"
+                >{original}</textarea>
+                <label for="note">note for the author (optional)</label>
+                <input
+                    id="note"
+                    class="dark:text-black"
+                    type="text"
+                    name="note"
+                />
+                <button
+                    class="bg-orange-500 text-white font-bold self-start p-2 m-2
+                    rounded"
+                >
+                    send suggestion
+                </button>
+            </form>
+            "#
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SuggestionPayload {
+    start: usize,
+    end: usize,
+    replacement: String,
+    note: String,
+}
+
+pub async fn handle_suggestion(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Path(block_id): Path<i32>,
+    Query(screen_area): Query<ScreenAreaParams>,
+    Form(payload): Form<SuggestionPayload>,
+) -> Result<Response> {
+    match Auth::from_headers(&db, &session_key, &headers).await {
+        AuthResult::Authenticated(auth) => {
+            let original =
+                quote_range(&db, block_id, payload.start, payload.end).await?;
+            // Blocks are single paragraphs, so line breaks can't be kept.
+            let replacement = payload
+                .replacement
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if replacement == original {
+                return Err(ErrStack::new(ErrT::ValidationError)
+                    .ctx("the suggestion doesn't change anything".into()));
+            }
+            let note = Some(payload.note.trim()).filter(|n| !n.is_empty());
+            let offset = |value: usize| {
+                i32::try_from(value).map_err(|e| {
+                    ErrStack::new(ErrT::ValidationError)
+                        .ctx(format!("offset {value} is out of range: {e}"))
+                })
+            };
+            query!(
+                "insert into suggestion
+                (
+                    start_offset,
+                    end_offset,
+                    original,
+                    replacement,
+                    note,
+                    block_id,
+                    token_id
+                ) values ($1, $2, $3, $4, $5, $6, $7)",
+                offset(payload.start)?,
+                offset(payload.end)?,
+                original,
+                replacement,
+                note,
+                block_id,
+                auth.token_id
+            )
+            .execute(&db)
+            .await
+            .map_err(|e| ErrStack::sqlx(&e, "handle_suggestion"))?;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "Hx-Push-Url",
+                HeaderValue::from_str(&Route::Book.as_string())
+                    .expect("book route is ASCII"),
+            );
+            let position = get_current_position(&auth, &db).await?;
+            Ok((
+                headers,
+                [
+                    render(
+                        &auth,
+                        &db,
+                        &position,
+                        &screen_area,
+                        AccessEventType::PageView,
+                    )
+                    .await?,
+                    Saved {
+                        message: "suggestion sent",
+                    }
+                    .render(),
+                ]
+                .join(""),
+            )
+                .into_response())
+        }
+        AuthResult::NotAuthenticated(_) => {
+            Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
+                .into_response())
+        }
+        AuthResult::Err(e) => Err(e),
+    }
+}
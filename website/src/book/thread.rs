@@ -0,0 +1,575 @@
+//! Comment threads. A reader's comment starts a thread, and the admin can
+//! reply to it; readers see the replies to their comments alongside the
+//! block, and are told when the author has answered them.
+
+use crate::{components::QuotedBlock, prelude::*};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use ides::{auth::Role, content::SequencedBlock};
+use std::collections::HashMap;
+
+pub struct ThreadComment {
+    pub id: i32,
+    pub parent_id: Option<i32>,
+    pub token_id: i32,
+    pub comment: String,
+    pub edited: bool,
+    pub deleted: bool,
+    /// Previous versions of the comment, oldest first. Only loaded for the
+    /// admin.
+    pub history: Vec<String>,
+    pub author_name: String,
+    pub author_is_admin: bool,
+    /// `None` for comments written before we kept track.
+    pub created_at: Option<DateTime<Utc>>,
+    /// Set on replies which the reader who started the thread has not seen.
+    pub unread: bool,
+    pub book_revision_id: i32,
+    pub block_content: String,
+    pub quote_start: Option<i32>,
+    pub quote_end: Option<i32>,
+    /// Set if the comment was carried forward into a new revision, and its
+    /// block has been edited since it was written.
+    pub possibly_addressed: bool,
+    /// The block as it was when the comment was written, if the comment has
+    /// been carried forward.
+    pub original_content: Option<String>,
+}
+
+pub struct Thread {
+    pub root: ThreadComment,
+    pub replies: Vec<ThreadComment>,
+}
+
+/// Group comments into threads. Comments must be ordered such that each
+/// thread's root comes before its replies.
+fn group(comments: Vec<ThreadComment>) -> Vec<Thread> {
+    let mut threads: Vec<Thread> = Vec::new();
+    for comment in comments {
+        match comment.parent_id {
+            None => threads.push(Thread {
+                root: comment,
+                replies: Vec::new(),
+            }),
+            Some(parent_id) => {
+                if let Some(thread) =
+                    threads.iter_mut().find(|t| t.root.id == parent_id)
+                {
+                    thread.replies.push(comment);
+                }
+            }
+        }
+    }
+    threads
+}
+
+/// Threads started by the reader; optionally only those on one block.
+/// Threads with the most recent activity come first.
+pub async fn reader_threads(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+    block_id: Option<i32>,
+) -> Result<Vec<Thread>> {
+    let admin_role_id: i32 = Role::Admin.into();
+    let comments = query_as!(
+        ThreadComment,
+        r#"with thread as (
+            select
+                root.id,
+                (
+                    select max(c.created_at) from comment c
+                    where c.id = root.id or c.parent_id = root.id
+                ) last_activity
+            from comment root
+            where
+                root.token_id = $1
+                and root.parent_id is null
+                and ($2::int is null or root.block_id = $2)
+                and (
+                    root.deleted_at is null
+                    or exists (select 1 from comment r where r.parent_id = root.id)
+                )
+        )
+        select
+            c.id,
+            c.parent_id,
+            c.token_id,
+            c.comment,
+            c.edited_at is not null "edited!",
+            c.deleted_at is not null "deleted!",
+            array[]::text[] "history!",
+            t.name author_name,
+            t.role_id = $3 "author_is_admin!",
+            c.created_at,
+            (
+                c.parent_id is not null
+                and c.token_id <> $1
+                and c.seen_at is null
+            ) "unread!",
+            b.book_revision_id,
+            b.content block_content,
+            c.quote_start,
+            c.quote_end,
+            c.possibly_addressed,
+            ob.content "original_content?"
+        from comment c
+        join thread on thread.id = coalesce(c.parent_id, c.id)
+        join token t on t.id = c.token_id
+        join block b on b.id = c.block_id
+        left join block ob on ob.id = c.original_block_id
+        order by
+            thread.last_activity desc nulls last,
+            thread.id,
+            c.parent_id nulls first,
+            c.created_at"#,
+        token_id,
+        block_id,
+        admin_role_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "reader_threads"))?;
+    Ok(group(comments))
+}
+
+/// The threads started by the given comments, in the same order. Edit
+/// history is only for the admin.
+pub async fn threads_by_root(
+    db: impl PgExecutor<'_>,
+    root_ids: &[i32],
+    with_history: bool,
+) -> Result<Vec<Thread>> {
+    let admin_role_id: i32 = Role::Admin.into();
+    let comments = query_as!(
+        ThreadComment,
+        r#"select
+            c.id,
+            c.parent_id,
+            c.token_id,
+            c.comment,
+            c.edited_at is not null "edited!",
+            c.deleted_at is not null "deleted!",
+            case when $3 then array(
+                select ce.comment from comment_edit ce
+                where ce.comment_id = c.id
+                order by ce.edited_at
+            ) else array[]::text[] end "history!",
+            t.name author_name,
+            t.role_id = $2 "author_is_admin!",
+            c.created_at,
+            false "unread!",
+            b.book_revision_id,
+            b.content block_content,
+            c.quote_start,
+            c.quote_end,
+            c.possibly_addressed,
+            ob.content "original_content?"
+        from comment c
+        join token t on t.id = c.token_id
+        join block b on b.id = c.block_id
+        left join block ob on ob.id = c.original_block_id
+        where coalesce(c.parent_id, c.id) = any($1)
+        order by
+            array_position($1, coalesce(c.parent_id, c.id)),
+            c.parent_id nulls first,
+            c.created_at"#,
+        root_ids,
+        admin_role_id,
+        with_history
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "threads_by_root"))?;
+    Ok(group(comments))
+}
+
+/// Every thread on a block, oldest first; for the admin, who sees all
+/// readers' comments while reading.
+pub async fn block_threads(
+    db: impl PgExecutor<'_> + Copy,
+    block_id: i32,
+) -> Result<Vec<Thread>> {
+    struct Qres {
+        id: i32,
+    }
+    let ids: Vec<i32> = query_as!(
+        Qres,
+        "select id from comment
+        where block_id = $1 and parent_id is null
+        order by created_at",
+        block_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "block_threads"))?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+    threads_by_root(db, &ids, true).await
+}
+
+/// The number of threads on each of some set of blocks, for drawing comment
+/// markers in the book. Readers see markers for their own comments, and for
+/// others' comments shared with them; the admin sees markers for everyone's.
+pub struct CommentMarkers(HashMap<i32, i64>);
+
+impl CommentMarkers {
+    pub async fn get(
+        auth: &Auth,
+        db: impl PgExecutor<'_>,
+        blocks: &[SequencedBlock],
+    ) -> Result<Self> {
+        struct Qres {
+            block_id: i32,
+            count: i64,
+        }
+        let block_ids: Vec<i32> = blocks.iter().map(|b| b.id).collect();
+        let everyone = auth.role == Role::Admin;
+        let counts = query_as!(
+            Qres,
+            r#"select block_id, count(*) "count!"
+            from comment
+            where
+                block_id = any($1)
+                and parent_id is null
+                and deleted_at is null
+                and (
+                    $2
+                    or token_id = $3
+                    or id in (
+                        select comment_id from shared_comment
+                        where viewer_id = $3
+                    )
+                )
+            group by block_id"#,
+            &block_ids,
+            everyone,
+            auth.token_id
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| ErrStack::sqlx(&e, "CommentMarkers::get"))?;
+        Ok(Self(
+            counts.into_iter().map(|r| (r.block_id, r.count)).collect(),
+        ))
+    }
+    pub fn for_block(&self, block_id: i32) -> i64 {
+        self.0.get(&block_id).copied().unwrap_or_default()
+    }
+}
+
+/// Mark replies in the reader's threads as seen; optionally only those on
+/// one block.
+pub async fn mark_replies_seen(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+    block_id: Option<i32>,
+) -> Result<()> {
+    query!(
+        "update comment
+        set seen_at = now()
+        where
+            seen_at is null
+            and token_id <> $1
+            and parent_id in (
+                select id from comment
+                where
+                    token_id = $1
+                    and ($2::int is null or block_id = $2)
+            )",
+        token_id,
+        block_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "mark_replies_seen"))?;
+    Ok(())
+}
+
+pub async fn count_unread_replies(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+) -> Result<i64> {
+    struct Qres {
+        count: i64,
+    }
+    query_as!(
+        Qres,
+        r#"select count(*) "count!"
+        from comment c
+        join comment root on root.id = c.parent_id
+        where
+            root.token_id = $1
+            and c.token_id <> $1
+            and c.seen_at is null"#,
+        token_id
+    )
+    .fetch_one(db)
+    .await
+    .map(|r| r.count)
+    .map_err(|e| ErrStack::sqlx(&e, "count_unread_replies"))
+}
+
+/// Insert a reply into the thread started by `root_id`, unless that comment
+/// was deleted. If `owner_id` is given, the thread must have been started by
+/// that token. Returns the block which the thread is about.
+pub async fn insert_reply(
+    db: impl PgExecutor<'_>,
+    token_id: i32,
+    root_id: i32,
+    owner_id: Option<i32>,
+    comment: &str,
+) -> Result<i32> {
+    struct Qres {
+        block_id: i32,
+    }
+    query_as!(
+        Qres,
+        "insert into comment (comment, parent_id, block_id, token_id)
+        select $1, id, block_id, $3
+        from comment
+        where
+            id = $2
+            and parent_id is null
+            and deleted_at is null
+            and ($4::int is null or token_id = $4)
+        returning block_id",
+        comment,
+        root_id,
+        token_id,
+        owner_id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "insert_reply"))?
+    .map(|r| r.block_id)
+    .ok_or_else(|| {
+        ErrStack::new(ErrT::NotFound)
+            .ctx(format!("insert_reply: no open thread {root_id}"))
+    })
+}
+
+/// A comment within a thread. Whoever wrote the comment gets links to edit
+/// or delete it.
+struct CommentEntry<'a> {
+    comment: &'a ThreadComment,
+    viewer_id: Option<i32>,
+}
+impl Component for CommentEntry<'_> {
+    fn render(&self) -> String {
+        let c = self.comment;
+        let author = clean(&c.author_name);
+        let author = if c.author_is_admin {
+            format!("{author} (author)")
+        } else {
+            author
+        };
+        let created_at = c
+            .created_at
+            .map(|t| {
+                t.with_timezone(&Tz::America__New_York)
+                    .format("%b %d, %Y %l:%M %p")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "date unknown".into());
+        let comment = if c.deleted {
+            r#"<span class="italic">This comment was deleted.</span>"#
+                .to_string()
+        } else {
+            clean(&c.comment)
+        };
+        let unread = if c.unread {
+            r#"<span class="rounded bg-orange-500 text-white text-xs px-1">new</span>"#
+        } else {
+            ""
+        };
+        let edited = if c.edited && !c.deleted {
+            "&middot; edited"
+        } else {
+            ""
+        };
+        let history = if c.history.is_empty() {
+            String::new()
+        } else {
+            let versions =
+                c.history.iter().fold(String::new(), |mut acc, v| {
+                    acc.push_str(&format!("<li>{}</li>", clean(v)));
+                    acc
+                });
+            format!(
+                r#"
+                <details class="text-sm">
+                    <summary class="cursor-pointer">previous versions</summary>
+                    <ol class="list-decimal list-inside">{versions}</ol>
+                </details>
+                "#
+            )
+        };
+        let controls = if self.viewer_id == Some(c.token_id) && !c.deleted {
+            let edit = Route::BookCommentEdit {
+                comment_id: Some(c.id),
+            };
+            let delete = Route::BookCommentDelete {
+                comment_id: Some(c.id),
+            };
+            format!(
+                r#"
+                &middot; <a class="link" href="{edit}">edit</a>
+                &middot;
+                <button
+                    class="link"
+                    hx-delete="{delete}"
+                    hx-confirm="Delete this comment?"
+                    hx-target="body"
+                >
+                    delete
+                </button>
+                "#
+            )
+        } else {
+            String::new()
+        };
+        format!(
+            r#"
+            <div class="flex flex-col">
+                <p class="text-sm">
+                    {author} &middot; {created_at} {edited} {unread} {controls}
+                </p>
+                <p>{comment}</p>
+                {history}
+            </div>
+            "#
+        )
+    }
+}
+
+pub struct ThreadView<'a> {
+    pub root: &'a ThreadComment,
+    pub replies: &'a [ThreadComment],
+    /// Show the block the thread is about.
+    pub show_block: bool,
+    /// Where replies are posted. The response replaces the whole page.
+    pub reply_route: Route,
+    /// The token viewing the thread, who may edit their own comments.
+    pub viewer_id: Option<i32>,
+}
+impl Component for ThreadView<'_> {
+    fn render(&self) -> String {
+        let block = if self.show_block {
+            QuotedBlock {
+                content: &self.root.block_content,
+                span: self
+                    .root
+                    .quote_start
+                    .zip(self.root.quote_end)
+                    .filter(|_| !self.root.deleted)
+                    .map(|(s, e)| (s as usize, e as usize)),
+            }
+            .render()
+        } else {
+            String::new()
+        };
+        let changed =
+            match (self.root.possibly_addressed, &self.root.original_content) {
+                (true, Some(original)) => {
+                    let original = QuotedBlock {
+                        content: original,
+                        span: None,
+                    }
+                    .render();
+                    format!(
+                        r#"
+                    <details class="text-sm">
+                        <summary class="cursor-pointer">
+                            This passage has been revised since the comment
+                            was written.
+                        </summary>
+                        {original}
+                    </details>
+                    "#
+                    )
+                }
+                _ => String::new(),
+            };
+        let viewer_id = self.viewer_id;
+        let root = CommentEntry {
+            comment: self.root,
+            viewer_id,
+        }
+        .render();
+        let replies = self.replies.iter().fold(String::new(), |mut acc, r| {
+            acc.push_str(
+                &CommentEntry {
+                    comment: r,
+                    viewer_id,
+                }
+                .render(),
+            );
+            acc
+        });
+        // A deleted comment's thread can be read, but not replied to.
+        let reply_form = if self.root.deleted {
+            String::new()
+        } else {
+            let reply_route = &self.reply_route;
+            format!(
+                r#"
+                <form
+                    class="flex gap-2 items-end"
+                    hx-post="{reply_route}"
+                    hx-target="body"
+                >
+                    <textarea
+                        class="flex-grow dark:text-black"
+                        name="comment"
+                        placeholder="Reply"
+                        rows="1"
+                        required
+                    ></textarea>
+                    <button
+                        class="bg-orange-500 text-white font-bold p-1 rounded"
+                    >
+                        reply
+                    </button>
+                </form>
+                "#
+            )
+        };
+        format!(
+            r#"
+            <div class="flex flex-col gap-2 rounded bg-stone-200 dark:bg-stone-800 p-2">
+                {block}
+                {changed}
+                {root}
+                <div class="flex flex-col gap-2 border-l-2 border-stone-400 pl-2">
+                    {replies}
+                </div>
+                {reply_form}
+            </div>
+            "#
+        )
+    }
+}
+
+/// Render threads for the reader UI, with forms to reply in them.
+pub fn render_reader_threads(
+    threads: &[Thread],
+    show_block: bool,
+    viewer_id: i32,
+) -> String {
+    threads.iter().fold(String::new(), |mut acc, t| {
+        acc.push_str(
+            &ThreadView {
+                root: &t.root,
+                replies: &t.replies,
+                show_block,
+                reply_route: Route::BookCommentReply {
+                    comment_id: Some(t.root.id),
+                },
+                viewer_id: Some(viewer_id),
+            }
+            .render(),
+        );
+        acc
+    })
+}
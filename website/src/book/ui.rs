@@ -1,8 +1,22 @@
 //! Reader UI
 
-use super::access::log_access;
+use super::{
+    access::log_access,
+    highlight::Highlights,
+    progress::{has_position_history, ProgressBar},
+    reaction::{BlockReactions, PageReactions, ReactionBar},
+    scroll::{get_reading_mode, ReadingMode, ScrollColumn},
+    thread::{count_unread_replies, CommentMarkers},
+};
 use crate::{htmx, prelude::*};
-use ides::content::SequencedBlock;
+use ides::{
+    analytics::AccessEventType,
+    content::{
+        char_budget, char_slice, page_containing, BlockSlice, Position,
+        Progress, Section,
+    },
+    highlight::segments,
+};
 
 #[derive(Deserialize)]
 pub struct ScreenAreaParams {
@@ -12,18 +26,26 @@ pub struct ScreenAreaParams {
 }
 
 pub async fn ui(
-    State(AppState { db }): State<AppState>,
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
     headers: HeaderMap,
     Query(params): Query<ScreenAreaParams>,
 ) -> Result<Response> {
-    match Auth::from_headers(&db, &headers).await {
+    match Auth::from_headers(&db, &session_key, &headers).await {
         AuthResult::Authenticated(auth) => {
             let position = get_current_position(&auth, &db).await?;
-            Ok(render(&auth, &db, &position, &params)
-                .await?
-                .into_response())
+            Ok(render(
+                &auth,
+                &db,
+                &position,
+                &params,
+                AccessEventType::PageView,
+            )
+            .await?
+            .into_response())
         }
-        AuthResult::NotAuthenticated => {
+        AuthResult::NotAuthenticated(_) => {
             Ok(htmx::redirect(HeaderMap::new(), &Route::Auth.as_string())
                 .into_response())
         }
@@ -36,27 +58,67 @@ pub async fn render(
     db: impl PgExecutor<'_> + Copy,
     position: &CurrentPosition,
     screen_area: &ScreenAreaParams,
+    event: AccessEventType,
 ) -> Result<String> {
-    log_access(auth, db, position.current_block_sequence)
+    log_access(auth, db, event, position, Some(screen_area.screen_area))
         .await
         .map_err(|e| {
             e.wrap(ErrT::BookUi).ctx("while accessing book UI".into())
         })?;
 
-    let blocks = ides::content::list_blocks(
+    let mode = get_reading_mode(auth, db).await?;
+    let section = Section::get(
         db,
+        position.book_revision_id,
         position.current_block_sequence,
+    )
+    .await?;
+    let highlights = Highlights::get(auth, db, &section.blocks).await?;
+    let markers = CommentMarkers::get(auth, db, &section.blocks).await?;
+    let reactions = PageReactions::get(auth, db, &section.blocks).await?;
+    let content = match mode {
+        ReadingMode::Paged => {
+            let pages = section.paginate(char_budget(screen_area.screen_area));
+            page_containing(&pages, position.as_position())
+                .map(|i| {
+                    render_slices(
+                        &pages[i].slices,
+                        &highlights,
+                        &markers,
+                        &reactions,
+                    )
+                })
+                .unwrap_or_default()
+        }
+        ReadingMode::Scroll => ScrollColumn {
+            blocks: &section.blocks,
+            highlights: &highlights,
+            markers: &markers,
+            reactions: &reactions,
+            current_block_id: position.current_block_id,
+        }
+        .render(),
+    };
+
+    let progress = Progress::get(
+        db,
         position.book_revision_id,
+        position.current_block_sequence,
     )
     .await?;
 
+    let unread_replies = count_unread_replies(db, auth.token_id).await?;
+    let can_go_back = has_position_history(auth, db).await?;
+
     Ok(Page {
         title: "The Ides of August",
         children: &Reader {
             reader_name: &auth.name,
-            blocks: &blocks,
-            position,
-            screen_area,
+            mode,
+            content: &content,
+            progress: &progress,
+            unread_replies,
+            can_go_back,
         },
     }
     .render())
@@ -67,6 +129,78 @@ pub struct CurrentPosition {
     pub book_revision_id: i32,
     pub current_block_sequence: i32,
     pub current_block_id: i32,
+    /// Character offset into the current block, for pages which begin
+    /// part-way through a paragraph.
+    pub current_block_offset: i32,
+}
+
+impl CurrentPosition {
+    pub fn as_position(&self) -> Position {
+        Position {
+            sequence: self.current_block_sequence,
+            offset: self.current_block_offset,
+        }
+    }
+}
+
+/// Look up `position` in `book_revision_id`, or in the current revision of
+/// the book if that's `None`. Readers move within the revision they're
+/// reading, so that their progress and where they land agree. Returns `None`
+/// if the revision has no block at that sequence.
+pub async fn position_at(
+    db: impl PgExecutor<'_>,
+    book_revision_id: Option<i32>,
+    Position { sequence, offset }: Position,
+) -> Result<Option<CurrentPosition>> {
+    query_as!(
+        CurrentPosition,
+        r#"select
+            id current_block_id,
+            book_revision_id,
+            sequence current_block_sequence,
+            $2::int "current_block_offset!"
+        from block
+        where
+            sequence = $1
+            and book_revision_id = coalesce(
+                $3,
+                (
+                    select revision_id
+                    from current_revision
+                    where book_id = 1
+                )
+            )
+        order by sequence
+        limit 1"#,
+        sequence,
+        offset,
+        book_revision_id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "position_at"))
+}
+
+pub async fn save_position(
+    auth: &Auth,
+    db: impl PgExecutor<'_>,
+    position: &CurrentPosition,
+) -> Result<()> {
+    query!(
+        "insert into current_block (token_id, block_id, block_offset)
+        values ($1, $2, $3)
+        on conflict (token_id)
+        do update set
+            block_id = $2,
+            block_offset = $3",
+        auth.token_id,
+        position.current_block_id,
+        position.current_block_offset
+    )
+    .execute(db)
+    .await
+    .map_err(|e| ErrStack::sqlx(&e, "save_position"))?;
+    Ok(())
 }
 
 pub async fn get_current_position(
@@ -78,7 +212,8 @@ pub async fn get_current_position(
         "select
             bl.id current_block_id,
             bl.book_revision_id,
-            bl.sequence current_block_sequence
+            bl.sequence current_block_sequence,
+            cb.block_offset current_block_offset
         from current_block cb
         join block bl on cb.block_id = bl.id
         where
@@ -95,10 +230,11 @@ pub async fn get_current_position(
         None => {
         query_as!(
             CurrentPosition,
-            "select
+            r#"select
                 id current_block_id,
                 book_revision_id,
-                sequence current_block_sequence
+                sequence current_block_sequence,
+                0 "current_block_offset!"
             from block
             where
                 book_revision_id = (
@@ -107,43 +243,108 @@ pub async fn get_current_position(
                     where book_id = 1
                 )
             order by sequence
-            limit 1"
+            limit 1"#
         ).fetch_one(db).await.map_err(|e| ErrStack::sqlx(&e, "get_current_position :: current revision does not exist in the first place"))
         }
     }
 }
 
-impl Component for SequencedBlock {
+/// A slice of a block, with the reader's highlights marked, a marker if
+/// there are comments on the block, and buttons to react to it.
+pub struct MarkedSlice<'a> {
+    pub slice: &'a BlockSlice<'a>,
+    pub highlights: &'a Highlights,
+    pub markers: &'a CommentMarkers,
+    pub reactions: &'a PageReactions,
+}
+impl Component for MarkedSlice<'_> {
     fn render(&self) -> String {
+        let slice = self.slice;
+        let block_id = slice.block.id;
+        let offset = slice.start;
         let comment = Route::BookComment {
-            block_id: Some(self.id),
+            block_id: Some(block_id),
+        };
+        let highlight = Route::BookHighlight {
+            block_id: Some(block_id),
         };
-        let content = match self.block.r#type {
+        let suggest = Route::BookSuggestion {
+            block_id: Some(block_id),
+        };
+        let highlights = self.highlights.for_block(block_id);
+        let ranges: Vec<(usize, usize)> = highlights
+            .iter()
+            .map(|h| (h.start_offset as usize, h.end_offset as usize))
+            .collect();
+        let text = segments(slice.start, slice.end, &ranges).iter().fold(
+            String::new(),
+            |mut acc, segment| {
+                let text = clean(char_slice(
+                    &slice.block.block.content,
+                    segment.start,
+                    segment.end,
+                ));
+                match segment.mark {
+                    Some(i) => acc.push_str(&format!(
+                        r#"<mark class="{}" data-highlight-id="{}">{text}</mark>"#,
+                        highlights[i].class(),
+                        highlights[i].id
+                    )),
+                    None => acc.push_str(&text),
+                };
+                acc
+            },
+        );
+        let content = match slice.block.block.r#type {
             ides::content::BlockType::SectionTitle => {
-                format!(
-                    r#"<h1 class="text-yellow-400">{}</h1>"#,
-                    clean(&self.block.content)
-                )
+                format!(r#"<h1 class="text-yellow-400">{text}</h1>"#)
             }
             ides::content::BlockType::H1 => {
-                format!(
-                    r#"<h2 class="extra-bold text-yellow-400">{}</h2>"#,
-                    clean(&self.block.content)
-                )
-            }
-            ides::content::BlockType::Paragraph => {
-                format!("<p>{}</p>", clean(&self.block.content))
+                format!(r#"<h2 class="extra-bold text-yellow-400">{text}</h2>"#)
             }
+            ides::content::BlockType::Paragraph => format!("<p>{text}</p>"),
         };
+        let count = self.markers.for_block(block_id);
+        let (label, title) = match count {
+            0 => (String::new(), "comment on this passage".to_string()),
+            count => (
+                format!(" {count}"),
+                format!("{count} comment threads; tap to view them"),
+            ),
+        };
+        let no_reactions = BlockReactions::default();
+        let reactions = ReactionBar {
+            block_id,
+            reactions: self
+                .reactions
+                .for_block(block_id)
+                .unwrap_or(&no_reactions),
+            compact: true,
+        }
+        .render();
 
         format!(
             r#"
             <div
-                class="cursor-pointer"
-                hx-push-url="true"
-                hx-target="body" 
-                hx-get="{comment}"
+                data-block-id="{block_id}"
+                data-offset="{offset}"
+                data-highlight-url="{highlight}"
+                data-comment-url="{comment}"
+                data-suggest-url="{suggest}"
             >
+                <div class="float-right ml-2 flex gap-1 items-start">
+                    {reactions}
+                    <button
+                        class="rounded bg-stone-300 dark:bg-stone-700 text-xs
+                        px-1 opacity-60 hover:opacity-100"
+                        title="{title}"
+                        hx-get="{comment}"
+                        hx-push-url="true"
+                        hx-target="body"
+                    >
+                        &#128172;{label}
+                    </button>
+                </div>
                 {content}
             </div>
             "#
@@ -151,63 +352,60 @@ impl Component for SequencedBlock {
     }
 }
 
+pub fn render_slices(
+    slices: &[BlockSlice],
+    highlights: &Highlights,
+    markers: &CommentMarkers,
+    reactions: &PageReactions,
+) -> String {
+    slices.iter().fold(String::new(), |mut acc, slice| {
+        acc.push_str(
+            &MarkedSlice {
+                slice,
+                highlights,
+                markers,
+                reactions,
+            }
+            .render(),
+        );
+        acc
+    })
+}
+
 struct Reader<'a> {
     reader_name: &'a str,
-    blocks: &'a [SequencedBlock],
-    position: &'a CurrentPosition,
-    screen_area: &'a ScreenAreaParams,
+    mode: ReadingMode,
+    content: &'a str,
+    progress: &'a Progress,
+    /// Replies to the reader's comments which they haven't seen yet.
+    unread_replies: i64,
+    can_go_back: bool,
 }
 impl Component for Reader<'_> {
     fn render(&self) -> String {
         let about = Route::About;
+        let bookmarks = Route::BookBookmarks;
+        let highlights = Route::BookHighlights;
+        let comments = Route::BookComments;
+        let devices = Route::AuthSessions;
+        let comments_label = match self.unread_replies {
+            0 => "comments".to_string(),
+            n => format!(
+                r#"comments <span class="rounded bg-orange-500 text-white px-1">{n} new</span>"#
+            ),
+        };
         let reader_name = clean(self.reader_name);
-
-        // Screen area and the amount of characters that look good should
-        // scale linearly. Here are two samples that looked good, and then
-        // we'll compute the character limit for any other screen area from
-        // these;
-        //
-        // - 717808 1928
-        // - 200552 744
-        let slope = (1928.0 - 744.0) / (717808.0 - 200552.0);
-        let constant = 1928.0 - slope * 717808.0;
-        let char_limit = (slope * f64::from(self.screen_area.screen_area)
-            + constant) as usize;
-
-        let mut chars_taken = 0;
-        let content = self
-            .blocks
-            .iter()
-            .filter(|b| b.sequence >= self.position.current_block_sequence)
-            .enumerate()
-            .take_while(|(i, block)| {
-                chars_taken += block.block.content.len();
-                chars_taken < char_limit || *i == 0
-            })
-            .fold(String::new(), |mut acc, (_, block)| {
-                match block.block.r#type {
-                    ides::content::BlockType::SectionTitle => {
-                        acc.push_str(&format!(
-                            r#"<h1 class="text-yellow-400">{}</h1>"#,
-                            clean(&block.block.content)
-                        ));
-                    }
-                    ides::content::BlockType::H1 => {
-                        acc.push_str(&format!(
-                            r#"<h2 class="extra-bold text-yellow-400">{}</h2>"#,
-                            clean(&block.block.content)
-                        ));
-                    }
-                    ides::content::BlockType::Paragraph => {
-                        acc.push_str(&format!(
-                            "<p>{}</p>",
-                            clean(&block.block.content)
-                        ));
-                    }
-                }
-                acc
-            });
-        let toolbar = Toolbar {}.render();
+        let content = self.content;
+        let mode_switch = ModeSwitch { current: self.mode }.render();
+        let progress = ProgressBar {
+            progress: self.progress,
+            can_go_back: self.can_go_back,
+        }
+        .render();
+        let toolbar = match self.mode {
+            ReadingMode::Paged => Toolbar {}.render(),
+            ReadingMode::Scroll => String::new(),
+        };
         format!(
             r#"
             <div
@@ -215,18 +413,27 @@ impl Component for Reader<'_> {
                 class="bg-stone-50 flex flex-col dark:bg-stone-900
                 dark:text-slate-200 h-dvh w-screen overflow-clip"
             >
-                <div class="w-screen flex-grow p-2 overflow-y-scroll">
+                <div
+                    id="reader-scroll"
+                    class="w-screen flex-grow p-2 overflow-y-scroll"
+                >
                     <div class="prose sm:p-4 md:p-8 dark:text-slate-200">
                         {content}
                     </div>
                 </div>
                 <div>
-                    <div class="rounded-t flex bg-stone-300 dark:bg-stone-700 px-2">
+                    <div class="rounded-t flex gap-2 bg-stone-300 dark:bg-stone-700 px-2">
                         <p>reading as {reader_name}</p>
+                        {mode_switch}
+                        <a class="link" href="{bookmarks}">bookmarks</a>
+                        <a class="link" href="{highlights}">highlights</a>
+                        <a class="link" href="{comments}">{comments_label}</a>
+                        <a class="link" href="{devices}">devices</a>
                         <a class="link flex-grow text-right" href="{about}">
                             about the site
                         </a>
                     </div>
+                    {progress}
                     {toolbar}
                 </div>
             </div>
@@ -235,6 +442,31 @@ impl Component for Reader<'_> {
     }
 }
 
+struct ModeSwitch {
+    current: ReadingMode,
+}
+impl Component for ModeSwitch {
+    fn render(&self) -> String {
+        let route = Route::BookReadingMode;
+        let (mode, label) = match self.current {
+            ReadingMode::Paged => ("scroll", "switch to scrolling"),
+            ReadingMode::Scroll => ("paged", "switch to pages"),
+        };
+        format!(
+            r##"
+            <button
+                class="link"
+                hx-post="{route}"
+                hx-target="#reader-container"
+                hx-vals='{{"mode": "{mode}"}}'
+            >
+                {label}
+            </button>
+            "##
+        )
+    }
+}
+
 struct Toolbar;
 impl Component for Toolbar {
     fn render(&self) -> String {
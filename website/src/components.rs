@@ -7,6 +7,7 @@
 #![allow(clippy::let_and_return)]
 
 use super::prelude::*;
+use ides::{content::char_slice, highlight::segments};
 
 #[cfg(feature = "live_reload")]
 const LIVE_RELOAD_SCRIPT: &str = r#"<script>
@@ -177,3 +178,39 @@ impl Component for Saved<'_> {
         )
     }
 }
+
+/// A block's content as a block quote, with one span of it marked; for
+/// showing which part of a block a comment is about.
+pub struct QuotedBlock<'a> {
+    pub content: &'a str,
+    /// Character offsets of the marked span, if any.
+    pub span: Option<(usize, usize)>,
+}
+impl Component for QuotedBlock<'_> {
+    fn render(&self) -> String {
+        let ranges: Vec<(usize, usize)> = self.span.into_iter().collect();
+        let quote = segments(0, self.content.chars().count(), &ranges)
+            .iter()
+            .fold(String::new(), |mut acc, segment| {
+                let text =
+                    clean(char_slice(self.content, segment.start, segment.end));
+                match segment.mark {
+                    Some(_) => acc.push_str(&format!(
+                        r#"<mark class="bg-yellow-200 dark:bg-yellow-700">{text}</mark>"#
+                    )),
+                    None => acc.push_str(&text),
+                };
+                acc
+            });
+        format!(
+            r#"
+            <blockquote
+                class="italic border-l-2 border-stone-300
+                dark:border-stone-700 pl-2"
+            >
+                {quote}
+            </blockquote>
+            "#
+        )
+    }
+}
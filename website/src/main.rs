@@ -1,5 +1,5 @@
 use dotenvy::dotenv;
-use ides::prelude::*;
+use ides::{invitation::public_url, prelude::*, session::SessionKey};
 use std::net::SocketAddr;
 
 mod about;
@@ -14,6 +14,14 @@ mod prelude;
 mod routes;
 mod r#static;
 
+/// `$SESSION_SECRET`, which signs session cookies; see [ides::session].
+fn session_key() -> Result<SessionKey> {
+    let secret = std::env::var("SESSION_SECRET").map_err(|e| {
+        ErrStack::new(ErrT::Config).ctx(format!("SESSION_SECRET: {e}"))
+    })?;
+    SessionKey::new(&secret)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
@@ -23,9 +31,13 @@ async fn main() -> Result<()> {
         ErrStack::new(ErrT::DbMigrationFailure)
             .ctx(format!("migrations failed: {e}"))
     })?;
-    let state = models::AppState { db };
+    let state = models::AppState {
+        db,
+        session_key: session_key()?,
+        public_url: public_url()?,
+    };
 
-    let app = routes::get_routes().with_state(state);
+    let app = routes::get_routes(&state).with_state(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
 
@@ -1,13 +1,23 @@
 //! Axum middlewares, modeled as async functions.
 
+use crate::{
+    auth::ui::{session_cookie, start_session, user_agent},
+    models::AppState,
+};
 use axum::{
     body::Body,
-    http::{HeaderValue, Request},
+    extract::State,
+    http::{HeaderMap, HeaderValue, Request},
     middleware::Next,
     response::Response,
 };
 use chrono::prelude::*;
 use chrono_tz::Tz;
+use ides::{
+    auth::{Auth, AuthResult, Token},
+    prelude::*,
+    session::{touch_session, SessionCookie, SESSION_COOKIE},
+};
 
 /// This will ensure that outgoing requests receive a content-type if the
 /// request handler did not specify one. 99% of request handlers in this
@@ -64,3 +74,85 @@ pub async fn log(request: Request<Body>, next: Next) -> Response {
 
     response
 }
+
+/// Keeps the session cookie fresh; a session's secret is rotated once it's
+/// due, and a browser still holding a token cookie from before we had
+/// sessions gets a session in exchange. This happens after the request is
+/// handled, which has already authenticated with the old cookie.
+///
+/// The exchange only happens when the browser loads a page. The htmx
+/// requests a page fires as it loads all carry the same token cookie, and
+/// exchanging on each would leave one device with several sessions.
+pub async fn session(
+    State(AppState {
+        db, session_key, ..
+    }): State<AppState>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let cookie =
+        SessionCookie::parse_from_headers(request.headers(), &session_key);
+    let legacy = match cookie {
+        Some(_) => None,
+        None if is_document(request.headers()) => {
+            Token::parse_from_headers(request.headers()).ok()
+        }
+        None => None,
+    };
+    let user_agent = user_agent(request.headers()).map(String::from);
+    let mut response = next.run(request).await;
+
+    // Handlers which sign the reader in set a new session cookie themselves.
+    let prefix = format!("{SESSION_COOKIE}=");
+    if response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .any(|c| c.as_bytes().starts_with(prefix.as_bytes()))
+    {
+        return response;
+    }
+    let set_cookie = match (cookie, legacy) {
+        (Some(cookie), _) => match touch_session(&db, &cookie).await {
+            Ok(Some(rotated)) => {
+                session_cookie(&rotated, &session_key).map(Some)
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        },
+        (None, Some(token)) => match Auth::get(&db, &token).await {
+            AuthResult::Authenticated(auth) => start_session(
+                &db,
+                &session_key,
+                auth.token_id,
+                user_agent.as_deref(),
+            )
+            .await
+            .map(Some),
+            AuthResult::NotAuthenticated(_) => Ok(None),
+            AuthResult::Err(e) => Err(e),
+        },
+        (None, None) => Ok(None),
+    };
+    match set_cookie {
+        Ok(Some(headers)) => {
+            for (name, value) in &headers {
+                response.headers_mut().append(name, value.clone());
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("{}", e.wrap(ErrT::AuthMiddleware)),
+    }
+
+    response
+}
+
+/// Whether the browser is navigating to a page, rather than fetching part of
+/// one with htmx.
+fn is_document(headers: &HeaderMap) -> bool {
+    let accepts_html = headers
+        .get("Accept")
+        .and_then(|a| a.to_str().ok())
+        .is_some_and(|a| a.contains("text/html"));
+    accepts_html && !headers.contains_key("HX-Request")
+}
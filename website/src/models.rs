@@ -1,5 +1,6 @@
 //! Core data-models for the application.
 
+use ides::session::SessionKey;
 use serde::Deserialize;
 use sqlx::PgPool;
 
@@ -7,6 +8,10 @@ use sqlx::PgPool;
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
+    pub session_key: SessionKey,
+    /// Where the site is served from, like `https://example.com`, for links
+    /// we hand out to be opened elsewhere.
+    pub public_url: String,
 }
 
 #[derive(Deserialize)]
@@ -6,7 +6,7 @@ pub use crate::{
     models::{AppState, PaginationParams, SqlPagination},
     routes::Route,
 };
-pub use ammonia::clean;
+pub use ammonia::{clean, clean_text};
 pub use axum::{
     extract::{Form, Path, Query, State},
     http::HeaderMap,
@@ -2,7 +2,7 @@
 
 use super::{about, admin, auth, book, middleware, models, r#static};
 use axum::{
-    middleware::from_fn,
+    middleware::{from_fn, from_fn_with_state},
     routing::{delete, get, post, Router},
 };
 
@@ -28,18 +28,97 @@ pub enum Route {
     AdminHome,
     AdminImportBook,
     AdminChangeRevision,
+    AdminComments,
+    AdminCommentReply {
+        comment_id: Option<i32>,
+    },
+    AdminCommentStatus,
+    AdminCommentExport,
+    AdminReactions,
+    AdminAnalytics,
+    AdminAuthorView,
+    AdminSuggestions,
+    AdminSuggestionStatus {
+        suggestion_id: Option<i32>,
+    },
+    AdminAcceptedSuggestions,
     AdminToken,
     AdminRevokeToken {
         token_id: Option<i32>,
     },
+    AdminReader {
+        token_id: Option<i32>,
+    },
+    AdminReaderPosition {
+        token_id: Option<i32>,
+    },
+    AdminReaderExpiry {
+        token_id: Option<i32>,
+    },
+    AdminReaderRotate {
+        token_id: Option<i32>,
+    },
+    AdminSession {
+        session_id: Option<i32>,
+    },
     Auth,
+    AuthSessions,
+    AuthSession {
+        session_id: Option<i32>,
+    },
     About,
+    /// Following an invitation link; the code is a secret.
+    Invite {
+        code: Option<String>,
+    },
     Book,
+    BookBookmark {
+        bookmark_id: Option<i32>,
+    },
+    BookBookmarkJump {
+        bookmark_id: Option<i32>,
+    },
+    BookBookmarks,
     BookComment {
         block_id: Option<i32>,
     },
+    BookCommentReply {
+        comment_id: Option<i32>,
+    },
+    BookCommentEdit {
+        comment_id: Option<i32>,
+    },
+    BookCommentDelete {
+        comment_id: Option<i32>,
+    },
+    BookComments,
+    BookCommentSharing,
+    BookHighlight {
+        block_id: Option<i32>,
+    },
+    BookReaction {
+        block_id: Option<i32>,
+    },
+    BookSuggestion {
+        block_id: Option<i32>,
+    },
+    BookHighlightDelete {
+        highlight_id: Option<i32>,
+    },
+    BookHighlightJump {
+        highlight_id: Option<i32>,
+    },
+    BookHighlights,
+    BookJump,
+    BookJumpBack,
+    BookStartOver,
     BookNextPage,
     BookPrevPage,
+    BookReadingMode,
+    BookScroll {
+        sequence: Option<i32>,
+    },
+    BookScrollPosition,
     Favicon,
     Htmx,
     Ping,
@@ -62,20 +141,122 @@ impl Route {
             Self::AdminHome => "/admin".into(),
             Self::AdminImportBook => "/admin/import-book".into(),
             Self::AdminChangeRevision => "/admin/change-revision".into(),
+            Self::AdminComments => "/admin/comments".into(),
+            Self::AdminCommentReply { comment_id } => match comment_id {
+                Some(id) => format!("/admin/comments/{id}/reply"),
+                None => "/admin/comments/:comment_id/reply".into(),
+            },
+            Self::AdminCommentStatus => "/admin/comments/status".into(),
+            Self::AdminCommentExport => "/admin/comments/export".into(),
+            Self::AdminReactions => "/admin/reactions".into(),
+            Self::AdminAnalytics => "/admin/analytics".into(),
+            Self::AdminAuthorView => "/admin/author-view".into(),
+            Self::AdminSuggestions => "/admin/suggestions".into(),
+            Self::AdminSuggestionStatus { suggestion_id } => {
+                match suggestion_id {
+                    Some(id) => format!("/admin/suggestions/{id}/status"),
+                    None => "/admin/suggestions/:suggestion_id/status".into(),
+                }
+            }
+            Self::AdminAcceptedSuggestions => {
+                "/admin/suggestions/accepted".into()
+            }
             Self::AdminToken => "/admin/manage-tokens".into(),
             Self::AdminRevokeToken { token_id } => match token_id {
                 Some(id) => format!("/admin/manage-tokens/{id}"),
                 None => "/admin/manage-tokens/:token_id".into(),
             },
+            Self::AdminReader { token_id } => match token_id {
+                Some(id) => format!("/admin/readers/{id}"),
+                None => "/admin/readers/:token_id".into(),
+            },
+            Self::AdminReaderPosition { token_id } => match token_id {
+                Some(id) => format!("/admin/readers/{id}/position"),
+                None => "/admin/readers/:token_id/position".into(),
+            },
+            Self::AdminReaderExpiry { token_id } => match token_id {
+                Some(id) => format!("/admin/readers/{id}/expiry"),
+                None => "/admin/readers/:token_id/expiry".into(),
+            },
+            Self::AdminReaderRotate { token_id } => match token_id {
+                Some(id) => format!("/admin/readers/{id}/rotate"),
+                None => "/admin/readers/:token_id/rotate".into(),
+            },
+            Self::AdminSession { session_id } => match session_id {
+                Some(id) => format!("/admin/sessions/{id}"),
+                None => "/admin/sessions/:session_id".into(),
+            },
             Self::Auth => "/".into(),
+            Self::AuthSessions => "/sessions".into(),
+            Self::AuthSession { session_id } => match session_id {
+                Some(id) => format!("/sessions/{id}"),
+                None => "/sessions/:session_id".into(),
+            },
             Self::About => "/about".into(),
+            Self::Invite { code } => match code {
+                Some(code) => format!("/invite/{code}"),
+                None => "/invite/:code".into(),
+            },
             Self::Book => "/book".into(),
+            Self::BookBookmark { bookmark_id } => match bookmark_id {
+                Some(id) => format!("/book/bookmarks/{id}"),
+                None => "/book/bookmarks/:bookmark_id".into(),
+            },
+            Self::BookBookmarkJump { bookmark_id } => match bookmark_id {
+                Some(id) => format!("/book/bookmarks/{id}/jump"),
+                None => "/book/bookmarks/:bookmark_id/jump".into(),
+            },
+            Self::BookBookmarks => "/book/bookmarks".into(),
             Self::BookComment { block_id } => match block_id {
                 Some(id) => format!("/block/{id}/comment"),
                 None => "/block/:block_id/comment".into(),
             },
+            Self::BookCommentReply { comment_id } => match comment_id {
+                Some(id) => format!("/book/comments/{id}/reply"),
+                None => "/book/comments/:comment_id/reply".into(),
+            },
+            Self::BookCommentEdit { comment_id } => match comment_id {
+                Some(id) => format!("/book/comments/{id}/edit"),
+                None => "/book/comments/:comment_id/edit".into(),
+            },
+            Self::BookCommentDelete { comment_id } => match comment_id {
+                Some(id) => format!("/book/comments/{id}"),
+                None => "/book/comments/:comment_id".into(),
+            },
+            Self::BookComments => "/book/comments".into(),
+            Self::BookCommentSharing => "/book/comments/sharing".into(),
+            Self::BookReaction { block_id } => match block_id {
+                Some(id) => format!("/block/{id}/reactions"),
+                None => "/block/:block_id/reactions".into(),
+            },
+            Self::BookSuggestion { block_id } => match block_id {
+                Some(id) => format!("/block/{id}/suggest"),
+                None => "/block/:block_id/suggest".into(),
+            },
+            Self::BookHighlight { block_id } => match block_id {
+                Some(id) => format!("/block/{id}/highlight"),
+                None => "/block/:block_id/highlight".into(),
+            },
+            Self::BookHighlightDelete { highlight_id } => match highlight_id {
+                Some(id) => format!("/book/highlights/{id}"),
+                None => "/book/highlights/:highlight_id".into(),
+            },
+            Self::BookHighlightJump { highlight_id } => match highlight_id {
+                Some(id) => format!("/book/highlights/{id}/jump"),
+                None => "/book/highlights/:highlight_id/jump".into(),
+            },
+            Self::BookHighlights => "/book/highlights".into(),
+            Self::BookJump => "/book/jump".into(),
+            Self::BookJumpBack => "/book/jump-back".into(),
+            Self::BookStartOver => "/book/start-over".into(),
             Self::BookNextPage => "/book/next-page".into(),
             Self::BookPrevPage => "/book/prev-page".into(),
+            Self::BookReadingMode => "/book/reading-mode".into(),
+            Self::BookScroll { sequence } => match sequence {
+                Some(seq) => format!("/book/scroll/{seq}"),
+                None => "/book/scroll/:sequence".into(),
+            },
+            Self::BookScrollPosition => "/book/position".into(),
             Self::Favicon => "/favicon.ico".into(),
             Self::Htmx => "/generated/htmx-2.0.2-mod3".into(),
             Self::Ping => "/ping".into(),
@@ -106,7 +287,7 @@ impl std::fmt::Display for Route {
     }
 }
 
-pub fn get_routes() -> Router<models::AppState> {
+pub fn get_routes(state: &models::AppState) -> Router<models::AppState> {
     Router::new()
         .route(&Route::About.as_string(), get(about::about))
         .route(&Route::AdminHome.as_string(), get(admin::home))
@@ -126,6 +307,37 @@ pub fn get_routes() -> Router<models::AppState> {
             &Route::AdminChangeRevision.as_string(),
             post(admin::handle_revision_change),
         )
+        .route(&Route::AdminComments.as_string(), get(admin::comments))
+        .route(
+            &Route::AdminCommentReply { comment_id: None }.as_string(),
+            post(admin::handle_comment_reply),
+        )
+        .route(
+            &Route::AdminCommentStatus.as_string(),
+            post(admin::handle_comment_status),
+        )
+        .route(
+            &Route::AdminCommentExport.as_string(),
+            get(admin::export_comments),
+        )
+        .route(&Route::AdminReactions.as_string(), get(admin::reactions))
+        .route(&Route::AdminAnalytics.as_string(), get(admin::analytics))
+        .route(&Route::AdminAuthorView.as_string(), get(admin::author_view))
+        .route(
+            &Route::AdminSuggestions.as_string(),
+            get(admin::suggestions),
+        )
+        .route(
+            &Route::AdminSuggestionStatus {
+                suggestion_id: None,
+            }
+            .as_string(),
+            post(admin::handle_suggestion_status),
+        )
+        .route(
+            &Route::AdminAcceptedSuggestions.as_string(),
+            get(admin::accepted_suggestions),
+        )
         .route(&Route::AdminToken.as_string(), get(admin::manage_tokens))
         .route(
             &Route::AdminToken.as_string(),
@@ -135,9 +347,58 @@ pub fn get_routes() -> Router<models::AppState> {
             &Route::AdminRevokeToken { token_id: None }.as_string(),
             delete(admin::handle_revoke_token),
         )
+        .route(
+            &Route::AdminReader { token_id: None }.as_string(),
+            get(admin::reader),
+        )
+        .route(
+            &Route::AdminReaderPosition { token_id: None }.as_string(),
+            post(admin::handle_reader_position),
+        )
+        .route(
+            &Route::AdminReaderExpiry { token_id: None }.as_string(),
+            post(admin::handle_reader_expiry),
+        )
+        .route(
+            &Route::AdminReaderRotate { token_id: None }.as_string(),
+            post(admin::handle_rotate_token),
+        )
+        .route(
+            &Route::AdminSession { session_id: None }.as_string(),
+            delete(admin::handle_revoke_session),
+        )
         .route(&Route::Auth.as_string(), get(auth::ui::get_handler))
         .route(&Route::Auth.as_string(), post(auth::ui::post_handler))
+        .route(
+            &Route::AuthSessions.as_string(),
+            get(auth::sessions::sessions),
+        )
+        .route(
+            &Route::AuthSession { session_id: None }.as_string(),
+            delete(auth::sessions::handle_revoke_session),
+        )
+        .route(
+            &Route::Invite { code: None }.as_string(),
+            get(auth::invite::invite),
+        )
+        .route(
+            &Route::Invite { code: None }.as_string(),
+            post(auth::invite::handle_invite),
+        )
         .route(&Route::Book.as_string(), get(book::ui))
+        .route(&Route::BookBookmarks.as_string(), get(book::bookmarks))
+        .route(
+            &Route::BookBookmarks.as_string(),
+            post(book::handle_create_bookmark),
+        )
+        .route(
+            &Route::BookBookmark { bookmark_id: None }.as_string(),
+            delete(book::handle_delete_bookmark),
+        )
+        .route(
+            &Route::BookBookmarkJump { bookmark_id: None }.as_string(),
+            post(book::handle_bookmark_jump),
+        )
         .route(
             &Route::BookComment { block_id: None }.as_string(),
             get(book::comment),
@@ -146,8 +407,89 @@ pub fn get_routes() -> Router<models::AppState> {
             &Route::BookComment { block_id: None }.as_string(),
             post(book::handle_comment),
         )
+        .route(&Route::BookComments.as_string(), get(book::my_comments))
+        .route(
+            &Route::BookCommentSharing.as_string(),
+            post(book::handle_share_comments),
+        )
+        .route(
+            &Route::BookCommentReply { comment_id: None }.as_string(),
+            post(book::handle_comment_reply),
+        )
+        .route(
+            &Route::BookCommentEdit { comment_id: None }.as_string(),
+            get(book::comment_edit),
+        )
+        .route(
+            &Route::BookCommentEdit { comment_id: None }.as_string(),
+            post(book::handle_comment_edit),
+        )
+        .route(
+            &Route::BookCommentDelete { comment_id: None }.as_string(),
+            delete(book::handle_delete_comment),
+        )
+        .route(
+            &Route::BookReaction { block_id: None }.as_string(),
+            post(book::handle_reaction),
+        )
+        .route(
+            &Route::BookSuggestion { block_id: None }.as_string(),
+            get(book::suggest),
+        )
+        .route(
+            &Route::BookSuggestion { block_id: None }.as_string(),
+            post(book::handle_suggestion),
+        )
+        .route(
+            &Route::BookHighlight { block_id: None }.as_string(),
+            get(book::highlight),
+        )
+        .route(
+            &Route::BookHighlight { block_id: None }.as_string(),
+            post(book::handle_highlight),
+        )
+        .route(&Route::BookHighlights.as_string(), get(book::highlights))
+        .route(
+            &Route::BookHighlightDelete { highlight_id: None }.as_string(),
+            delete(book::handle_delete_highlight),
+        )
+        .route(
+            &Route::BookHighlightJump { highlight_id: None }.as_string(),
+            post(book::handle_highlight_jump),
+        )
+        .route(&Route::BookJump.as_string(), post(book::handle_jump))
+        .route(
+            &Route::BookJumpBack.as_string(),
+            post(book::handle_jump_back),
+        )
+        .route(
+            &Route::BookStartOver.as_string(),
+            post(book::handle_start_over),
+        )
         .route(&Route::BookNextPage.as_string(), get(book::next_page))
         .route(&Route::BookPrevPage.as_string(), get(book::prev_page))
+        .route(
+            &Route::BookReadingMode.as_string(),
+            post(book::handle_reading_mode),
+        )
+        .route(
+            &Route::BookScroll { sequence: None }.as_string(),
+            get(book::scroll_section),
+        )
+        .route(
+            &Route::BookScrollPosition.as_string(),
+            post(book::handle_scroll_position),
+        )
+        .layer(from_fn_with_state(state.clone(), middleware::session))
+        .merge(static_routes())
+        .layer(from_fn(middleware::html_headers))
+        .layer(from_fn(middleware::log))
+}
+
+/// Static assets are left out of the session middleware; a page load fetches
+/// several, and none of them need the session kept fresh.
+fn static_routes() -> Router<models::AppState> {
+    Router::new()
         .route(&Route::Favicon.as_string(), get(r#static::get_favicon))
         .route(&Route::Htmx.as_string(), get(r#static::get_htmx_js))
         .route(
@@ -189,6 +531,4 @@ pub fn get_routes() -> Router<models::AppState> {
             get(r#static::get_small_icon),
         )
         .route(&Route::Void.as_string(), get(r#static::void))
-        .layer(from_fn(middleware::html_headers))
-        .layer(from_fn(middleware::log))
 }
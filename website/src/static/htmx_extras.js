@@ -45,9 +45,9 @@ htmx.on("htmx:beforeSwap", (e) => {
 htmx.config.defaultSwapStyle = "outerHTML";
 
 function transformBookLinks(currentArea) {
-  for (const attribute of ["hx-get", "href"]) {
+  for (const attribute of ["hx-get", "hx-post", "href"]) {
     for (const element of document.querySelectorAll(
-      `[${attribute}^='/book']`,
+      `[${attribute}^='/book'], [${attribute}^='/block']`,
     )) {
       const url = new URL(
         window.location.origin + element.getAttribute(attribute),
@@ -96,3 +96,165 @@ window.addEventListener("resize", () => {
   clearTimeout(debounce);
   debounce = setTimeout(later, 200);
 });
+
+/**
+ * In continuous scroll mode, start the reader where they left off, and
+ * record the block at the top of their screen as their position. Updates
+ * are throttled, so we send at most one every few seconds.
+ */
+function setupScrollReader() {
+  const column = document.getElementById("scroll-column");
+  const scroller = document.getElementById("reader-scroll");
+  if (!column || !scroller || column.dataset.initialized) {
+    return;
+  }
+  column.dataset.initialized = "true";
+  const current = column.querySelector(
+    `[data-block-id='${column.dataset.currentBlock}']`,
+  );
+  if (current) {
+    current.scrollIntoView();
+  }
+
+  let lastRecorded = column.dataset.currentBlock;
+  let throttle;
+  scroller.addEventListener("scroll", () => {
+    if (throttle) {
+      return;
+    }
+    throttle = setTimeout(() => {
+      throttle = null;
+      const top = scroller.getBoundingClientRect().top;
+      const block = Array.from(
+        column.querySelectorAll("[data-block-id]"),
+      ).find((el) => el.getBoundingClientRect().bottom > top);
+      if (block && block.dataset.blockId !== lastRecorded) {
+        lastRecorded = block.dataset.blockId;
+        htmx.ajax("POST", column.dataset.positionUrl, {
+          values: { block_id: lastRecorded },
+          swap: "none",
+        });
+      }
+    }, 3000);
+  });
+}
+
+htmx.on("htmx:afterSwap", setupScrollReader);
+window.addEventListener("DOMContentLoaded", setupScrollReader);
+
+/**
+ * When the previous section loads above the reader, keep the text they're
+ * reading where it was, instead of letting it jump down the screen. Browsers
+ * disagree about doing this themselves, so we turn theirs off.
+ */
+htmx.on("htmx:beforeSwap", (e) => {
+  const scroller = document.getElementById("reader-scroll");
+  if (!scroller || !e.detail.elt.dataset.scrollUp) {
+    return;
+  }
+  scroller.style.overflowAnchor = "none";
+  const height = scroller.scrollHeight;
+  const top = scroller.scrollTop;
+  setTimeout(() => {
+    scroller.scrollTop = top + scroller.scrollHeight - height;
+  }, 0);
+});
+
+/**
+ * The block containing `node`, if any.
+ */
+function blockOf(node) {
+  const el = node.nodeType === Node.ELEMENT_NODE ? node : node.parentElement;
+  return el && el.closest("[data-block-id]");
+}
+
+/**
+ * Convert the reader's selection into character offsets within a block's
+ * content. The server counts characters as unicode code points, so we do the
+ * same. Blocks carry `data-offset`, because a page may begin part-way
+ * through a paragraph. Returns null unless the selection lies within a
+ * single block.
+ */
+function selectedRange() {
+  const selection = window.getSelection();
+  if (!selection || selection.isCollapsed || selection.rangeCount === 0) {
+    return null;
+  }
+  const range = selection.getRangeAt(0);
+  const block = blockOf(range.startContainer);
+  if (!block || block !== blockOf(range.endContainer)) {
+    return null;
+  }
+  // Buttons beside the block come first; its text is always last.
+  const content = block.lastElementChild;
+  const length = Array.from(content.textContent).length;
+  const charsBefore = (container, offset) => {
+    const before = document.createRange();
+    before.selectNodeContents(content);
+    try {
+      before.setEnd(container, offset);
+    } catch {
+      return 0;
+    }
+    return Math.min(Array.from(before.toString()).length, length);
+  };
+  const base = parseInt(block.dataset.offset);
+  const start = base + charsBefore(range.startContainer, range.startOffset);
+  const end = base + charsBefore(range.endContainer, range.endOffset);
+  if (start >= end) {
+    return null;
+  }
+  return { block, start, end, rect: range.getBoundingClientRect() };
+}
+
+/**
+ * When the reader selects some text in the book, show a small toolbar next
+ * to it which offers to highlight or comment on the selection.
+ */
+function updateSelectionToolbar() {
+  const existing = document.getElementById("selection-toolbar");
+  if (existing) {
+    existing.remove();
+  }
+  const selected = selectedRange();
+  if (!selected) {
+    return;
+  }
+
+  const toolbar = document.createElement("div");
+  toolbar.id = "selection-toolbar";
+  toolbar.classList.add("fixed");
+  toolbar.classList.add("flex");
+  toolbar.classList.add("gap-2");
+  toolbar.classList.add("rounded");
+  toolbar.classList.add("p-1");
+  toolbar.classList.add("bg-stone-700");
+  toolbar.classList.add("text-white");
+  toolbar.style.top = `${selected.rect.bottom + 4}px`;
+  toolbar.style.left = `${selected.rect.left}px`;
+
+  for (const [label, path] of [
+    ["highlight", selected.block.dataset.highlightUrl],
+    ["comment", selected.block.dataset.commentUrl],
+    ["suggest edit", selected.block.dataset.suggestUrl],
+  ]) {
+    const url = new URL(window.location.origin + path);
+    url.searchParams.set("start", selected.start);
+    url.searchParams.set("end", selected.end);
+    const button = document.createElement("button");
+    button.innerText = label;
+    // Keep the selection alive while the button is pressed.
+    button.addEventListener("mousedown", (e) => e.preventDefault());
+    button.addEventListener("click", () => {
+      window.location.href = url.toString();
+    });
+    toolbar.appendChild(button);
+  }
+  document.body.appendChild(toolbar);
+}
+
+let selectionDebounce;
+document.addEventListener("selectionchange", () => {
+  clearTimeout(selectionDebounce);
+  selectionDebounce = setTimeout(updateSelectionToolbar, 200);
+});